@@ -106,7 +106,9 @@ fn pack_dir<P: AsRef<Path>>(src_dir: P, out_img: &Directory) -> Result<()> {
             let mut file = File::open(ent.path())?;
             let mut buf = vec![];
             file.read_to_end(&mut buf)?;
-            file_in_img.write_at(0, &buf);
+            if file_in_img.write_at(0, &buf) < buf.len() {
+                anyhow::bail!("no space for {:?} in the image", ent.path());
+            }
         }
     }
     Ok(())
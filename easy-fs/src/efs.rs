@@ -2,6 +2,7 @@ use crate::bitmap::Bitmap;
 use crate::block_cache::BlockCache;
 use crate::block_cache::BlockCacheManager;
 use crate::layout::*;
+use crate::vfs::Error;
 use crate::BLOCK_SIZE;
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
@@ -30,16 +31,20 @@ impl Inode {
         self.read_disk_inode(|di, _| di.size as usize)
     }
 
-    pub fn resize(&self, new_size: u32) {
-        self.modify_disk_inode(|di, fs| di.resize(new_size, fs));
+    pub fn resize(&self, new_size: u32) -> Result<(), Error> {
+        self.modify_disk_inode(|di, fs| di.resize(new_size, fs))
+    }
+
+    pub fn truncate(&self, new_size: u32) {
+        self.modify_disk_inode(|di, fs| di.truncate(new_size, fs));
     }
 
     pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
         self.read_disk_inode(|di, fs| di.read_at(offset, buf, fs))
     }
 
-    pub fn write_at(&self, offset: usize, data: &[u8]) {
-        self.modify_disk_inode(|di, fs| di.write_at(offset, data, fs));
+    pub fn write_at(&self, offset: usize, data: &[u8]) -> usize {
+        self.modify_disk_inode(|di, fs| di.write_at(offset, data, fs))
     }
 
     pub fn read_disk_inode<V>(&self, f: impl FnOnce(&DiskInode, &Arc<EasyFileSystem>) -> V) -> V {
@@ -196,7 +201,7 @@ impl EasyFileSystem {
     }
 
     fn dealloc_inode(&self, inode_id: u32) {
-        let f = |di: &mut DiskInode| di.resize(0, self);
+        let f = |di: &mut DiskInode| di.truncate(0, self);
         self.modify_disk_inode(inode_id, f);
 
         self.inode_bitmap
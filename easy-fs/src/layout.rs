@@ -1,4 +1,5 @@
 use crate::efs::EasyFileSystem;
+use crate::vfs::{Error, Result};
 use crate::{Block, BLOCK_SIZE, BLOCK_BITS};
 use bitflags::bitflags;
 use core::cmp;
@@ -15,7 +16,7 @@ const MAX_FILE_SIZE: usize =
 // pub const DIR_ENTRY_SIZE: usize = core::mem::size_of::<DirEntry>();
 // assert_eq_size!(DirEntry, [u8; 32]);
 
-const MAX_FILE_NAME_LENGTH: usize = 123;
+pub const MAX_FILE_NAME_LENGTH: usize = 123;
 
 pub const DIR_ENTRY_SIZE: usize = core::mem::size_of::<DirEntry>();
 assert_eq_size!(DirEntry, [u8; 128]);
@@ -144,26 +145,17 @@ impl DiskInode {
         (size as usize).div_ceil(BLOCK_SIZE)
     }
 
-    fn increase_size(&mut self, new_size: u32, fs: &EasyFileSystem) {
-        assert!(
-            new_size as usize <= MAX_FILE_SIZE,
-            "file size limit exceeded"
-        );
+    /// Grow to new_size, or as far as the free blocks and MAX_FILE_SIZE allow. Return the size
+    /// reached, which is at least the current size. `self.size` is left to the caller.
+    fn increase_size(&mut self, new_size: usize, fs: &EasyFileSystem) -> usize {
+        let new_size = cmp::min(new_size, MAX_FILE_SIZE);
         let old_blocks = Self::blocks_for_size(self.size);
-        let new_blocks = Self::blocks_for_size(new_size);
+        let new_blocks = Self::blocks_for_size(new_size as u32);
 
         if old_blocks >= new_blocks {
-            return;
+            return cmp::max(new_size, self.size as usize);
         }
 
-        let alloc_zeroed_block = || {
-            let new_block = fs.alloc_block().expect("cannot alloc more blocks");
-            unsafe {
-                new_block.modify(0, |b: &mut Block| b.fill(0));
-            }
-            new_block
-        };
-
         if self.size as usize % BLOCK_SIZE > 0 {
             // clear pass-the-end data at old last block
             let last_block_id = self.get_block_id(old_blocks - 1, fs);
@@ -173,75 +165,78 @@ impl DiskInode {
             unsafe { last_block.modify(0, f) }
         }
 
-        let mut k = old_blocks;
-        'out: while k < new_blocks {
-            while let InnerIndex::Direct(i) = InnerIndex::new(k) {
-                self.direct[i] = alloc_zeroed_block().block_id() as u32;
-
-                k += 1;
-                if k >= new_blocks {
-                    break 'out;
-                }
+        for inner_id in old_blocks..new_blocks {
+            if self.alloc_inner_block(inner_id, fs).is_none() {
+                return inner_id * BLOCK_SIZE;
             }
+        }
+        new_size
+    }
 
-            let indirect1 = if self.indirect[0] == 0 {
-                let indirect1 = alloc_zeroed_block();
-                self.indirect[0] = indirect1.block_id() as u32;
-                indirect1
-            } else {
-                fs.get_block(self.indirect[0] as usize)
-            };
-            let f = |indirect1: &mut IndirectBlock| {
-                while let InnerIndex::Indirect1(i) = InnerIndex::new(k) {
-                    indirect1[i] = alloc_zeroed_block().block_id() as u32;
-
-                    k += 1;
-                    if k >= new_blocks {
-                        return false;
-                    }
+    /// Allocate the data block of inner_id, with the indirect blocks leading to it if they
+    /// are missing. Either all of them are allocated, or none.
+    fn alloc_inner_block(&mut self, inner_id: usize, fs: &EasyFileSystem) -> Option<()> {
+        match InnerIndex::new(inner_id) {
+            InnerIndex::Direct(i) => {
+                let [data] = Self::alloc_zeroed_blocks(fs)?;
+                self.direct[i] = data;
+            }
+            InnerIndex::Indirect1(i) => {
+                if self.indirect[0] == 0 {
+                    let [indirect1, data] = Self::alloc_zeroed_blocks(fs)?;
+                    self.indirect[0] = indirect1;
+                    Self::set_indirect_entry(indirect1, i, data, fs);
+                } else {
+                    let [data] = Self::alloc_zeroed_blocks(fs)?;
+                    Self::set_indirect_entry(self.indirect[0], i, data, fs);
                 }
-                true
-            };
-            let has_more =  unsafe { indirect1.modify(0, f) };
-            if !has_more {
-                break;
             }
-
-            let indirect2_1 = if self.indirect[1] == 0 {
-                let indirect2_1 = alloc_zeroed_block();
-                self.indirect[1] = indirect2_1.block_id() as u32;
-                indirect2_1
-            } else {
-                fs.get_block(self.indirect[1] as usize)
-            };
-            let f = |indirect2_1: &mut IndirectBlock| {
-                let InnerIndex::Indirect2(mut indirect2_2_i, mut indirect2_2_j) = InnerIndex::new(k) else { unreachable!() };
-                loop {
-                    let indirect2_2 = if indirect2_1[indirect2_2_i] == 0 {
-                        let indirect2_2 = alloc_zeroed_block();
-                        indirect2_1[indirect2_2_i] = indirect2_2.block_id() as u32;
-                        indirect2_2
+            InnerIndex::Indirect2(i, j) => {
+                if self.indirect[1] == 0 {
+                    let [indirect2_1, indirect2_2, data] = Self::alloc_zeroed_blocks(fs)?;
+                    self.indirect[1] = indirect2_1;
+                    Self::set_indirect_entry(indirect2_1, i, indirect2_2, fs);
+                    Self::set_indirect_entry(indirect2_2, j, data, fs);
+                } else {
+                    let indirect2_2 = Self::indirect_entry(self.indirect[1], i, fs);
+                    if indirect2_2 == 0 {
+                        let [indirect2_2, data] = Self::alloc_zeroed_blocks(fs)?;
+                        Self::set_indirect_entry(self.indirect[1], i, indirect2_2, fs);
+                        Self::set_indirect_entry(indirect2_2, j, data, fs);
                     } else {
-                        fs.get_block(indirect2_1[indirect2_2_i] as usize)
-                    };
-                    while indirect2_2_j < INODE_INDIRECT_COUNT {
-                        let f = |indirect2_2: &mut IndirectBlock| {
-                            indirect2_2[indirect2_2_j] = alloc_zeroed_block().block_id() as u32;
-                        };
-                        unsafe { indirect2_2.modify(0, f) }
-
-                        indirect2_2_j += 1;
-                        k += 1;
-                        if k >= new_blocks {
-                            return;
-                        }
+                        let [data] = Self::alloc_zeroed_blocks(fs)?;
+                        Self::set_indirect_entry(indirect2_2, j, data, fs);
                     }
-                    indirect2_2_i += 1;
-                    indirect2_2_j = 0;
                 }
+            }
+        }
+        Some(())
+    }
+
+    /// Allocate N zeroed blocks, or none of them if there aren't enough free blocks.
+    fn alloc_zeroed_blocks<const N: usize>(fs: &EasyFileSystem) -> Option<[u32; N]> {
+        let mut block_ids = [0; N];
+        for i in 0..N {
+            let Some(new_block) = fs.alloc_block() else {
+                block_ids[..i].iter().for_each(|&block_id| fs.dealloc_block(block_id as usize));
+                return None;
             };
-            unsafe { indirect2_1.modify(0, f) }
+            unsafe {
+                new_block.modify(0, |b: &mut Block| b.fill(0));
+            }
+            block_ids[i] = new_block.block_id() as u32;
         }
+        Some(block_ids)
+    }
+
+    fn indirect_entry(indirect_block_id: u32, i: usize, fs: &EasyFileSystem) -> u32 {
+        let indirect = fs.get_block(indirect_block_id as usize);
+        unsafe { indirect.read(0, |indirect: &IndirectBlock| indirect[i]) }
+    }
+
+    fn set_indirect_entry(indirect_block_id: u32, i: usize, block_id: u32, fs: &EasyFileSystem) {
+        let indirect = fs.get_block(indirect_block_id as usize);
+        unsafe { indirect.modify(0, |indirect: &mut IndirectBlock| indirect[i] = block_id) }
     }
 
     fn decrease_size(&mut self, new_size: u32, fs: &EasyFileSystem) {
@@ -322,7 +317,7 @@ impl DiskInode {
                 fs.dealloc_block(self.indirect[0] as usize);
                 self.indirect[0] = 0;
                 if !matches!(to, InnerIndex::Indirect1(0)) {
-                    from = InnerIndex::Direct(INODE_DIRECT_COUNT);
+                    from = InnerIndex::Direct(INODE_DIRECT_COUNT - 1);
                 }
             }
         }
@@ -339,13 +334,35 @@ impl DiskInode {
         }
     }
 
-    pub fn resize(&mut self, new_size: u32, fs: &EasyFileSystem) {
+    /// It fails with NoSpace if it can't grow to new_size, and the size is left unchanged.
+    /// Shrinking never fails.
+    pub fn resize(&mut self, new_size: u32, fs: &EasyFileSystem) -> Result<()> {
         if self.size < new_size {
-            self.increase_size(new_size, fs);
+            let reached = self.increase_size(new_size as usize, fs);
+            if reached < new_size as usize {
+                self.shrink_back(reached, fs);
+                return Err(Error::NoSpace);
+            }
         } else if self.size > new_size {
             self.decrease_size(new_size, fs);
         }
         self.size = new_size;
+        Ok(())
+    }
+
+    /// Shrink to new_size, which must not be larger than the current size.
+    pub fn truncate(&mut self, new_size: u32, fs: &EasyFileSystem) {
+        assert!(new_size <= self.size, "truncate can't grow a file");
+        self.decrease_size(new_size, fs);
+        self.size = new_size;
+    }
+
+    /// Free the blocks allocated by a failed increase_size, which reached `reached`.
+    fn shrink_back(&mut self, reached: usize, fs: &EasyFileSystem) {
+        let old_size = self.size;
+        self.size = reached as u32;
+        self.decrease_size(old_size, fs);
+        self.size = old_size;
     }
 
     pub fn read_at(&self, offset: usize, buf: &mut [u8], fs: &EasyFileSystem) -> usize {
@@ -380,10 +397,19 @@ impl DiskInode {
         buf_start
     }
 
-    pub fn write_at(&mut self, offset: usize, data: &[u8], fs: &EasyFileSystem) {
-        if offset + data.len() > self.size as usize {
-            self.resize((offset + data.len()) as u32, fs);
+    /// Return the bytes written, which are fewer than `data.len()` if the disk is full or the
+    /// file reaches MAX_FILE_SIZE.
+    pub fn write_at(&mut self, offset: usize, data: &[u8], fs: &EasyFileSystem) -> usize {
+        let end = offset.saturating_add(data.len());
+        if end > self.size as usize {
+            let reached = self.increase_size(end, fs);
+            if reached <= offset {
+                self.shrink_back(reached, fs);
+                return 0;
+            }
+            self.size = cmp::min(end, reached) as u32;
         }
+        let data = &data[..cmp::min(data.len(), self.size as usize - offset)];
         let (start_inner_id, start_offset) = Self::offset_to_inner(offset);
 
         let mut inner_id = start_inner_id;
@@ -406,6 +432,7 @@ impl DiskInode {
             inner_id += 1;
             block_start = 0;
         }
+        data.len()
     }
 
     fn get_block_id(&self, inner_id: usize, fs: &EasyFileSystem) -> u32 {
@@ -19,4 +19,5 @@ pub type Block = [u8; BLOCK_SIZE];
 pub use block_cache::BlockCacheManager;
 pub use block_dev::BlockDevice;
 pub use efs::EasyFileSystem;
+pub use layout::MAX_FILE_NAME_LENGTH;
 pub use vfs::{Directory, Error, File, FileOrDirectory, Result};
@@ -11,8 +11,12 @@ pub enum Error {
     AllocInodeFailed,
     IsDir,
     IsFile,
+    /// Longer than MAX_FILE_NAME_LENGTH.
+    NameTooLong,
     NotEmpty,
     NotFound,
+    /// No free blocks, or the file is at its max size.
+    NoSpace,
 }
 
 pub type Result<T> = core::result::Result<T, Error>;
@@ -76,15 +80,16 @@ impl File {
         self.0.size()
     }
 
-    pub fn resize(&self, new_size: usize) {
-        self.0.resize(new_size.try_into().unwrap())
+    pub fn resize(&self, new_size: usize) -> Result<()> {
+        self.0.resize(new_size.try_into().map_err(|_| Error::NoSpace)?)
     }
 
     pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
         self.0.read_at(offset, buf)
     }
 
-    pub fn write_at(&self, offset: usize, data: &[u8]) {
+    /// Return the bytes written, which are fewer than `data.len()` if there is no space.
+    pub fn write_at(&self, offset: usize, data: &[u8]) -> usize {
         self.0.write_at(offset, data)
     }
 }
@@ -119,6 +124,7 @@ impl Directory {
     }
 
     pub fn create_file(&self, name: &str) -> Result<File> {
+        check_name(name)?;
         let mut entry_buf = DirEntry::empty();
 
         let existing_inode = self.0.read_disk_inode(|di, fs| {
@@ -131,7 +137,7 @@ impl Directory {
             if inode.is_dir() {
                 Err(Error::IsDir)
             } else {
-                inode.resize(0);
+                inode.truncate(0);
                 Ok(File(inode))
             }
         } else {
@@ -141,15 +147,13 @@ impl Directory {
                 .alloc_inode(InodeType::FILE)
                 .ok_or(Error::AllocInodeFailed)?;
             entry_buf = DirEntry::new(name, new_inode.id());
-            self.0.modify_disk_inode(|di, fs| {
-                let end = di.size as usize;
-                di.write_at(end, entry_buf.as_bytes(), fs);
-            });
+            self.add_entry(&entry_buf, &new_inode)?;
             Ok(File(new_inode))
         }
     }
 
     pub fn create_dir(&self, name: &str) -> Result<Directory> {
+        check_name(name)?;
         let mut entry_buf = DirEntry::empty();
         self.0.read_disk_inode(|di, fs| {
             if Self::find_entry_offset(name, &mut entry_buf, di, fs).is_some() {
@@ -164,10 +168,7 @@ impl Directory {
             .alloc_inode(InodeType::DIRECTORY)
             .ok_or(Error::AllocInodeFailed)?;
         entry_buf = DirEntry::new(name, new_inode.id());
-        self.0.modify_disk_inode(|di, fs| {
-            let end = di.size as usize;
-            di.write_at(end, entry_buf.as_bytes(), fs);
-        });
+        self.add_entry(&entry_buf, &new_inode)?;
         Ok(Directory(new_inode))
     }
 
@@ -248,8 +249,22 @@ impl Directory {
         assert!(last_entry_offset % DIR_ENTRY_SIZE == 0);
         Self::read_entry(last_entry_offset, entry_buf, di, fs);
 
+        // Overwriting an existing entry needs no more blocks.
         di.write_at(target_entry_offset, entry_buf.as_bytes(), fs);
-        di.resize(last_entry_offset as u32, fs);
+        di.truncate(last_entry_offset as u32, fs);
+    }
+
+    /// Append the entry of the new inode. The inode is deleted if there is no space for it.
+    fn add_entry(&self, entry: &DirEntry, new_inode: &Inode) -> Result<()> {
+        let added = self.0.modify_disk_inode(|di, fs| {
+            let end = di.size as usize;
+            di.write_at(end, entry.as_bytes(), fs) == DIR_ENTRY_SIZE
+        });
+        if !added {
+            self.0.fs().delete_inode(new_inode.id());
+            return Err(Error::NoSpace);
+        }
+        Ok(())
     }
 
     fn find_entry_inode_id(
@@ -281,9 +296,17 @@ impl Directory {
     }
 }
 
+fn check_name(name: &str) -> Result<()> {
+    if name.len() > MAX_FILE_NAME_LENGTH {
+        return Err(Error::NameTooLong);
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::BLOCK_SIZE;
     use crate::efs::tests::setup;
 
     // 2 for the root inode and the file inode 
@@ -302,7 +325,7 @@ mod tests {
         let mut buf = [0; 5];
 
         let a = root_dir.create_file("a")?;
-        a.write_at(0, data);
+        assert_eq!(a.write_at(0, data), 5);
 
         drop(a);
 
@@ -329,6 +352,24 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn long_name() -> Result<()> {
+        let fs = setup();
+        let root_dir = fs.create_root_dir()?;
+
+        let name = "a".repeat(MAX_FILE_NAME_LENGTH);
+        root_dir.create_file(&name)?;
+        assert!(root_dir.open(&name).is_some());
+
+        let too_long = "a".repeat(MAX_FILE_NAME_LENGTH + 1);
+        assert!(matches!(root_dir.create_file(&too_long), Err(Error::NameTooLong)));
+        assert!(matches!(root_dir.create_dir(&too_long), Err(Error::NameTooLong)));
+        assert!(root_dir.open(&too_long).is_none());
+        assert_eq!(root_dir.list().len(), 1);
+
+        Ok(())
+    }
+
     #[test]
     fn so_many_dirs_and_files() -> Result<()> {
         let fs = setup();
@@ -366,7 +407,7 @@ mod tests {
         let mut buf = [0; 5];
 
         let offset = MAX_TEST_INODE_SIZE - 5; 
-        a.write_at(offset, data);
+        assert_eq!(a.write_at(offset, data), 5);
         a.read_at(offset, &mut buf);
         assert_eq!(data, &buf);
 
@@ -380,7 +421,7 @@ mod tests {
         let a = root_dir.create_file("a")?;
 
         for n in 0..MAX_TEST_INODE_SIZE {
-            a.resize(n);
+            a.resize(n)?;
         }
 
         Ok(())
@@ -391,14 +432,59 @@ mod tests {
         let fs = setup();
         let root_dir = fs.create_root_dir()?;
         let a = root_dir.create_file("a")?;
-        a.resize(MAX_TEST_INODE_SIZE);
+        a.resize(MAX_TEST_INODE_SIZE)?;
 
         for n in (0..MAX_TEST_INODE_SIZE).rev() {
-            a.resize(n);
+            a.resize(n)?;
         }
 
         let b = root_dir.create_file("b")?;
-        b.resize(MAX_TEST_INODE_SIZE);
+        b.resize(MAX_TEST_INODE_SIZE)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn disk_full() -> Result<()> {
+        let fs = setup();
+        let root_dir = fs.create_root_dir()?;
+        let a = root_dir.create_file("a")?;
+
+        // A failed resize leaves the file as it was.
+        assert!(matches!(a.resize(MAX_TEST_INODE_SIZE + BLOCK_SIZE), Err(Error::NoSpace)));
+        assert_eq!(a.size(), 0);
+
+        // The last write is short.
+        let data = [1; 3000];
+        let mut written = 0;
+        loop {
+            let n = a.write_at(written, &data);
+            written += n;
+            if n < data.len() {
+                break;
+            }
+        }
+        assert_eq!(written, MAX_TEST_INODE_SIZE);
+        assert_eq!(a.size(), written);
+        assert_eq!(a.write_at(written, b"x"), 0);
+        assert_eq!(a.size(), written);
+
+        // The entries fill up the block of the root dir, then there is no room for more.
+        let mut created = Vec::new();
+        let err = loop {
+            match root_dir.create_file(&format!("{}", created.len())) {
+                Ok(file) => created.push(file),
+                Err(err) => break err,
+            }
+        };
+        assert!(matches!(err, Error::NoSpace));
+        assert_eq!(root_dir.list().len(), 1 + created.len());
+        assert_eq!(created[0].write_at(0, b"x"), 0);
+
+        // The space is back once the file shrinks.
+        a.resize(0)?;
+        assert_eq!(created[0].write_at(0, &data), data.len());
+        root_dir.create_file("b")?;
 
         Ok(())
     }
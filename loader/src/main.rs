@@ -14,7 +14,7 @@ const KERNEL_BASE_ADDRESS: VirtAddr = unsafe {
     VirtAddr::new_unchecked(0xffffffffc0000000)
 };
 
-const QEMU_MEMORY_START: usize = 0x80000000;
+const GIGA_PAGE_SIZE: usize = 1 << 30;
 
 extern "C" {
     fn spacked_kernel();
@@ -26,26 +26,22 @@ extern "C" {
 static mut KERNEL_ROOT_PAGE_TABLE: PageTable = PageTable::empty();
 static mut KERNEL_SUB_PAGE_TABLE: PageTable = PageTable::empty();
 
+/// hartid and dtb are passed by the SBI in a0 and a1, and forwarded to the kernel.
 #[no_mangle]
-fn loader_main() {
+fn loader_main(hartid: usize, dtb: usize) {
     println!("hello from loader");
     println!("loader: 0x{:x} - 0x{:x}", sloader as usize, eloader as usize);
     println!("kernel: 0x{:x} - 0x{:x}", spacked_kernel as usize, epacked_kernel as usize);
 
-    // Build identity mapping for physical memory using 1GiB huge page.
-    let memory_va = VirtAddr::new(QEMU_MEMORY_START);
-    let memory_pa = PhysAddr::new(QEMU_MEMORY_START);
-    let mut memory_ppn = memory_pa.ppn();
-    memory_ppn.set_level(0, 0);
-    memory_ppn.set_level(1, 0);
-    let memory_pte = PageTableEntry::leaf(
-        memory_ppn, 
-        PteFlags::kernel_leaf()
-    );
+    let machine = unsafe { fdt::Machine::parse(dtb) }.expect("invalid device tree");
+    let memory = machine.memory_containing(sloader as usize)
+        .expect("loader out of the memory of the device tree");
+    println!("memory: 0x{:x} - 0x{:x}", memory.start, memory.end());
 
-    unsafe {
-        KERNEL_ROOT_PAGE_TABLE.set_entry(memory_va.vpn().level(2), memory_pte);
-    }
+    // Build identity mapping for physical memory using 1GiB huge page.
+    identity_map(sloader as usize);
+    // The kernel parses the device tree again, which may be in another 1GiB.
+    identity_map(dtb);
 
     let kernel_pa = PhysAddr::new(spacked_kernel as usize);
     let kernel_size = (epacked_kernel as usize).checked_sub(spacked_kernel as usize)
@@ -86,12 +82,12 @@ fn loader_main() {
         //     options(noreturn)
         // );
         asm!(
-            "mv a0, {}",
-            "mv a1, {}",
             "jalr x0, {}",
-            in(reg) kernel_pa.0,
-            in(reg) kernel_size,
             in(reg) KERNEL_BASE_ADDRESS.0,
+            in("a0") kernel_pa.0,
+            in("a1") kernel_size,
+            in("a2") hartid,
+            in("a3") dtb,
             options(noreturn)
         );
     }
@@ -101,6 +97,20 @@ fn loader_main() {
     // sbi::shutdown();
 }
 
+/// Identity map the 1GiB containing pa.
+fn identity_map(pa: usize) {
+    let memory_pa = PhysAddr::new(pa / GIGA_PAGE_SIZE * GIGA_PAGE_SIZE);
+    let memory_va = VirtAddr::new(memory_pa.0);
+    let memory_pte = PageTableEntry::leaf(
+        memory_pa.ppn(),
+        PteFlags::kernel_leaf()
+    );
+
+    unsafe {
+        KERNEL_ROOT_PAGE_TABLE.set_entry(memory_va.vpn().level(2), memory_pte);
+    }
+}
+
 fn build_sub_page_mapping(
     page_table: &mut PageTable,
     vbase: VirtAddr, pbase: PhysAddr, size: usize, flags: PteFlags,
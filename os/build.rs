@@ -1,6 +1,12 @@
+use rustc_demangle::demangle;
 use serde::Deserialize;
+use std::env;
 use std::fs;
+use std::path::Path;
 use std::process::Command;
+use xmas_elf::sections::SectionData;
+use xmas_elf::symbol_table::{Entry, Type};
+use xmas_elf::ElfFile;
 
 const APP_BASE_ADDR: *mut u8 = 0x80400000 as *mut u8;
 const MAX_APP_SIZE: usize = 0x20000;
@@ -64,6 +70,91 @@ fn main() {
         .collect();
     let link_app_asm = build_link_app_asm(bins, elf_paths);
     fs::write("src/link_app.S", link_app_asm).expect("cannot write link_app.S");
+
+    build_ksymtab();
+}
+
+/// The kernel checks the address of this function to tell if the symbol table is of itself.
+const KSYMTAB_ANCHOR: &str = "trap_handler";
+
+struct Symbol {
+    start: u64,
+    size: u64,
+    name: String,
+}
+
+/// Write the function symbols of the kernel at $KSYMTAB_FROM to ksymtab.bin, which is
+/// embedded in the .ksymtab section. The Makefile links the kernel once, and again with
+/// the symbols of the first link. The section comes after the code, so the functions stay
+/// where they are. Without $KSYMTAB_FROM, the table is empty and the backtraces have no
+/// names.
+fn build_ksymtab() {
+    println!("cargo:rerun-if-env-changed=KSYMTAB_FROM");
+    let symbols = match env::var("KSYMTAB_FROM") {
+        Ok(kernel_path) if !kernel_path.is_empty() => {
+            println!("cargo:rerun-if-changed={kernel_path}");
+            let kernel = fs::read(&kernel_path).expect("cannot read KSYMTAB_FROM");
+            read_symbols(&kernel)
+        }
+        _ => Vec::new(),
+    };
+    let ksymtab = encode_ksymtab(&symbols);
+    let out_path = Path::new(&env::var("OUT_DIR").unwrap()).join("ksymtab.bin");
+    // Don't touch it if unchanged, or the kernel would be rebuilt every time.
+    if fs::read(&out_path).ok().as_ref() != Some(&ksymtab) {
+        fs::write(out_path, ksymtab).expect("cannot write ksymtab.bin");
+    }
+}
+
+/// Functions sorted by the address, with the names demangled.
+fn read_symbols(kernel: &[u8]) -> Vec<Symbol> {
+    let elf = match ElfFile::new(kernel) {
+        Ok(elf) => elf,
+        Err(_) => return Vec::new(),
+    };
+    let entries = match elf.find_section_by_name(".symtab").map(|symtab| symtab.get_data(&elf)) {
+        Some(Ok(SectionData::SymbolTable64(entries))) => entries,
+        _ => return Vec::new(),
+    };
+    let mut symbols: Vec<Symbol> = entries
+        .iter()
+        .filter(|entry| entry.get_type() == Ok(Type::Func) && entry.size() > 0)
+        .filter_map(|entry| {
+            Some(Symbol {
+                start: entry.value(),
+                size: entry.size(),
+                name: format!("{:#}", demangle(entry.get_name(&elf).ok()?)),
+            })
+        })
+        .collect();
+    symbols.sort_by_key(|symbol| symbol.start);
+    symbols.dedup_by_key(|symbol| symbol.start);
+    symbols
+}
+
+/// The layout, all in little endian:
+/// - the address of KSYMTAB_ANCHOR (u64), or 0 if not found
+/// - the number of symbols (u32)
+/// - for each symbol: the address (u64), size (u32), name offset (u32) and name length (u32)
+/// - the names
+fn encode_ksymtab(symbols: &[Symbol]) -> Vec<u8> {
+    let anchor = symbols
+        .iter()
+        .find(|symbol| symbol.name == KSYMTAB_ANCHOR)
+        .map_or(0, |symbol| symbol.start);
+    let mut ksymtab = Vec::new();
+    let mut names: Vec<u8> = Vec::new();
+    ksymtab.extend(anchor.to_le_bytes());
+    ksymtab.extend((symbols.len() as u32).to_le_bytes());
+    for symbol in symbols {
+        ksymtab.extend(symbol.start.to_le_bytes());
+        ksymtab.extend((symbol.size as u32).to_le_bytes());
+        ksymtab.extend((names.len() as u32).to_le_bytes());
+        ksymtab.extend((symbol.name.len() as u32).to_le_bytes());
+        names.extend(symbol.name.as_bytes());
+    }
+    ksymtab.extend(names);
+    ksymtab
 }
 
 fn build_link_app_asm(bins: Vec<String>, elf_paths: Vec<String>) -> String {
@@ -0,0 +1,110 @@
+//! Backtraces by the frame pointers, which are kept by `-Cforce-frame-pointers`.
+//! The names come from the symbol table embedded by build.rs.
+
+use crate::config::KERNEL_STACK_VA;
+use crate::println;
+use crate::trap::trap_handler;
+use core::arch::asm;
+
+/// The symbol table of the first link of this kernel, or empty if not linked by the
+/// Makefile. See `encode_ksymtab` in build.rs for the layout.
+#[used]
+#[link_section = ".ksymtab"]
+static KSYMTAB: [u8; include_bytes!(concat!(env!("OUT_DIR"), "/ksymtab.bin")).len()] =
+    *include_bytes!(concat!(env!("OUT_DIR"), "/ksymtab.bin"));
+
+extern "C" {
+    fn sksymtab();
+    fn eksymtab();
+}
+
+const HEADER_SIZE: usize = 12;
+const ENTRY_SIZE: usize = 20;
+
+const MAX_DEPTH: usize = 32;
+
+fn read_u64(bytes: &[u8], offset: usize) -> usize {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap()) as usize
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> usize {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize
+}
+
+/// None if the table is empty, or of another kernel, e.g. KSYMTAB_FROM was stale. It's
+/// found by the linker symbols rather than KSYMTAB, so that the code doesn't depend on its
+/// content.
+fn symbol_table() -> Option<&'static [u8]> {
+    let table = unsafe {
+        core::slice::from_raw_parts(sksymtab as usize as *const u8, eksymtab as usize - sksymtab as usize)
+    };
+    if table.len() < HEADER_SIZE || read_u64(table, 0) != trap_handler as usize {
+        return None;
+    }
+    Some(table)
+}
+
+/// Return the function containing the address, and the offset in it.
+pub fn lookup(addr: usize) -> Option<(&'static str, usize)> {
+    let table = symbol_table()?;
+    let count = read_u32(table, 8);
+    let entry = |i: usize| HEADER_SIZE + i * ENTRY_SIZE;
+
+    // Find the last function that starts at or before the address.
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let mid = (low + high) / 2;
+        if read_u64(table, entry(mid)) <= addr {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    let offset = entry(low.checked_sub(1)?);
+    let start = read_u64(table, offset);
+    if addr - start >= read_u32(table, offset + 8) {
+        return None;
+    }
+    let name_start = entry(count) + read_u32(table, offset + 12);
+    let name_len = read_u32(table, offset + 16);
+    let name = core::str::from_utf8(&table[name_start..name_start + name_len]).ok()?;
+    Some((name, addr - start))
+}
+
+fn print_frame(depth: usize, ra: usize) {
+    // ra is after the call, which may be the start of the next function.
+    match lookup(ra.wrapping_sub(1)) {
+        Some((name, offset)) => println!("  #{:<2} {:#x} {}+{:#x}", depth, ra, name, offset + 1),
+        None => println!("  #{:<2} {:#x}", depth, ra),
+    }
+}
+
+/// Walk the frames up from `fp`. The return address of a frame is at fp - 8, and the fp
+/// of the caller at fp - 16.
+pub fn print_backtrace_from(mut fp: usize) {
+    println!("backtrace:");
+    for depth in 0..MAX_DEPTH {
+        // Stop at anything strange, e.g. 0 at the bottom of the boot stack, or the fp of
+        // the user at the bottom of a kernel stack.
+        if fp < KERNEL_STACK_VA.0 + 16 || fp % 8 != 0 {
+            break;
+        }
+        let (ra, caller_fp) = unsafe { (*((fp - 8) as *const usize), *((fp - 16) as *const usize)) };
+        print_frame(depth, ra);
+        // The stack grows downward.
+        if caller_fp <= fp {
+            break;
+        }
+        fp = caller_fp;
+    }
+}
+
+/// Print the backtrace of the caller.
+#[inline(never)]
+pub fn print_backtrace() {
+    let fp: usize;
+    unsafe {
+        asm!("mv {}, s0", out(reg) fp);
+    }
+    print_backtrace_from(fp);
+}
@@ -9,17 +9,53 @@ pub const PAGE_SIZE: usize = 4096;
 pub const USER_STACK_SIZE: usize = 2 * 4096;
 pub const KERNEL_STACK_SIZE: usize = 2 * 4096;
 
-pub const QEMU_MEMORY_START: usize = 0x80000000;
-pub const QEMU_MEMORY_END: usize = 0x88000000;
+/// Harts with larger ids are left alone. It can't exceed the bits of usize, since
+/// hart masks are usize.
+pub const MAX_HARTS: usize = 8;
+/// Each hart schedules on its own boot stack.
+pub const BOOT_STACK_SIZE: usize = 16 * 4096;
 
+/// Physical memory is identity mapped by the 1 GiB page containing the kernel. The memory
+/// regions are only used as far as they are in it.
+pub const IDENTITY_MAP_SIZE: usize = 1 << 30;
+
+// MMIO regions below this pa are mapped to MMIO_BASE_VA + pa.
+pub const MMIO_MAX_PA: usize = 0x20000000;
+pub const MMIO_BASE_VA: VirtAddr = unsafe {
+    // Upper half of the last 1GB, which is out of the reach of the kernel image.
+    VirtAddr::new_unchecked(0xffffffffe0000000)
+};
+
+/// Kernel stacks of the threads go upward from here, one guard page apart.
 pub const KERNEL_STACK_VA: VirtAddr = unsafe {
     VirtAddr::new_unchecked(0xffffffff80000000)
 };
 
+pub const fn kernel_stack_va(tid: usize) -> VirtAddr {
+    KERNEL_STACK_VA.add(tid * (KERNEL_STACK_SIZE + PAGE_SIZE))
+}
+
 pub const KERNEL_BRK_VA: VirtAddr = unsafe {
     VirtAddr::new_unchecked(0xffffffff40000000)
 };
 
+/// The user can only map pages below it, which is the lower half of Sv39.
+pub const USER_SPACE_END: usize = 1 << 38;
+
+/// User stack of the main thread. Those of the other threads go downward from here,
+/// one guard page apart.
 pub const USER_STACK_VA: VirtAddr = unsafe {
     VirtAddr::new_unchecked(0x70000000)
 };
+
+pub const fn user_stack_va(tid: usize) -> VirtAddr {
+    USER_STACK_VA.offset_to(-((tid * (USER_STACK_SIZE + PAGE_SIZE)) as isize))
+}
+
+/// Threads of a process, including the exited ones that aren't waited for yet.
+pub const MAX_THREADS: usize = 1024;
+
+/// Reserved for the user stacks of all the possible threads, so that mmap and the elf
+/// segments can't take the place of a stack created later.
+pub const USER_STACK_REGION_START: VirtAddr = user_stack_va(MAX_THREADS - 1);
+pub const USER_STACK_REGION_END: VirtAddr = USER_STACK_VA.add(USER_STACK_SIZE);
@@ -1,19 +1,28 @@
 use core::fmt;
 // use crate::sys_write;
+use crate::fs::tty;
 use crate::sbi::console_putchar;
+use spin::Mutex;
 
 struct Stdout;
 
+// Keep the lines from different harts apart.
+static STDOUT: Mutex<Stdout> = Mutex::new(Stdout);
+
 impl fmt::Write for Stdout {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        s.bytes().for_each(|c| console_putchar(c as usize));
+        // Once the tty drives the UART, go through it, or we would race with its output.
+        match tty::get() {
+            Some(tty) => tty.write_kernel(s.as_bytes()),
+            None => s.bytes().for_each(|c| console_putchar(c as usize)),
+        }
         Ok(())
     }
 }
 
 pub fn print(args: fmt::Arguments) {
     use fmt::Write;
-    Stdout.write_fmt(args).unwrap();
+    STDOUT.lock().write_fmt(args).unwrap();
 }
 
 #[macro_export]
@@ -0,0 +1,4 @@
+pub mod virtio_blk;
+pub mod ns16550;
+pub mod plic;
+pub mod irq;
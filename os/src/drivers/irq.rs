@@ -0,0 +1,70 @@
+//! External interrupts. The drivers register their handlers here, and the interrupts are
+//! routed to all the harts through the PLIC.
+
+use super::plic;
+use crate::config::MAX_HARTS;
+use crate::smp::{hart_id, online_harts};
+use crate::println;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use spin::RwLock;
+
+pub type IrqHandler = Arc<dyn Fn() + Send + Sync>;
+
+/// Indexed by the irq. It's read on every interrupt, and written only by the drivers
+/// setting up.
+static HANDLERS: RwLock<BTreeMap<u32, IrqHandler>> = RwLock::new(BTreeMap::new());
+
+/// Attach the handler to the irq, and enable it on all the harts. The handler runs with
+/// the interrupts disabled, and the device is expected to stop asserting the interrupt.
+/// Return false if the irq is taken.
+pub fn register(irq: u32, priority: u32, handler: impl Fn() + Send + Sync + 'static) -> bool {
+    let mut handlers = HANDLERS.write();
+    if handlers.contains_key(&irq) {
+        return false;
+    }
+    handlers.insert(irq, Arc::new(handler));
+    plic::set_priority(irq, priority);
+    for_each_online_hart(|hart| plic::set_enabled(plic::s_context(hart), irq, true));
+    true
+}
+
+/// Disable the irq and detach its handler. Return false if it isn't registered.
+pub fn unregister(irq: u32) -> bool {
+    let mut handlers = HANDLERS.write();
+    if handlers.remove(&irq).is_none() {
+        return false;
+    }
+    for_each_online_hart(|hart| plic::set_enabled(plic::s_context(hart), irq, false));
+    plic::set_priority(irq, 0);
+    true
+}
+
+/// Enable the registered irqs on a hart coming online. It must be marked online already,
+/// so that the irqs registered later are enabled on it as well.
+pub fn init_hart(hart: usize) {
+    let context = plic::s_context(hart);
+    let handlers = HANDLERS.read();
+    plic::set_threshold(context, 0);
+    handlers.keys().for_each(|&irq| plic::set_enabled(context, irq, true));
+}
+
+fn for_each_online_hart(f: impl Fn(usize)) {
+    let online_harts = online_harts();
+    (0..MAX_HARTS).filter(|hart| online_harts & (1 << hart) != 0).for_each(f);
+}
+
+/// Handle the pending interrupts of the current hart. They are claimed by one of the harts,
+/// so there may be none left when we get here.
+pub fn handle_pending() {
+    let context = plic::s_context(hart_id());
+    while let Some(irq) = plic::claim(context) {
+        // The handler may take a while, so the lock isn't held.
+        let handler = HANDLERS.read().get(&irq).cloned();
+        match handler {
+            Some(handler) => handler(),
+            None => println!("[kernel] unexpected irq {}", irq),
+        }
+        plic::complete(context, irq);
+    }
+}
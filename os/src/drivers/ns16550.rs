@@ -0,0 +1,60 @@
+// A minimal polling driver of the NS16550 UART, with registers one byte apart as on QEMU.
+
+use crate::mm::page_table::ioremap;
+
+// Register offsets, with DLAB cleared.
+const REG_RBR: usize = 0; // read
+const REG_THR: usize = 0; // write
+const REG_IER: usize = 1;
+const REG_FCR: usize = 2; // write
+const REG_LCR: usize = 3;
+const REG_MCR: usize = 4;
+const REG_LSR: usize = 5;
+
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const LCR_8N1: u8 = 0x03;
+const FCR_ENABLE_AND_CLEAR: u8 = 0x07;
+const MCR_DTR_RTS: u8 = 0x03;
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_THR_EMPTY: u8 = 1 << 5;
+
+pub struct Ns16550 {
+    base: usize,
+}
+
+impl Ns16550 {
+    /// Map the UART at pa and set it up for polling: 8N1, FIFOs on, interrupts off.
+    /// The baud rate is left as the firmware set it.
+    pub fn new(pa: usize) -> Self {
+        let uart = Self { base: ioremap(pa, 8).0 };
+        uart.write_reg(REG_IER, 0);
+        uart.write_reg(REG_LCR, LCR_8N1);
+        uart.write_reg(REG_FCR, FCR_ENABLE_AND_CLEAR);
+        uart.write_reg(REG_MCR, MCR_DTR_RTS);
+        uart
+    }
+
+    /// Raise the interrupt when there is input.
+    pub fn set_rx_interrupt(&self, enabled: bool) {
+        self.write_reg(REG_IER, if enabled { IER_RX_AVAILABLE } else { 0 });
+    }
+
+    pub fn try_read(&self) -> Option<u8> {
+        (self.read_reg(REG_LSR) & LSR_DATA_READY != 0).then(|| self.read_reg(REG_RBR))
+    }
+
+    pub fn write(&self, byte: u8) {
+        while self.read_reg(REG_LSR) & LSR_THR_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+        self.write_reg(REG_THR, byte);
+    }
+
+    fn read_reg(&self, offset: usize) -> u8 {
+        unsafe { ((self.base + offset) as *const u8).read_volatile() }
+    }
+
+    fn write_reg(&self, offset: usize, value: u8) {
+        unsafe { ((self.base + offset) as *mut u8).write_volatile(value) }
+    }
+}
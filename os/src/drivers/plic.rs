@@ -0,0 +1,79 @@
+// A driver of the platform-level interrupt controller, with the layout of QEMU virt,
+// where the S-mode context of hart i is 2 * i + 1.
+// See https://github.com/riscv/riscv-plic-spec/blob/master/riscv-plic.adoc
+
+use crate::config::MAX_HARTS;
+use crate::fdt::machine;
+use crate::mm::page_table::ioremap;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+// Register offsets.
+const REG_PRIORITY: usize = 0x000000;
+const REG_ENABLE: usize = 0x002000;
+const ENABLE_STRIDE: usize = 0x80;
+const REG_THRESHOLD: usize = 0x200000;
+const REG_CLAIM: usize = 0x200004;
+const CONTEXT_STRIDE: usize = 0x1000;
+
+/// Interrupt sources are 1 to 1023, and 0 means none.
+pub const MAX_IRQS: u32 = 1024;
+
+static BASE: AtomicUsize = AtomicUsize::new(0);
+
+/// The context of the S-mode of the hart.
+pub fn s_context(hart: usize) -> usize {
+    2 * hart + 1
+}
+
+fn reg(offset: usize) -> *mut u32 {
+    (BASE.load(Ordering::Relaxed) + offset) as *mut u32
+}
+
+pub fn init() {
+    let plic = machine().plic.expect("no PLIC in the device tree");
+    let size = REG_THRESHOLD + CONTEXT_STRIDE * (s_context(MAX_HARTS - 1) + 1);
+    BASE.store(ioremap(plic.start, size.min(plic.size)).0, Ordering::Relaxed);
+}
+
+fn check_irq(irq: u32) {
+    assert!(irq > 0 && irq < MAX_IRQS, "invalid irq {}", irq);
+}
+
+/// Priority 0 means never. The higher, the earlier it's claimed.
+pub fn set_priority(irq: u32, priority: u32) {
+    check_irq(irq);
+    unsafe {
+        reg(REG_PRIORITY + irq as usize * 4).write_volatile(priority);
+    }
+}
+
+/// Only the interrupts with priorities above it reach the context.
+pub fn set_threshold(context: usize, threshold: u32) {
+    unsafe {
+        reg(REG_THRESHOLD + context * CONTEXT_STRIDE).write_volatile(threshold);
+    }
+}
+
+/// Let the interrupt reach the context, or not.
+pub fn set_enabled(context: usize, irq: u32, enabled: bool) {
+    check_irq(irq);
+    let word = reg(REG_ENABLE + context * ENABLE_STRIDE + irq as usize / 32 * 4);
+    let bit = 1 << (irq % 32);
+    unsafe {
+        let bits = word.read_volatile();
+        word.write_volatile(if enabled { bits | bit } else { bits & !bit });
+    }
+}
+
+/// Take the highest pending interrupt of the context. Return None if there is none.
+pub fn claim(context: usize) -> Option<u32> {
+    let irq = unsafe { reg(REG_CLAIM + context * CONTEXT_STRIDE).read_volatile() };
+    (irq != 0).then_some(irq)
+}
+
+/// The interrupt can be claimed again after it's completed.
+pub fn complete(context: usize, irq: u32) {
+    unsafe {
+        reg(REG_CLAIM + context * CONTEXT_STRIDE).write_volatile(irq);
+    }
+}
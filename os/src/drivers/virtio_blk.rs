@@ -0,0 +1,315 @@
+// A minimal polling virtio-mmio block driver.
+// See https://docs.oasis-open.org/virtio/virtio/v1.1/virtio-v1.1.html
+//
+// It supports both the legacy (version 1, QEMU's default for virtio-mmio) and the modern
+// (version 2) register layout. Only one request is in flight at a time, so a tiny queue
+// and a single bounce buffer are enough.
+
+use core::mem::size_of;
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{fence, Ordering};
+use easy_fs::{Block, BlockDevice, BLOCK_SIZE};
+use spin::Mutex;
+use crate::config::*;
+use crate::fdt::machine;
+use crate::mm::*;
+use crate::mm::frame_allocator::{frame_alloc, frame_alloc_contiguous};
+use crate::println;
+
+const VIRTIO_MAGIC: u32 = 0x74726976;
+const VIRTIO_DEVICE_BLOCK: u32 = 2;
+
+// Register offsets.
+const REG_MAGIC: usize = 0x000;
+const REG_VERSION: usize = 0x004;
+const REG_DEVICE_ID: usize = 0x008;
+const REG_DEVICE_FEATURES: usize = 0x010;
+const REG_DEVICE_FEATURES_SEL: usize = 0x014;
+const REG_DRIVER_FEATURES: usize = 0x020;
+const REG_DRIVER_FEATURES_SEL: usize = 0x024;
+// Legacy only
+const REG_GUEST_PAGE_SIZE: usize = 0x028;
+const REG_QUEUE_SEL: usize = 0x030;
+const REG_QUEUE_NUM_MAX: usize = 0x034;
+const REG_QUEUE_NUM: usize = 0x038;
+// Legacy only
+const REG_QUEUE_ALIGN: usize = 0x03c;
+// Legacy only
+const REG_QUEUE_PFN: usize = 0x040;
+// Modern only
+const REG_QUEUE_READY: usize = 0x044;
+const REG_QUEUE_NOTIFY: usize = 0x050;
+const REG_INTERRUPT_STATUS: usize = 0x060;
+const REG_INTERRUPT_ACK: usize = 0x064;
+const REG_STATUS: usize = 0x070;
+// Modern only
+const REG_QUEUE_DESC_LOW: usize = 0x080;
+const REG_QUEUE_DESC_HIGH: usize = 0x084;
+const REG_QUEUE_DRIVER_LOW: usize = 0x090;
+const REG_QUEUE_DRIVER_HIGH: usize = 0x094;
+const REG_QUEUE_DEVICE_LOW: usize = 0x0a0;
+const REG_QUEUE_DEVICE_HIGH: usize = 0x0a4;
+
+// Device status bits.
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FEATURES_OK: u32 = 8;
+
+// Feature bit 32, i.e. bit 0 of the features word 1.
+const VIRTIO_F_VERSION_1: u32 = 1 << 0;
+
+const VIRTQ_DESC_F_NEXT: u16 = 1;
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_S_OK: u8 = 0;
+
+// One request uses 3 descriptors: header, data and status.
+const QUEUE_SIZE: usize = 4;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct VirtqDesc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+struct VirtqAvail {
+    flags: u16,
+    idx: u16,
+    ring: [u16; QUEUE_SIZE],
+    used_event: u16,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct VirtqUsedElem {
+    id: u32,
+    len: u32,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+struct VirtqUsed {
+    flags: u16,
+    idx: u16,
+    ring: [VirtqUsedElem; QUEUE_SIZE],
+    avail_event: u16,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+struct BlkReqHeader {
+    ty: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+// Layout of the bounce buffer frame.
+const DMA_HEADER_OFFSET: usize = 0;
+const DMA_STATUS_OFFSET: usize = 256;
+const DMA_DATA_OFFSET: usize = 512;
+
+pub struct VirtIOBlock(Mutex<VirtIOBlockInner>);
+
+struct VirtIOBlockInner {
+    regs: usize,
+    // The first page holds descriptors and the available ring, and the second holds the used
+    // ring. This satisfies both the legacy layout with 4096 queue align and the modern one.
+    queue: PPN,
+    dma: PPN,
+    last_used_idx: u16,
+}
+
+impl VirtIOBlockInner {
+    fn read_reg(&self, offset: usize) -> u32 {
+        unsafe { ((self.regs + offset) as *const u32).read_volatile() }
+    }
+
+    fn write_reg(&self, offset: usize, val: u32) {
+        unsafe { ((self.regs + offset) as *mut u32).write_volatile(val) }
+    }
+
+    fn desc(&self) -> *mut VirtqDesc {
+        self.queue.as_pa().0 as *mut VirtqDesc
+    }
+
+    fn avail(&self) -> *mut VirtqAvail {
+        (self.queue.as_pa().0 + QUEUE_SIZE * size_of::<VirtqDesc>()) as *mut VirtqAvail
+    }
+
+    fn used(&self) -> *mut VirtqUsed {
+        (self.queue.as_pa().0 + PAGE_SIZE) as *mut VirtqUsed
+    }
+
+    fn dma_pa(&self, offset: usize) -> usize {
+        self.dma.as_pa().0 + offset
+    }
+
+    fn init(&mut self) {
+        let version = self.read_reg(REG_VERSION);
+
+        self.write_reg(REG_STATUS, 0);
+        let mut status = STATUS_ACKNOWLEDGE | STATUS_DRIVER;
+        self.write_reg(REG_STATUS, status);
+
+        // We don't need any device specific features.
+        self.write_reg(REG_DEVICE_FEATURES_SEL, 0);
+        let _ = self.read_reg(REG_DEVICE_FEATURES);
+        self.write_reg(REG_DRIVER_FEATURES_SEL, 0);
+        self.write_reg(REG_DRIVER_FEATURES, 0);
+        self.write_reg(REG_DRIVER_FEATURES_SEL, 1);
+        self.write_reg(REG_DRIVER_FEATURES, if version == 1 { 0 } else { VIRTIO_F_VERSION_1 });
+        if version != 1 {
+            status |= STATUS_FEATURES_OK;
+            self.write_reg(REG_STATUS, status);
+            assert!(self.read_reg(REG_STATUS) & STATUS_FEATURES_OK != 0, "virtio-blk rejects our features");
+        }
+
+        self.write_reg(REG_QUEUE_SEL, 0);
+        let queue_num_max = self.read_reg(REG_QUEUE_NUM_MAX) as usize;
+        assert!(queue_num_max >= QUEUE_SIZE, "virtio-blk queue too small");
+        self.write_reg(REG_QUEUE_NUM, QUEUE_SIZE as u32);
+
+        let desc_pa = self.desc() as usize;
+        let avail_pa = self.avail() as usize;
+        let used_pa = self.used() as usize;
+        if version == 1 {
+            self.write_reg(REG_GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+            self.write_reg(REG_QUEUE_ALIGN, PAGE_SIZE as u32);
+            self.write_reg(REG_QUEUE_PFN, self.queue.as_usize() as u32);
+        } else {
+            self.write_reg(REG_QUEUE_DESC_LOW, desc_pa as u32);
+            self.write_reg(REG_QUEUE_DESC_HIGH, (desc_pa >> 32) as u32);
+            self.write_reg(REG_QUEUE_DRIVER_LOW, avail_pa as u32);
+            self.write_reg(REG_QUEUE_DRIVER_HIGH, (avail_pa >> 32) as u32);
+            self.write_reg(REG_QUEUE_DEVICE_LOW, used_pa as u32);
+            self.write_reg(REG_QUEUE_DEVICE_HIGH, (used_pa >> 32) as u32);
+            self.write_reg(REG_QUEUE_READY, 1);
+        }
+
+        status |= STATUS_DRIVER_OK;
+        self.write_reg(REG_STATUS, status);
+    }
+
+    /// Submit a request for the block, and wait for its completion.
+    fn request(&mut self, ty: u32, block_id: usize) {
+        unsafe {
+            let header = self.dma_pa(DMA_HEADER_OFFSET) as *mut BlkReqHeader;
+            header.write(BlkReqHeader {
+                ty,
+                reserved: 0,
+                // Block size of easy-fs happens to be the sector size.
+                sector: block_id as u64,
+            });
+            let status = self.dma_pa(DMA_STATUS_OFFSET) as *mut u8;
+            status.write_volatile(0xff);
+
+            let desc = self.desc();
+            desc.add(0).write(VirtqDesc {
+                addr: self.dma_pa(DMA_HEADER_OFFSET) as u64,
+                len: size_of::<BlkReqHeader>() as u32,
+                flags: VIRTQ_DESC_F_NEXT,
+                next: 1,
+            });
+            desc.add(1).write(VirtqDesc {
+                addr: self.dma_pa(DMA_DATA_OFFSET) as u64,
+                len: BLOCK_SIZE as u32,
+                flags: VIRTQ_DESC_F_NEXT | if ty == VIRTIO_BLK_T_IN { VIRTQ_DESC_F_WRITE } else { 0 },
+                next: 2,
+            });
+            desc.add(2).write(VirtqDesc {
+                addr: self.dma_pa(DMA_STATUS_OFFSET) as u64,
+                len: 1,
+                flags: VIRTQ_DESC_F_WRITE,
+                next: 0,
+            });
+
+            let avail = self.avail();
+            let avail_idx = addr_of!((*avail).idx).read_volatile();
+            addr_of_mut!((*avail).ring[avail_idx as usize % QUEUE_SIZE]).write_volatile(0);
+            // Descriptors must be visible before the idx.
+            fence(Ordering::SeqCst);
+            addr_of_mut!((*avail).idx).write_volatile(avail_idx.wrapping_add(1));
+            fence(Ordering::SeqCst);
+            self.write_reg(REG_QUEUE_NOTIFY, 0);
+
+            let used = self.used();
+            while addr_of!((*used).idx).read_volatile() == self.last_used_idx {
+                core::hint::spin_loop();
+            }
+            fence(Ordering::SeqCst);
+            self.last_used_idx = self.last_used_idx.wrapping_add(1);
+
+            let interrupt_status = self.read_reg(REG_INTERRUPT_STATUS);
+            self.write_reg(REG_INTERRUPT_ACK, interrupt_status);
+
+            assert_eq!(status.read_volatile(), VIRTIO_BLK_S_OK, "virtio-blk request failed");
+        }
+    }
+}
+
+impl VirtIOBlock {
+    fn new(regs: usize) -> Self {
+        let queue = frame_alloc_contiguous(2).expect("no memory for the virtqueue");
+        let dma = frame_alloc().expect("no memory for the dma buffer");
+        unsafe {
+            core::ptr::write_bytes(queue.as_pa().0 as *mut u8, 0, 2 * PAGE_SIZE);
+            core::ptr::write_bytes(dma.as_pa().0 as *mut u8, 0, PAGE_SIZE);
+        }
+        let mut inner = VirtIOBlockInner {
+            regs,
+            queue,
+            dma,
+            last_used_idx: 0,
+        };
+        inner.init();
+
+        Self(Mutex::new(inner))
+    }
+}
+
+impl BlockDevice for VirtIOBlock {
+    fn read_block(&self, block_id: usize, buf: &mut Block) {
+        let mut inner = self.0.lock();
+        inner.request(VIRTIO_BLK_T_IN, block_id);
+        let data = inner.dma_pa(DMA_DATA_OFFSET) as *const u8;
+        unsafe {
+            core::ptr::copy_nonoverlapping(data, buf.as_mut_ptr(), BLOCK_SIZE);
+        }
+    }
+
+    fn write_block(&self, block_id: usize, buf: &Block) {
+        let mut inner = self.0.lock();
+        let data = inner.dma_pa(DMA_DATA_OFFSET) as *mut u8;
+        unsafe {
+            core::ptr::copy_nonoverlapping(buf.as_ptr(), data, BLOCK_SIZE);
+        }
+        inner.request(VIRTIO_BLK_T_OUT, block_id);
+    }
+}
+
+/// Find the first virtio block device in the virtio-mmio slots of the device tree.
+pub fn probe() -> Option<VirtIOBlock> {
+    for device in machine().virtio_mmio.as_slice() {
+        let regs = ioremap(device.reg.start, device.reg.size).0;
+        let (magic, device_id) = unsafe {
+            (
+                ((regs + REG_MAGIC) as *const u32).read_volatile(),
+                ((regs + REG_DEVICE_ID) as *const u32).read_volatile(),
+            )
+        };
+        if magic == VIRTIO_MAGIC && device_id == VIRTIO_DEVICE_BLOCK {
+            println!("[kernel] found virtio-blk at 0x{:x}", device.reg.start);
+            return Some(VirtIOBlock::new(regs));
+        }
+    }
+    None
+}
@@ -0,0 +1,302 @@
+//! A minimal parser of the flattened device tree passed by SBI, which tells what the machine
+//! has. It doesn't allocate, so that it works before the heap, and in the loader.
+//! See https://devicetree-specification.readthedocs.io/en/stable/flattened-format.html
+
+use spin::Once;
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+// Offsets in the header.
+const HEADER_TOTAL_SIZE: usize = 4;
+const HEADER_OFF_DT_STRUCT: usize = 8;
+const HEADER_OFF_DT_STRINGS: usize = 12;
+const HEADER_SIZE_DT_STRUCT: usize = 36;
+
+const MAX_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Region {
+    pub start: usize,
+    pub size: usize,
+}
+
+impl Region {
+    pub fn end(&self) -> usize {
+        self.start + self.size
+    }
+
+    pub fn contains(&self, pa: usize) -> bool {
+        (self.start..self.end()).contains(&pa)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Device {
+    pub reg: Region,
+    /// The first cell of `interrupts`, which is the irq for the PLIC.
+    pub irq: Option<u32>,
+}
+
+/// At most N items, without allocation. The ones beyond are dropped.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedList<T, const N: usize> {
+    items: [T; N],
+    len: usize,
+}
+
+impl<T: Copy + Default, const N: usize> FixedList<T, N> {
+    fn new() -> Self {
+        Self {
+            items: [T::default(); N],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, item: T) {
+        if self.len < N {
+            self.items[self.len] = item;
+            self.len += 1;
+        }
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.items[..self.len]
+    }
+}
+
+/// What we need from the device tree.
+#[derive(Debug, Clone, Copy)]
+pub struct Machine {
+    pub memory: FixedList<Region, 8>,
+    /// Bit i is set for hart i.
+    pub harts: usize,
+    /// Of the `time` CSR.
+    pub timebase_frequency: usize,
+    /// NS16550 compatible ones. The first is the console.
+    pub uarts: FixedList<Device, 4>,
+    pub plic: Option<Region>,
+    pub virtio_mmio: FixedList<Device, 8>,
+}
+
+/// The properties of a node we care about, as they are in the blob.
+#[derive(Clone, Copy)]
+struct Node<'a> {
+    /// Of the reg of the children.
+    address_cells: usize,
+    size_cells: usize,
+    device_type: &'a [u8],
+    compatible: &'a [u8],
+    status: &'a [u8],
+    reg: &'a [u8],
+    interrupts: &'a [u8],
+    timebase_frequency: Option<usize>,
+}
+
+impl Default for Node<'_> {
+    /// The cells are the defaults of the spec.
+    fn default() -> Self {
+        Self {
+            address_cells: 2,
+            size_cells: 1,
+            device_type: &[],
+            compatible: &[],
+            status: &[],
+            reg: &[],
+            interrupts: &[],
+            timebase_frequency: None,
+        }
+    }
+}
+
+impl<'a> Node<'a> {
+    fn set_property(&mut self, name: &[u8], value: &'a [u8]) {
+        match name {
+            b"#address-cells" => self.address_cells = read_cells(value, 1).unwrap_or(2),
+            b"#size-cells" => self.size_cells = read_cells(value, 1).unwrap_or(1),
+            b"device_type" => self.device_type = trim_nul(value),
+            b"compatible" => self.compatible = value,
+            b"status" => self.status = trim_nul(value),
+            b"reg" => self.reg = value,
+            b"interrupts" => self.interrupts = value,
+            b"timebase-frequency" => self.timebase_frequency = read_cells(value, value.len() / 4),
+            _ => {}
+        }
+    }
+
+    fn is_compatible(&self, name: &[u8]) -> bool {
+        self.compatible.split(|&c| c == 0).any(|item| item == name)
+    }
+
+    /// The i-th region of reg, whose cells are given by the parent.
+    fn reg(&self, parent: &Node, i: usize) -> Option<Region> {
+        let entry_len = (parent.address_cells + parent.size_cells) * 4;
+        let entry = self.reg.get(i * entry_len..(i + 1) * entry_len)?;
+        let (start, size) = entry.split_at(parent.address_cells * 4);
+        Some(Region {
+            start: read_cells(start, parent.address_cells)?,
+            size: read_cells(size, parent.size_cells)?,
+        })
+    }
+
+    fn device(&self, parent: &Node) -> Option<Device> {
+        Some(Device {
+            reg: self.reg(parent, 0)?,
+            irq: read_cells(self.interrupts.get(..4)?, 1).map(|irq| irq as u32),
+        })
+    }
+}
+
+impl Machine {
+    fn empty() -> Self {
+        Self {
+            memory: FixedList::new(),
+            harts: 0,
+            timebase_frequency: 0,
+            uarts: FixedList::new(),
+            plic: None,
+            virtio_mmio: FixedList::new(),
+        }
+    }
+
+    /// Parse the device tree blob at dtb. Return None if it's malformed.
+    ///
+    /// # Safety
+    ///
+    /// The blob must be readable at dtb.
+    pub unsafe fn parse(dtb: usize) -> Option<Self> {
+        let header = core::slice::from_raw_parts(dtb as *const u8, HEADER_SIZE_DT_STRUCT + 4);
+        if read_u32(header, 0)? != FDT_MAGIC {
+            return None;
+        }
+        let total_size = read_u32(header, HEADER_TOTAL_SIZE)? as usize;
+        Self::parse_blob(core::slice::from_raw_parts(dtb as *const u8, total_size))
+    }
+
+    pub fn parse_blob(blob: &[u8]) -> Option<Self> {
+        let struct_start = read_u32(blob, HEADER_OFF_DT_STRUCT)? as usize;
+        let struct_size = read_u32(blob, HEADER_SIZE_DT_STRUCT)? as usize;
+        let strings_start = read_u32(blob, HEADER_OFF_DT_STRINGS)? as usize;
+        let structure = blob.get(struct_start..struct_start.checked_add(struct_size)?)?;
+        let strings = blob.get(strings_start..)?;
+
+        let mut machine = Self::empty();
+        let mut nodes = [Node::default(); MAX_DEPTH];
+        let mut depth = 0;
+        let mut pos = 0;
+        loop {
+            let token = read_u32(structure, pos)?;
+            pos += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    if depth == MAX_DEPTH {
+                        return None;
+                    }
+                    let name_len = structure.get(pos..)?.iter().position(|&c| c == 0)?;
+                    nodes[depth] = Node::default();
+                    depth += 1;
+                    pos = align4(pos + name_len + 1);
+                }
+                FDT_END_NODE => {
+                    depth = depth.checked_sub(1)?;
+                    // The root has no parent, nor reg.
+                    if depth > 0 {
+                        machine.add_node(&nodes[depth], &nodes[depth - 1]);
+                    }
+                }
+                FDT_PROP => {
+                    let len = read_u32(structure, pos)? as usize;
+                    let name_offset = read_u32(structure, pos + 4)? as usize;
+                    let value = structure.get(pos + 8..pos + 8 + len)?;
+                    let name = strings.get(name_offset..)?;
+                    let name = &name[..name.iter().position(|&c| c == 0)?];
+                    nodes[depth.checked_sub(1)?].set_property(name, value);
+                    pos = align4(pos + 8 + len);
+                }
+                FDT_NOP => {}
+                FDT_END => break,
+                _ => return None,
+            }
+        }
+        Some(machine)
+    }
+
+    fn add_node(&mut self, node: &Node, parent: &Node) {
+        if !node.status.is_empty() && node.status != b"okay" && node.status != b"ok" {
+            return;
+        }
+        if let Some(frequency) = node.timebase_frequency {
+            self.timebase_frequency = frequency;
+        }
+        match node.device_type {
+            b"memory" => (0..)
+                .map_while(|i| node.reg(parent, i))
+                .for_each(|region| self.memory.push(region)),
+            b"cpu" => {
+                if let Some(Region { start: hartid, .. }) = node.reg(parent, 0) {
+                    if hartid < usize::BITS as usize {
+                        self.harts |= 1 << hartid;
+                    }
+                }
+            }
+            _ => {}
+        }
+        if node.is_compatible(b"ns16550a") || node.is_compatible(b"ns16550") {
+            if let Some(uart) = node.device(parent) {
+                self.uarts.push(uart);
+            }
+        } else if node.is_compatible(b"riscv,plic0") || node.is_compatible(b"sifive,plic-1.0.0") {
+            self.plic = node.reg(parent, 0);
+        } else if node.is_compatible(b"virtio,mmio") {
+            if let Some(device) = node.device(parent) {
+                self.virtio_mmio.push(device);
+            }
+        }
+    }
+
+    /// The memory region containing pa.
+    pub fn memory_containing(&self, pa: usize) -> Option<Region> {
+        self.memory.as_slice().iter().copied().find(|region| region.contains(pa))
+    }
+}
+
+static MACHINE: Once<Machine> = Once::new();
+
+/// Parse the device tree passed by SBI. What we need is copied out, so the blob can be
+/// overwritten later.
+pub fn init(dtb: usize) {
+    let machine = unsafe { Machine::parse(dtb) }.expect("invalid device tree");
+    MACHINE.call_once(|| machine);
+}
+
+pub fn machine() -> &'static Machine {
+    MACHINE.get().expect("device tree not parsed")
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// A number of 1 or 2 big-endian cells.
+fn read_cells(bytes: &[u8], cells: usize) -> Option<usize> {
+    match cells {
+        1 => read_u32(bytes, 0).map(|n| n as usize),
+        2 => Some((read_u32(bytes, 0)? as usize) << 32 | read_u32(bytes, 4)? as usize),
+        // e.g. size-cells of the cpus.
+        0 => Some(0),
+        _ => None,
+    }
+}
+
+fn trim_nul(value: &[u8]) -> &[u8] {
+    value.strip_suffix(&[0]).unwrap_or(value)
+}
+
+fn align4(pos: usize) -> usize {
+    (pos + 3) & !3
+}
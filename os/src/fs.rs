@@ -0,0 +1,92 @@
+mod inode;
+mod pipe;
+mod stdio;
+pub mod tty;
+
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use easy_fs::{BlockCacheManager, EasyFileSystem};
+use crate::drivers::virtio_blk;
+use crate::println;
+use crate::syscall::Errno;
+
+pub use inode::{mount, open_file, OSInode, OpenFlags};
+pub use pipe::{make_pipe, Pipe};
+pub use stdio::{Stdin, Stdout};
+
+/// Set up the console tty, and mount the easy-fs on the virtio block device as the root
+/// directory.
+/// It's fine to run without a disk, we just can't open any file.
+pub fn init() {
+    tty::init();
+    let block_dev = match virtio_blk::probe() {
+        Some(block_dev) => block_dev,
+        None => {
+            println!("[kernel] no block device found, skip mounting root fs");
+            return;
+        }
+    };
+    let cache_mgr = BlockCacheManager::new(block_dev);
+    match EasyFileSystem::open(cache_mgr) {
+        Ok(efs) => {
+            mount(&efs).expect("cannot open root dir");
+            println!("[kernel] root fs mounted");
+        }
+        Err(_) => println!("[kernel] invalid easy-fs image, skip mounting root fs"),
+    }
+}
+
+/// Everything that can sit in a fd table slot.
+pub trait File: Send + Sync {
+    fn readable(&self) -> bool;
+    fn writable(&self) -> bool;
+    /// Return the number of bytes read, 0 means EOF.
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno>;
+    /// Return the number of bytes written, which may be fewer than `buf.len()`.
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno>;
+    /// Whether the terminal ioctls apply to it.
+    fn is_tty(&self) -> bool {
+        false
+    }
+}
+
+impl core::fmt::Debug for dyn File {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("File")
+            .field("readable", &self.readable())
+            .field("writable", &self.writable())
+            .finish()
+    }
+}
+
+pub type FdTable = Vec<Option<Arc<dyn File>>>;
+
+/// Fds must be less than it.
+pub const FD_LIMIT: usize = 1024;
+
+/// Build the fd table for a fresh task, with stdin, stdout and stderr opened.
+pub fn new_fd_table() -> FdTable {
+    vec![
+        // 0 -> stdin
+        Some(Arc::new(Stdin)),
+        // 1 -> stdout
+        Some(Arc::new(Stdout)),
+        // 2 -> stderr
+        Some(Arc::new(Stdout)),
+    ]
+}
+
+/// Put the file into the lowest free slot and return its fd.
+/// Return None if all the fds below FD_LIMIT are in use.
+pub fn alloc_fd(fd_table: &mut FdTable, file: Arc<dyn File>) -> Option<usize> {
+    if let Some(fd) = fd_table.iter().position(|f| f.is_none()) {
+        fd_table[fd] = Some(file);
+        Some(fd)
+    } else if fd_table.len() < FD_LIMIT {
+        fd_table.push(Some(file));
+        Some(fd_table.len() - 1)
+    } else {
+        None
+    }
+}
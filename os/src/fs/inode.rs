@@ -0,0 +1,146 @@
+use super::File;
+use crate::syscall::Errno;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use bitflags::bitflags;
+use easy_fs::{Directory, EasyFileSystem, FileOrDirectory, MAX_FILE_NAME_LENGTH};
+use spin::Mutex;
+
+// The easy-fs directory isn't safe to be used concurrently (see the design note in
+// easy_fs::Directory), so all path lookups go through this lock.
+static ROOT_DIR: Mutex<Option<Directory>> = Mutex::new(None);
+
+pub fn mount(efs: &Arc<EasyFileSystem>) -> easy_fs::Result<()> {
+    let root_dir = efs.open_root_dir()?;
+    ROOT_DIR.lock().replace(root_dir);
+    Ok(())
+}
+
+bitflags! {
+    /// Same values as Linux.
+    pub struct OpenFlags: u32 {
+        const RDONLY = 0;
+        const WRONLY = 1 << 0;
+        const RDWR = 1 << 1;
+        const CREATE = 1 << 6;
+        const TRUNC = 1 << 9;
+    }
+}
+
+impl OpenFlags {
+    /// Return (readable, writable).
+    pub fn read_write(self) -> (bool, bool) {
+        if self.contains(Self::WRONLY) {
+            (false, true)
+        } else if self.contains(Self::RDWR) {
+            (true, true)
+        } else {
+            (true, false)
+        }
+    }
+}
+
+/// An opened easy-fs file with its own offset.
+pub struct OSInode {
+    readable: bool,
+    writable: bool,
+    offset: Mutex<usize>,
+    file: easy_fs::File,
+}
+
+impl OSInode {
+    pub fn new(readable: bool, writable: bool, file: easy_fs::File) -> Self {
+        Self {
+            readable,
+            writable,
+            offset: Mutex::new(0),
+            file,
+        }
+    }
+
+    /// Read the whole file, regardless of the current offset.
+    pub fn read_all(&self) -> Vec<u8> {
+        let mut buf = vec![0; self.file.size()];
+        let n = self.file.read_at(0, &mut buf);
+        buf.truncate(n);
+        buf
+    }
+}
+
+impl File for OSInode {
+    fn readable(&self) -> bool {
+        self.readable
+    }
+
+    fn writable(&self) -> bool {
+        self.writable
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        let mut offset = self.offset.lock();
+        let n = self.file.read_at(*offset, buf);
+        *offset += n;
+        Ok(n)
+    }
+
+    /// It's short if the disk fills up, and fails with ENOSPC if nothing is written.
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        let mut offset = self.offset.lock();
+        let n = self.file.write_at(*offset, buf);
+        if n == 0 && !buf.is_empty() {
+            return Err(Errno::ENOSPC);
+        }
+        *offset += n;
+        Ok(n)
+    }
+}
+
+/// Open the file at `path`, which is always resolved from the root directory.
+/// It fails with ENOENT if the fs isn't mounted, the path doesn't exist or it's a directory,
+/// and with ENOSPC if there is no room to create it.
+pub fn open_file(path: &str, flags: OpenFlags) -> Result<Arc<OSInode>, Errno> {
+    let (readable, writable) = flags.read_write();
+
+    let path = path.trim_start_matches('/');
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+    if name.is_empty() {
+        return Err(Errno::ENOENT);
+    }
+    if path.split('/').any(|component| component.len() > MAX_FILE_NAME_LENGTH) {
+        return Err(Errno::ENAMETOOLONG);
+    }
+
+    let root_dir = ROOT_DIR.lock();
+    let root_dir = root_dir.as_ref().ok_or(Errno::ENOENT)?;
+
+    // Walk down to the parent directory of the target.
+    let mut sub_dir: Option<Directory> = None;
+    for component in parent.split('/').filter(|c| !c.is_empty()) {
+        let cur = sub_dir.as_ref().unwrap_or(root_dir);
+        match cur.open(component).ok_or(Errno::ENOENT)? {
+            FileOrDirectory::Directory(dir) => sub_dir = Some(dir),
+            FileOrDirectory::File(_) => return Err(Errno::ENOENT),
+        }
+    }
+    let parent_dir = sub_dir.as_ref().unwrap_or(root_dir);
+
+    let file = match parent_dir.open(name) {
+        Some(FileOrDirectory::File(file)) => {
+            if flags.contains(OpenFlags::TRUNC) {
+                // Shrinking never fails.
+                file.resize(0).map_err(|_| Errno::ENOSPC)?;
+            }
+            file
+        }
+        Some(FileOrDirectory::Directory(_)) => return Err(Errno::ENOENT),
+        None if flags.contains(OpenFlags::CREATE) => parent_dir.create_file(name).map_err(|err| match err {
+            easy_fs::Error::NameTooLong => Errno::ENAMETOOLONG,
+            easy_fs::Error::NoSpace | easy_fs::Error::AllocInodeFailed => Errno::ENOSPC,
+            _ => Errno::ENOENT,
+        })?,
+        None => return Err(Errno::ENOENT),
+    };
+
+    Ok(Arc::new(OSInode::new(readable, writable, file)))
+}
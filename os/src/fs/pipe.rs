@@ -0,0 +1,143 @@
+use super::File;
+use crate::syscall::Errno;
+use crate::task::signal::{send_signal, SIGPIPE};
+use crate::task::{current_process, WaitQueue};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use spin::Mutex;
+
+const PIPE_BUF_SIZE: usize = 4096;
+
+/// One end of a pipe.
+pub struct Pipe {
+    readable: bool,
+    writable: bool,
+    shared: Arc<PipeShared>,
+}
+
+struct PipeShared {
+    ring: Mutex<PipeRing>,
+    // Readers waiting for data.
+    read_wait: WaitQueue,
+    // Writers waiting for room.
+    write_wait: WaitQueue,
+}
+
+struct PipeRing {
+    buf: VecDeque<u8>,
+    read_ends: usize,
+    write_ends: usize,
+}
+
+/// Return (read end, write end).
+pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
+    let shared = Arc::new(PipeShared {
+        ring: Mutex::new(PipeRing {
+            buf: VecDeque::with_capacity(PIPE_BUF_SIZE),
+            read_ends: 1,
+            write_ends: 1,
+        }),
+        read_wait: WaitQueue::new(),
+        write_wait: WaitQueue::new(),
+    });
+    let read_end = Pipe {
+        readable: true,
+        writable: false,
+        shared: Arc::clone(&shared),
+    };
+    let write_end = Pipe {
+        readable: false,
+        writable: true,
+        shared,
+    };
+    (Arc::new(read_end), Arc::new(write_end))
+}
+
+impl File for Pipe {
+    fn readable(&self) -> bool {
+        self.readable
+    }
+
+    fn writable(&self) -> bool {
+        self.writable
+    }
+
+    /// Block until there is some data, or all the write ends are closed.
+    /// A signal interrupts it with EINTR.
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let n = self.shared.read_wait.wait_until_interruptible(|| {
+            let mut ring = self.shared.ring.lock();
+            if !ring.buf.is_empty() {
+                let n = core::cmp::min(buf.len(), ring.buf.len());
+                buf.iter_mut().zip(ring.buf.drain(..n)).for_each(|(dst, src)| *dst = src);
+                Some(n)
+            } else if ring.write_ends == 0 {
+                // EOF
+                Some(0)
+            } else {
+                None
+            }
+        })?;
+        if n > 0 {
+            self.shared.write_wait.wake_all();
+        }
+        Ok(n)
+    }
+
+    /// Block until all the data is written, or all the read ends are closed.
+    /// If nothing is written because there are no readers, it fails with EPIPE and
+    /// raises SIGPIPE on the writer, like Linux. A signal interrupts it with EINTR if
+    /// nothing is written yet.
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        let mut written = 0;
+        while written < buf.len() {
+            let n = self.shared.write_wait.wait_until_interruptible(|| {
+                let mut ring = self.shared.ring.lock();
+                if ring.read_ends == 0 {
+                    return Some(0);
+                }
+                let room = PIPE_BUF_SIZE - ring.buf.len();
+                if room == 0 {
+                    return None;
+                }
+                let n = core::cmp::min(room, buf.len() - written);
+                ring.buf.extend(&buf[written..written + n]);
+                Some(n)
+            });
+            let n = match n {
+                Ok(n) => n,
+                Err(_) if written > 0 => break,
+                Err(err) => return Err(err),
+            };
+            if n == 0 {
+                if written == 0 {
+                    send_signal(&current_process(), SIGPIPE);
+                    return Err(Errno::EPIPE);
+                }
+                break;
+            }
+            written += n;
+            self.shared.read_wait.wake_all();
+        }
+        Ok(written)
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        let mut ring = self.shared.ring.lock();
+        if self.readable {
+            ring.read_ends -= 1;
+        }
+        if self.writable {
+            ring.write_ends -= 1;
+        }
+        drop(ring);
+        // Let them see the EOF or the broken pipe.
+        self.shared.read_wait.wake_all();
+        self.shared.write_wait.wake_all();
+    }
+}
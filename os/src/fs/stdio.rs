@@ -0,0 +1,51 @@
+use super::tty::tty;
+use super::File;
+use crate::syscall::Errno;
+
+pub struct Stdin;
+
+pub struct Stdout;
+
+impl File for Stdin {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        tty().read(buf)
+    }
+
+    fn write(&self, _buf: &[u8]) -> Result<usize, Errno> {
+        panic!("Cannot write to stdin!");
+    }
+
+    fn is_tty(&self) -> bool {
+        true
+    }
+}
+
+impl File for Stdout {
+    fn readable(&self) -> bool {
+        false
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    fn read(&self, _buf: &mut [u8]) -> Result<usize, Errno> {
+        panic!("Cannot read from stdout!");
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        Ok(tty().write(buf))
+    }
+
+    fn is_tty(&self) -> bool {
+        true
+    }
+}
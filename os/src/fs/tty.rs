@@ -0,0 +1,274 @@
+//! The console terminal: the input of the UART arrives by interrupts, and goes through a
+//! line discipline before the readers see it.
+
+use crate::drivers::ns16550::Ns16550;
+use crate::drivers::irq;
+use crate::fdt::machine;
+use crate::mm::user_ptr::Pod;
+use crate::syscall::Errno;
+use crate::task::WaitQueue;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use spin::{Mutex, Once};
+
+/// Input not yet read. The chars beyond it are dropped.
+const INPUT_BUF_SIZE: usize = 4096;
+/// The line being edited in the canonical mode can't be longer.
+const MAX_LINE: usize = 255;
+
+const BS: u8 = 0x08;
+const DEL: u8 = 0x7f;
+
+// Flags of Termios, with the same values as Linux.
+pub const ICRNL: u32 = 0o400;
+pub const OPOST: u32 = 0o1;
+pub const ONLCR: u32 = 0o4;
+pub const CS8: u32 = 0o60;
+pub const CREAD: u32 = 0o200;
+pub const ICANON: u32 = 0o2;
+pub const ECHO: u32 = 0o10;
+pub const ECHOE: u32 = 0o20;
+
+pub const NCCS: usize = 19;
+pub const VERASE: usize = 2;
+pub const VEOF: usize = 4;
+
+/// Same layout as the Linux `struct termios` of TCGETS and TCSETS. Only ICRNL of iflag,
+/// OPOST and ONLCR of oflag, ICANON and ECHO of lflag, and VERASE and VEOF of cc take
+/// effect. The others are kept as they are set.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Termios {
+    pub iflag: u32,
+    pub oflag: u32,
+    pub cflag: u32,
+    pub lflag: u32,
+    pub line: u8,
+    pub cc: [u8; NCCS],
+}
+
+unsafe impl Pod for Termios {}
+
+impl Default for Termios {
+    /// A cooked terminal, like that of Linux.
+    fn default() -> Self {
+        let mut cc = [0; NCCS];
+        cc[VERASE] = DEL;
+        cc[VEOF] = 0x04;
+        Self {
+            iflag: ICRNL,
+            oflag: OPOST | ONLCR,
+            cflag: CS8 | CREAD,
+            lflag: ICANON | ECHO | ECHOE,
+            line: 0,
+            cc,
+        }
+    }
+}
+
+pub struct Tty {
+    uart: Ns16550,
+    discipline: Mutex<LineDiscipline>,
+    /// Held while writing to the UART, so that the writes and the echo aren't interleaved.
+    /// The discipline isn't locked meanwhile, since the UART is slow.
+    output: Mutex<()>,
+    read_wait: WaitQueue,
+}
+
+struct LineDiscipline {
+    termios: Termios,
+    /// Ready for the readers. It only has complete lines in the canonical mode.
+    input: VecDeque<u8>,
+    /// Lengths of the lines in input, in the canonical mode. A line ends with '\n', or with
+    /// VEOF which isn't kept, so an empty one is an end of file.
+    lines: VecDeque<usize>,
+    /// The line being edited in the canonical mode.
+    line: Vec<u8>,
+}
+
+static TTY: Once<Tty> = Once::new();
+
+/// Take over the console UART from SBI, for the kernel messages as well. It's the first UART
+/// of the device tree.
+pub fn init() {
+    let device = *machine().uarts.as_slice().first().expect("no UART in the device tree");
+    let irq = device.irq.expect("no irq of the console UART");
+    TTY.call_once(|| {
+        let uart = Ns16550::new(device.reg.start);
+        uart.set_rx_interrupt(true);
+        Tty {
+            uart,
+            discipline: Mutex::new(LineDiscipline {
+                termios: Termios::default(),
+                input: VecDeque::with_capacity(INPUT_BUF_SIZE),
+                lines: VecDeque::new(),
+                line: Vec::with_capacity(MAX_LINE),
+            }),
+            output: Mutex::new(()),
+            read_wait: WaitQueue::new(),
+        }
+    });
+    assert!(irq::register(irq, 1, || tty().handle_irq()), "uart irq taken");
+}
+
+/// None before init.
+pub fn get() -> Option<&'static Tty> {
+    TTY.get()
+}
+
+pub fn tty() -> &'static Tty {
+    TTY.get().expect("tty not initialized")
+}
+
+impl Tty {
+    /// Block until there is some input. A read in the canonical mode returns at most one line,
+    /// and 0 for VEOF at the start of a line. A signal interrupts it with EINTR.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.read_wait.wait_until_interruptible(|| {
+            let mut discipline = self.discipline.lock();
+            let canonical = discipline.termios.lflag & ICANON != 0;
+            let available = if canonical {
+                *discipline.lines.front()?
+            } else {
+                discipline.input.len()
+            };
+            if !canonical && available == 0 {
+                return None;
+            }
+            let n = available.min(buf.len());
+            buf.iter_mut().zip(discipline.input.drain(..n)).for_each(|(dst, c)| *dst = c);
+            if canonical {
+                // The rest of the line is left for the next read.
+                if n == available {
+                    discipline.lines.pop_front();
+                } else {
+                    discipline.lines[0] -= n;
+                }
+            }
+            Some(n)
+        })
+    }
+
+    /// Write the bytes with the output processing, a utf8 char may be split across writes.
+    pub fn write(&self, buf: &[u8]) -> usize {
+        let oflag = self.discipline.lock().termios.oflag;
+        self.output(buf, oflag);
+        buf.len()
+    }
+
+    /// For the messages of the kernel, which always end the lines with "\r\n", whatever
+    /// the user has set.
+    pub fn write_kernel(&self, bytes: &[u8]) {
+        self.output(bytes, OPOST | ONLCR);
+    }
+
+    /// '\n' becomes "\r\n" with OPOST and ONLCR.
+    fn output(&self, bytes: &[u8], oflag: u32) {
+        let onlcr = oflag & (OPOST | ONLCR) == OPOST | ONLCR;
+        let _output = self.output.lock();
+        for &c in bytes {
+            if onlcr && c == b'\n' {
+                self.uart.write(b'\r');
+            }
+            self.uart.write(c);
+        }
+    }
+
+    pub fn termios(&self) -> Termios {
+        self.discipline.lock().termios
+    }
+
+    /// The line being edited is ready for reading when leaving the canonical mode, and the
+    /// input typed before is read as a line when entering it.
+    pub fn set_termios(&self, termios: Termios) {
+        let mut discipline = self.discipline.lock();
+        let was_canonical = discipline.termios.lflag & ICANON != 0;
+        discipline.termios = termios;
+        let LineDiscipline { input, lines, line, .. } = &mut *discipline;
+        if termios.lflag & ICANON == 0 {
+            input.extend(line.drain(..));
+            lines.clear();
+        } else if !was_canonical && !input.is_empty() {
+            lines.push_back(input.len());
+        }
+        drop(discipline);
+        self.read_wait.wake_all();
+    }
+
+    /// Called on the UART interrupt.
+    pub fn handle_irq(&self) {
+        #[cfg(feature = "monitor")]
+        let mut received = Vec::new();
+        let mut echo = Vec::new();
+        let mut discipline = self.discipline.lock();
+        while let Some(c) = self.uart.try_read() {
+            discipline.receive(c, &mut echo);
+            #[cfg(feature = "monitor")]
+            received.push(c);
+        }
+        let oflag = discipline.termios.oflag;
+        drop(discipline);
+        self.read_wait.wake_all();
+        self.output(&echo, oflag);
+        // The monitor may be entered, which takes a while, so it's done without the lock.
+        #[cfg(feature = "monitor")]
+        received.into_iter().for_each(crate::monitor::check_magic);
+    }
+
+    /// Leave the input to someone else, e.g. the monitor reading it through SBI.
+    pub fn set_input_enabled(&self, enabled: bool) {
+        self.uart.set_rx_interrupt(enabled);
+    }
+}
+
+impl LineDiscipline {
+    /// Take a received char. What to echo is appended to `echoed`.
+    fn receive(&mut self, c: u8, echoed: &mut Vec<u8>) {
+        let termios = self.termios;
+        let c = if c == b'\r' && termios.iflag & ICRNL != 0 { b'\n' } else { c };
+        let mut echo = |bytes: &[u8]| {
+            if termios.lflag & ECHO != 0 {
+                echoed.extend_from_slice(bytes);
+            }
+        };
+        if termios.lflag & ICANON == 0 {
+            if self.input.len() < INPUT_BUF_SIZE {
+                self.input.push_back(c);
+                echo(&[c]);
+            }
+            return;
+        }
+        match c {
+            b'\n' => {
+                self.line.push(b'\n');
+                self.end_line();
+                echo(b"\n");
+            }
+            // 0 disables it, like _POSIX_VDISABLE of Linux.
+            _ if c == termios.cc[VEOF] && c != 0 => self.end_line(),
+            _ if c == BS || c == DEL || c == termios.cc[VERASE] => {
+                if self.line.pop().is_some() {
+                    echo(&[BS, b' ', BS]);
+                }
+            }
+            _ if self.line.len() < MAX_LINE => {
+                self.line.push(c);
+                echo(&[c]);
+            }
+            _ => {}
+        }
+    }
+
+    /// Make the line being edited readable. A line that doesn't fit is dropped as a whole.
+    fn end_line(&mut self) {
+        let room = INPUT_BUF_SIZE - self.input.len();
+        if self.line.len() <= room {
+            self.input.extend(self.line.iter());
+            self.lines.push_back(self.line.len());
+        }
+        self.line.clear();
+    }
+}
@@ -0,0 +1,599 @@
+//! A GDB remote serial protocol stub on a second NS16550, for debugging a user process.
+//!
+//! Connect with `target extended-remote <serial port>`, then `attach <pid>`. The packets
+//! are polled on the timer ticks. The process stops the next time one of its threads traps,
+//! and that thread serves gdb until it's resumed, with the other tasks still running. The
+//! other threads of the process wait meanwhile, and the registers are those of the stopped
+//! thread. Breakpoints are `ebreak`s patched into the code. There is no hardware single-step
+//! on RISC-V, so gdb steps with breakpoints as well. They are forgotten when the target execs
+//! or exits, and a child forked from it gets the original code.
+
+use crate::drivers::ns16550::Ns16550;
+use crate::fdt::machine;
+use crate::mm::address_space::AddressSpace;
+use crate::mm::user_ptr::{self, copy_from_user};
+use crate::task::signal::{send_signal, SIGKILL};
+use crate::task::{current_process, run_next_task, ProcessControlBlock, WaitQueue};
+use crate::trap::TrapContext;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+use spin::{Mutex, MutexGuard};
+
+/// Advertised by qSupported. The memory reads are limited to fit in it.
+const PACKET_SIZE: usize = 4096;
+const NO_TARGET: usize = usize::MAX;
+const EBREAK: [u8; 4] = 0x00100073u32.to_le_bytes();
+const C_EBREAK: [u8; 2] = 0x9002u16.to_le_bytes();
+/// The registers in the order of gdb: x0 to x31, then pc.
+const NUM_REGS: usize = 33;
+const PC: usize = 32;
+
+lazy_static! {
+    static ref STUB: Mutex<GdbStub> = Mutex::new(GdbStub::new());
+    /// Threads of the target waiting for it to be resumed.
+    static ref RESUMED: WaitQueue = WaitQueue::new();
+}
+
+/// Pid of the process being debugged.
+static TARGET: AtomicUsize = AtomicUsize::new(NO_TARGET);
+/// Set by attaching and by Ctrl-C in gdb.
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// Only the holder touches STUB, so no one spins on it while the serving thread sleeps.
+static SERVING: AtomicBool = AtomicBool::new(false);
+/// A thread of the target is serving gdb.
+static STOPPED: AtomicBool = AtomicBool::new(false);
+/// The code of the target has been replaced by exec or freed by exit, so the breakpoints are
+/// stale. It's set with the process locked, and the stub checks it with the process locked
+/// before touching the code.
+static IMAGE_GONE: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy)]
+enum RxState {
+    Idle,
+    Data,
+    /// With the first digit if received.
+    Checksum(Option<u8>),
+}
+
+enum Input {
+    Packet(Vec<u8>),
+    Interrupt,
+}
+
+enum Action {
+    Stay,
+    Resume,
+}
+
+struct GdbStub {
+    uart: Ns16550,
+    rx_state: RxState,
+    rx_packet: Vec<u8>,
+    /// Sent again if gdb asks for it.
+    last_sent: Vec<u8>,
+    /// The original code under the breakpoints.
+    breakpoints: BTreeMap<usize, Vec<u8>>,
+}
+
+/// Handle the packets that arrive while the target is running, or there is no target.
+pub fn poll() {
+    if gdb_uart_pa().is_none() || !take_serving() {
+        return;
+    }
+    let mut stub = STUB.lock();
+    stub.check_target_exit();
+    while let Some(input) = stub.poll_input() {
+        match input {
+            Input::Interrupt => request_stop(),
+            Input::Packet(packet) => stub.handle_running(&packet),
+        }
+    }
+    drop(stub);
+    SERVING.store(false, Ordering::Release);
+}
+
+/// Called before a thread returns to the user. It stops the process if requested, or waits
+/// while another thread has stopped it.
+pub fn check_stop(cx: &mut TrapContext) {
+    if (STOP_REQUESTED.load(Ordering::Acquire) || STOPPED.load(Ordering::Acquire)) && is_target() {
+        stop(cx, false);
+    }
+}
+
+/// Called on an ebreak of the user. Return false if the process isn't being debugged.
+pub fn handle_breakpoint(cx: &mut TrapContext) -> bool {
+    if !is_target() {
+        return false;
+    }
+    stop(cx, true);
+    true
+}
+
+/// The second UART of the device tree, since the first one is the console.
+fn gdb_uart_pa() -> Option<usize> {
+    machine().uarts.as_slice().get(1).map(|uart| uart.reg.start)
+}
+
+/// Called by exec and exit with the process locked.
+pub fn on_image_gone(pid: usize) {
+    if TARGET.load(Ordering::Acquire) == pid {
+        IMAGE_GONE.store(true, Ordering::Release);
+    }
+}
+
+/// Taken by fork before copying the process, so that the breakpoints don't change meanwhile.
+/// It holds nothing if the process isn't the target.
+pub struct BreakpointsGuard(Option<MutexGuard<'static, GdbStub>>);
+
+pub fn hold_breakpoints(pid: usize) -> BreakpointsGuard {
+    loop {
+        if TARGET.load(Ordering::Acquire) != pid {
+            return BreakpointsGuard(None);
+        }
+        if take_serving() {
+            let stub = STUB.lock();
+            // It may have been detached meanwhile.
+            if TARGET.load(Ordering::Acquire) == pid {
+                return BreakpointsGuard(Some(stub));
+            }
+            drop(stub);
+            SERVING.store(false, Ordering::Release);
+            return BreakpointsGuard(None);
+        }
+        // Another thread of the target may be stopped, or the poller holds it for a moment.
+        run_next_task();
+    }
+}
+
+impl BreakpointsGuard {
+    /// Put the original code back in the copy of the child, which must be called with the
+    /// parent locked.
+    pub fn restore_code(&self, child_addr_space: &mut AddressSpace) {
+        let Some(stub) = &self.0 else {
+            return;
+        };
+        if IMAGE_GONE.load(Ordering::Acquire) {
+            return;
+        }
+        for (&addr, original) in &stub.breakpoints {
+            // The code may have been unmapped.
+            let _ = child_addr_space.poke(addr, original);
+        }
+    }
+}
+
+impl Drop for BreakpointsGuard {
+    fn drop(&mut self) {
+        if self.0.take().is_some() {
+            SERVING.store(false, Ordering::Release);
+        }
+    }
+}
+
+fn take_serving() -> bool {
+    SERVING.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok()
+}
+
+fn is_target() -> bool {
+    TARGET.load(Ordering::Acquire) == current_process().pid.0
+}
+
+fn request_stop() {
+    if TARGET.load(Ordering::Acquire) != NO_TARGET {
+        STOP_REQUESTED.store(true, Ordering::Release);
+    }
+}
+
+/// Stop the target on the current thread, which is one of its threads.
+fn stop(cx: &mut TrapContext, on_breakpoint: bool) {
+    loop {
+        if take_serving() {
+            let mut stub = STUB.lock();
+            let requested = STOP_REQUESTED.swap(false, Ordering::AcqRel);
+            // It may have been detached, or stopped and resumed by another thread.
+            if is_target() && (on_breakpoint || requested) {
+                STOPPED.store(true, Ordering::Release);
+                stub.serve(cx, on_breakpoint);
+                STOPPED.store(false, Ordering::Release);
+            }
+            drop(stub);
+            SERVING.store(false, Ordering::Release);
+            RESUMED.wake_all();
+            return;
+        }
+        if STOPPED.load(Ordering::Acquire) {
+            // A breakpoint is hit again after resuming, unless gdb has removed it.
+            RESUMED.wait_until(|| (!STOPPED.load(Ordering::Acquire)).then_some(()));
+            return;
+        }
+        // The poller holds it for a moment.
+        run_next_task();
+    }
+}
+
+impl GdbStub {
+    fn new() -> Self {
+        Self {
+            uart: Ns16550::new(gdb_uart_pa().expect("no UART for gdb")),
+            rx_state: RxState::Idle,
+            rx_packet: Vec::new(),
+            last_sent: Vec::new(),
+            breakpoints: BTreeMap::new(),
+        }
+    }
+
+    /// Serve gdb until it resumes the target.
+    fn serve(&mut self, cx: &mut TrapContext, on_breakpoint: bool) {
+        if IMAGE_GONE.swap(false, Ordering::AcqRel) {
+            self.breakpoints.clear();
+        }
+        // An ebreak of the program itself is skipped when resuming, or it would trap forever.
+        let stop_pc = cx.sepc;
+        let foreign_ebreak = on_breakpoint && !self.breakpoints.contains_key(&stop_pc);
+        self.send(b"S05");
+        loop {
+            let packet = match self.receive() {
+                Input::Packet(packet) => packet,
+                Input::Interrupt => continue,
+            };
+            if let Action::Resume = self.handle_stopped(&packet, cx) {
+                break;
+            }
+        }
+        if foreign_ebreak && cx.sepc == stop_pc {
+            cx.sepc += instruction_len(stop_pc);
+        }
+    }
+
+    fn handle_stopped(&mut self, packet: &[u8], cx: &mut TrapContext) -> Action {
+        match packet {
+            b"?" => self.send(b"S05"),
+            b"g" => {
+                let mut reply = Vec::new();
+                for reg in 0..NUM_REGS {
+                    push_hex(&mut reply, &read_reg(cx, reg).to_le_bytes());
+                }
+                self.send(&reply);
+            }
+            [b'G', values @ ..] => {
+                for (reg, value) in values.chunks(16).take(NUM_REGS).enumerate() {
+                    if let Some(value) = parse_reg_value(value) {
+                        write_reg(cx, reg, value);
+                    }
+                }
+                self.send(b"OK");
+            }
+            [b'p', reg @ ..] => match parse_hex(reg).filter(|&reg| reg < NUM_REGS) {
+                Some(reg) => {
+                    let mut reply = Vec::new();
+                    push_hex(&mut reply, &read_reg(cx, reg).to_le_bytes());
+                    self.send(&reply);
+                }
+                None => self.send(b"E01"),
+            },
+            [b'P', args @ ..] => {
+                let parsed = split_args(args, b'=').and_then(|(reg, value)| {
+                    Some((parse_hex(reg).filter(|&reg| reg < NUM_REGS)?, parse_reg_value(value)?))
+                });
+                match parsed {
+                    Some((reg, value)) => {
+                        write_reg(cx, reg, value);
+                        self.send(b"OK");
+                    }
+                    None => self.send(b"E01"),
+                }
+            }
+            [b'm', args @ ..] => match parse_range(args) {
+                Some((addr, len)) => {
+                    let mut buf = alloc::vec![0; len.min(PACKET_SIZE / 2 - 4)];
+                    match with_target_addr_space(|addr_space| copy_from_user(addr_space, &mut buf, addr)) {
+                        Some(Ok(())) => {
+                            let mut reply = Vec::new();
+                            push_hex(&mut reply, &buf);
+                            self.send(&reply);
+                        }
+                        _ => self.send(b"E14"),
+                    }
+                }
+                None => self.send(b"E01"),
+            },
+            [b'M', args @ ..] => {
+                let parsed = split_args(args, b':').and_then(|(range, data)| {
+                    let (addr, len) = parse_range(range)?;
+                    parse_hex_bytes(data).filter(|data| data.len() == len).map(|data| (addr, data))
+                });
+                match parsed {
+                    Some((addr, data)) => {
+                        match with_target_addr_space(|addr_space| addr_space.poke(addr, &data)) {
+                            Some(Ok(())) => self.send(b"OK"),
+                            _ => self.send(b"E14"),
+                        }
+                    }
+                    None => self.send(b"E01"),
+                }
+            }
+            [b'Z', b'0', b',', args @ ..] => match parse_range(args) {
+                Some((addr, kind)) => {
+                    let reply: &[u8] = if self.insert_breakpoint(addr, kind) { b"OK" } else { b"E14" };
+                    self.send(reply);
+                }
+                None => self.send(b"E01"),
+            },
+            [b'z', b'0', b',', args @ ..] => match parse_range(args) {
+                Some((addr, _)) => {
+                    self.remove_breakpoint(addr);
+                    self.send(b"OK");
+                }
+                None => self.send(b"E01"),
+            },
+            [b'c', addr @ ..] => {
+                if let Some(addr) = parse_hex(addr) {
+                    cx.sepc = addr;
+                }
+                return Action::Resume;
+            }
+            [b'D', ..] => {
+                self.detach();
+                self.send(b"OK");
+                return Action::Resume;
+            }
+            b"k" => {
+                self.kill();
+                return Action::Resume;
+            }
+            _ => self.handle_common(packet),
+        }
+        Action::Stay
+    }
+
+    fn handle_running(&mut self, packet: &[u8]) {
+        match packet {
+            // The stop reply is sent once the target has stopped.
+            b"?" if TARGET.load(Ordering::Acquire) != NO_TARGET => request_stop(),
+            b"?" => self.send(b"W00"),
+            _ if packet.starts_with(b"vAttach;") => {
+                let process = parse_hex(&packet[8..]).and_then(ProcessControlBlock::find);
+                match process {
+                    Some(process) if TARGET.load(Ordering::Acquire) == NO_TARGET && !process.is_zombie() => {
+                        IMAGE_GONE.store(false, Ordering::Release);
+                        TARGET.store(process.pid.0, Ordering::Release);
+                        request_stop();
+                    }
+                    _ => self.send(b"E01"),
+                }
+            }
+            [b'D', ..] => {
+                self.detach();
+                self.send(b"OK");
+            }
+            b"k" => self.kill(),
+            _ => self.handle_common(packet),
+        }
+    }
+
+    /// Packets that don't depend on whether the target is stopped. The unsupported ones are
+    /// answered with an empty packet.
+    fn handle_common(&mut self, packet: &[u8]) {
+        if packet.starts_with(b"qSupported") {
+            self.send(format!("PacketSize={:x}", PACKET_SIZE).as_bytes());
+        } else if packet == b"qAttached" {
+            self.send(b"1");
+        } else if packet.starts_with(b"H") || packet.starts_with(b"T") {
+            // There is only the thread that has stopped.
+            self.send(b"OK");
+        } else {
+            self.send(b"");
+        }
+    }
+
+    /// Run f with the address space of the target locked, and the breakpoints that are in it.
+    /// They are forgotten first if the code they were in is gone.
+    fn with_target_addr_space<R>(
+        &mut self,
+        f: impl FnOnce(&mut AddressSpace, &mut BTreeMap<usize, Vec<u8>>) -> R,
+    ) -> Option<R> {
+        let process = match ProcessControlBlock::find(TARGET.load(Ordering::Acquire)) {
+            Some(process) => process,
+            None => {
+                self.breakpoints.clear();
+                return None;
+            }
+        };
+        let mut process_inner = process.lock();
+        if IMAGE_GONE.swap(false, Ordering::AcqRel) {
+            self.breakpoints.clear();
+        }
+        Some(f(&mut process_inner.addr_space, &mut self.breakpoints))
+    }
+
+    /// kind is the length of the instruction to replace.
+    fn insert_breakpoint(&mut self, addr: usize, kind: usize) -> bool {
+        let ebreak: &[u8] = match kind {
+            2 => &C_EBREAK,
+            4 => &EBREAK,
+            _ => return false,
+        };
+        let patched = self.with_target_addr_space(|addr_space, breakpoints| -> user_ptr::Result<()> {
+            if breakpoints.contains_key(&addr) {
+                return Ok(());
+            }
+            let mut original = alloc::vec![0; kind];
+            copy_from_user(addr_space, &mut original, addr)?;
+            addr_space.poke(addr, ebreak)?;
+            breakpoints.insert(addr, original);
+            Ok(())
+        });
+        matches!(patched, Some(Ok(())))
+    }
+
+    fn remove_breakpoint(&mut self, addr: usize) {
+        self.with_target_addr_space(|addr_space, breakpoints| {
+            if let Some(original) = breakpoints.remove(&addr) {
+                // It's gone if the code has been unmapped.
+                let _ = addr_space.poke(addr, &original);
+            }
+        });
+    }
+
+    fn detach(&mut self) {
+        let addrs: Vec<usize> = self.breakpoints.keys().copied().collect();
+        addrs.into_iter().for_each(|addr| self.remove_breakpoint(addr));
+        TARGET.store(NO_TARGET, Ordering::Release);
+        STOP_REQUESTED.store(false, Ordering::Release);
+    }
+
+    /// The process is killed once its threads return to the user mode.
+    fn kill(&mut self) {
+        if let Some(process) = ProcessControlBlock::find(TARGET.load(Ordering::Acquire)) {
+            send_signal(&process, SIGKILL);
+        }
+        self.detach();
+    }
+
+    /// Tell gdb if the target has exited.
+    fn check_target_exit(&mut self) {
+        let pid = TARGET.load(Ordering::Acquire);
+        if pid == NO_TARGET {
+            return;
+        }
+        let exit_code = match ProcessControlBlock::find(pid) {
+            Some(process) => {
+                let process_inner = process.lock();
+                if !process_inner.is_zombie {
+                    return;
+                }
+                process_inner.exit_code
+            }
+            None => 0,
+        };
+        // Its code is gone with it.
+        self.breakpoints.clear();
+        TARGET.store(NO_TARGET, Ordering::Release);
+        STOP_REQUESTED.store(false, Ordering::Release);
+        self.send(format!("W{:02x}", exit_code as u8).as_bytes());
+    }
+
+    /// Wait for a packet or an interrupt, letting the other tasks run.
+    fn receive(&mut self) -> Input {
+        loop {
+            if let Some(input) = self.poll_input() {
+                return input;
+            }
+            run_next_task();
+        }
+    }
+
+    /// Take the received bytes until a packet or an interrupt is complete.
+    fn poll_input(&mut self) -> Option<Input> {
+        while let Some(byte) = self.uart.try_read() {
+            match (self.rx_state, byte) {
+                (_, b'$') => {
+                    self.rx_packet.clear();
+                    self.rx_state = RxState::Data;
+                }
+                (RxState::Idle, 0x03) => return Some(Input::Interrupt),
+                (RxState::Idle, b'-') => self.write_packet(&self.last_sent),
+                // Acks, and noise between the packets.
+                (RxState::Idle, _) => {}
+                (RxState::Data, b'#') => self.rx_state = RxState::Checksum(None),
+                (RxState::Data, _) if self.rx_packet.len() < PACKET_SIZE => self.rx_packet.push(byte),
+                (RxState::Data, _) => self.rx_state = RxState::Idle,
+                (RxState::Checksum(None), _) => self.rx_state = RxState::Checksum(Some(byte)),
+                (RxState::Checksum(Some(high)), _) => {
+                    self.rx_state = RxState::Idle;
+                    if parse_hex(&[high, byte]) == Some(checksum(&self.rx_packet) as usize) {
+                        self.uart.write(b'+');
+                        return Some(Input::Packet(core::mem::take(&mut self.rx_packet)));
+                    }
+                    self.uart.write(b'-');
+                }
+            }
+        }
+        None
+    }
+
+    fn send(&mut self, data: &[u8]) {
+        self.last_sent.clear();
+        self.last_sent.extend_from_slice(data);
+        self.write_packet(data);
+    }
+
+    fn write_packet(&self, data: &[u8]) {
+        let mut trailer = Vec::from(*b"#");
+        push_hex(&mut trailer, &[checksum(data)]);
+        self.uart.write(b'$');
+        data.iter().chain(&trailer).for_each(|&byte| self.uart.write(byte));
+    }
+}
+
+fn read_reg(cx: &TrapContext, reg: usize) -> usize {
+    match reg {
+        0 => 0,
+        PC => cx.sepc,
+        _ => cx.x[reg],
+    }
+}
+
+fn write_reg(cx: &mut TrapContext, reg: usize, value: usize) {
+    match reg {
+        0 => {}
+        PC => cx.sepc = value,
+        _ => cx.x[reg] = value,
+    }
+}
+
+/// 2 for a compressed instruction, whose lowest 2 bits aren't 0b11.
+fn instruction_len(pc: usize) -> usize {
+    let mut low = [0; 1];
+    let read = with_target_addr_space(|addr_space| copy_from_user(addr_space, &mut low, pc));
+    match read {
+        Some(Ok(())) if low[0] & 0b11 != 0b11 => 2,
+        _ => 4,
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn push_hex(out: &mut Vec<u8>, bytes: &[u8]) {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    for &byte in bytes {
+        out.push(DIGITS[(byte >> 4) as usize]);
+        out.push(DIGITS[(byte & 0xf) as usize]);
+    }
+}
+
+fn parse_hex(s: &[u8]) -> Option<usize> {
+    if s.is_empty() || s.len() > 16 {
+        return None;
+    }
+    s.iter().try_fold(0, |n, &c| (c as char).to_digit(16).map(|digit| n << 4 | digit as usize))
+}
+
+fn parse_hex_bytes(s: &[u8]) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    s.chunks(2).map(|pair| parse_hex(pair).map(|byte| byte as u8)).collect()
+}
+
+/// A register is sent as its bytes in the target order, i.e. little-endian.
+fn parse_reg_value(s: &[u8]) -> Option<usize> {
+    let bytes: [u8; 8] = parse_hex_bytes(s)?.try_into().ok()?;
+    Some(usize::from_le_bytes(bytes))
+}
+
+fn split_args(args: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let pos = args.iter().position(|&c| c == separator)?;
+    Some((&args[..pos], &args[pos + 1..]))
+}
+
+/// `addr,len`, also `addr,kind` of the breakpoints.
+fn parse_range(args: &[u8]) -> Option<(usize, usize)> {
+    let (addr, len) = split_args(args, b',')?;
+    Some((parse_hex(addr)?, parse_hex(len)?))
+}
@@ -1,6 +1,10 @@
+use crate::backtrace::print_backtrace;
 use crate::println;
 use crate::sbi::shutdown;
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
 
 #[panic_handler]
 fn panic_handler(info: &PanicInfo) -> ! {
@@ -14,5 +18,11 @@ fn panic_handler(info: &PanicInfo) -> ! {
     } else {
         println!("panic: {}", info)
     }
+    // Walking the stack may fault and panic again, so only the first panic does it.
+    if !PANICKING.swap(true, Ordering::SeqCst) {
+        print_backtrace();
+        #[cfg(feature = "monitor")]
+        crate::monitor::enter(false);
+    }
     shutdown();
 }
@@ -12,6 +12,7 @@ extern crate alloc;
 
 pub mod console;
 pub mod lang_items;
+pub mod backtrace;
 pub mod sbi;
 pub mod syscall;
 pub mod trap;
@@ -19,4 +20,13 @@ pub mod task;
 pub mod time;
 pub mod mm;
 pub mod utils;
-pub mod config;
\ No newline at end of file
+pub mod config;
+pub mod fdt;
+pub mod fs;
+pub mod drivers;
+pub mod smp;
+pub mod sync;
+#[cfg(feature = "monitor")]
+pub mod monitor;
+#[cfg(feature = "gdbstub")]
+pub mod gdbstub;
@@ -19,6 +19,11 @@ pub fn clear_bss() {
 
 pub fn init() {
     clear_bss();
+}
+
+/// Set up the traps and interrupts of the current hart.
+fn init_hart(hartid: usize) {
+    smp::init_hart(hartid);
     trap::init();
 
     unsafe {
@@ -26,20 +31,80 @@ pub fn init() {
         riscv::register::sstatus::clear_sie();
         riscv::register::sstatus::set_sum();
         riscv::register::sie::set_stimer();
+        // For the IPIs that wake up idle harts.
+        riscv::register::sie::set_ssoft();
+        // For the devices, routed by the PLIC.
+        riscv::register::sie::set_sext();
+    }
+}
+
+/// The frames of the memory region containing the kernel are those after it, within the
+/// identity mapping. The memory before it has the SBI and the loader.
+fn frame_range(kernel_pa: PhysAddr, kernel_size: usize) -> (PPN, PPN) {
+    let memory = fdt::machine().memory_containing(kernel_pa.0)
+        .expect("kernel out of the memory of the device tree");
+    let identity_map_end = kernel_pa.0 / IDENTITY_MAP_SIZE * IDENTITY_MAP_SIZE + IDENTITY_MAP_SIZE;
+    if memory.end() > identity_map_end {
+        println!("[kernel] memory beyond 0x{:x} is unused", identity_map_end);
     }
+    let frame_start = PhysAddr::new(kernel_pa.0 + kernel_size + PAGE_SIZE - 1).ppn();
+    let frame_end = PhysAddr::new(memory.end().min(identity_map_end)).ppn();
+    (frame_start, frame_end)
 }
 
+/// Give the other memory regions of the device tree to the frame allocator as a whole, as
+/// far as they are identity mapped.
+fn add_other_memory(kernel_pa: PhysAddr) {
+    let identity_map = identity_mapped_range();
+    for region in fdt::machine().memory.as_slice() {
+        if region.contains(kernel_pa.0) {
+            continue;
+        }
+        let start = region.start.max(identity_map.start);
+        let end = region.end().min(identity_map.end);
+        if start >= end {
+            println!("[kernel] memory 0x{:x} - 0x{:x} is unused", region.start, region.end());
+            continue;
+        }
+        if (start, end) != (region.start, region.end()) {
+            println!("[kernel] memory 0x{:x} - 0x{:x} is partly unused", region.start, region.end());
+        }
+        let frame_start = PhysAddr::new(start + PAGE_SIZE - 1).ppn();
+        let frame_end = PhysAddr::new(end).ppn();
+        if frame_start.0 < frame_end.0 {
+            frame_allocator::init(frame_start, frame_end);
+        }
+    }
+}
+
+/// The loader forwards hartid and dtb, which are passed by the SBI.
 #[no_mangle]
-pub extern "C" fn rust_main(kernel_pa: PhysAddr, kernel_size: usize) {
+pub extern "C" fn rust_main(kernel_pa: PhysAddr, kernel_size: usize, hartid: usize, dtb: usize) {
     init();
-    let kernel_pa_end = PhysAddr::new(kernel_pa.0 + kernel_size + PAGE_SIZE - 1).ppn();
-    let memory_pa_end = PhysAddr::new(QEMU_MEMORY_END).ppn();
-    mm::init(kernel_pa_end, memory_pa_end);
+    // Before the frames are allocated, which may overwrite the blob.
+    fdt::init(dtb);
+    time::init(fdt::machine().timebase_frequency);
+    init_hart(hartid);
+    let (frame_start, frame_end) = frame_range(kernel_pa, kernel_size);
+    mm::init(frame_start, frame_end);
+    add_other_memory(kernel_pa);
+    drivers::plic::init();
+    drivers::irq::init_hart(hartid);
 
     println!("hello from os");
     println!("kernel pa: 0x{:x} 0x{:x}", kernel_pa.0, kernel_size);
     println!("satp: 0x{:x}", riscv::register::satp::read().bits());
-    task::run_initproc();
+    fs::init();
+    task::add_initproc();
+    smp::start_secondary_harts(kernel_pa);
+    task::run_tasks();
+}
 
-    sbi::shutdown();
+/// Where the other harts jump to from _secondary_start, on their own boot stacks.
+#[no_mangle]
+pub extern "C" fn secondary_main(hartid: usize) -> ! {
+    init_hart(hartid);
+    drivers::irq::init_hart(hartid);
+    println!("hart {} is online", hartid);
+    task::run_tasks();
 }
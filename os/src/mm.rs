@@ -2,6 +2,7 @@ pub mod page_table;
 pub mod frame_allocator;
 pub mod heap_allocator;
 pub mod address_space;
+pub mod user_ptr;
 
 use crate::utils::BitField;
 pub use page_table::*;
@@ -9,7 +10,7 @@ pub use page_table::*;
 pub fn init(frame_start: PPN, frame_end: PPN) {
     heap_allocator::init();
     frame_allocator::init(frame_start, frame_end);
-    page_table::init();
+    page_table::init(frame_start.as_pa());
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -48,6 +49,15 @@ impl VirtAddr {
         Self(va)
     }
 
+    /// Return None if va isn't a valid Sv39 address.
+    pub fn try_new(va: usize) -> Option<Self> {
+        let valid = match va.get_bits(38) {
+            0 => va.get_bits(39..=63) == 0,
+            _ => va.get_bits(39..=63) == (1 << 25) - 1,
+        };
+        valid.then_some(Self(va))
+    }
+
     pub const unsafe fn new_unchecked(va: usize) -> Self {
         Self(va)
     }
@@ -2,8 +2,10 @@ use super::*;
 use alloc::vec::Vec;
 use frame_allocator::*;
 use page_table::GLOBAL_PTES;
+use user_ptr::{AccessError, BadAddress, NoMemory};
+use crate::syscall::Errno;
 use crate::{
-    config::*, trap::TrapContext,
+    config::*, sbi, smp, trap::{trap_cx_va, TrapContext},
 };
 // use crate::println;
 
@@ -13,107 +15,225 @@ pub struct AddressSpace {
     brk: VPN,
     page_table: PPN,
     allocated_frames: Vec<PPN>,
+    peak_frames: usize,
+    vmas: Vec<Vma>,
 }
 
 impl AddressSpace {
-    pub fn new(asid: usize) -> Self {
+    /// It fails with ENOMEM if there is no frame for the root page table.
+    pub fn new(asid: usize) -> Result<Self, Errno> {
         let mut allocated_frames = Vec::new();
         let root_page_table = unsafe {
-            let ppn = frame_alloc();
+            let ppn = frame_alloc().ok_or(Errno::ENOMEM)?;
             allocated_frames.push(ppn);
             ppn.as_page_table_mut()
         };
         root_page_table.clear();
         root_page_table.add_globals();
 
-        Self {
+        Ok(Self {
             asid,
             // Be careful not to overlap with global mapping (esp. 1 GB pte).
             brk: KERNEL_BRK_VA.vpn(),
             page_table: root_page_table.ppn(),
             allocated_frames,
-        }
+            peak_frames: 1,
+            vmas: Vec::new(),
+        })
     }
 
-    // Return the address space and kernel stack pointer
-    pub fn from_elf(elf_data: &[u8], asid: usize) -> Self {
-        let elf = xmas_elf::ElfFile::new(elf_data).expect("invalid elf data");
-        let magic = elf.header.pt1.magic;
-        assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf");
-        let entry_point = elf.header.pt2.entry_point();
+    /// Return the address space and the entry point. The stacks of the threads are
+    /// allocated separately.
+    /// It fails with ENOEXEC if the elf is malformed, or any segment is out of the user space
+    /// or overlaps with another, or with the stacks, and with ENOMEM if we run out of frames.
+    /// Segments may share a page as long as their bytes don't overlap, like those of most
+    /// linkers. The page has the permissions of both.
+    pub fn from_elf(elf_data: &[u8], asid: usize) -> Result<(Self, usize), Errno> {
+        let elf = xmas_elf::ElfFile::new(elf_data).map_err(|_| Errno::ENOEXEC)?;
+        if elf.header.pt1.magic != [0x7f, 0x45, 0x4c, 0x46] {
+            return Err(Errno::ENOEXEC);
+        }
+        // xmas_elf indexes the program header table without bounds checks.
+        let pt2 = &elf.header.pt2;
+        let ph_table_end = (pt2.ph_entry_size() as usize)
+            .checked_mul(pt2.ph_count() as usize)
+            .and_then(|size| size.checked_add(pt2.ph_offset() as usize));
+        if elf.header.pt1.class() != xmas_elf::header::Class::SixtyFour
+            || pt2.ph_entry_size() as usize != core::mem::size_of::<xmas_elf::program::ProgramHeader64>()
+            || !matches!(ph_table_end, Some(end) if end <= elf_data.len())
+        {
+            return Err(Errno::ENOEXEC);
+        }
+        let entry_point = elf.header.pt2.entry_point() as usize;
+        if entry_point >= USER_SPACE_END {
+            return Err(Errno::ENOEXEC);
+        }
 
-        let mut addr_space = Self::new(asid);
+        let mut addr_space = Self::new(asid)?;
+        // [start, end) of the segments loaded.
+        let mut segments: Vec<(usize, usize)> = Vec::new();
 
         for ph in elf.program_iter() {
-            if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
-                let start_va = VirtAddr::new(ph.virtual_addr() as usize);
-                let end_va = VirtAddr::new((ph.virtual_addr() + ph.mem_size()) as usize);
-                let flags_at_level = {
-                    let mut flags_at_level = [
-                        PteFlags::user_leaf(),
-                        PteFlags::user_inner(),
-                        PteFlags::user_inner(),
-                    ];
-                    let ph_flags = ph.flags();
-                    if ph_flags.is_read() {
-                        flags_at_level[0] |= PteFlags::R;
-                    }
-                    if ph_flags.is_write() {
-                        // W imply R
-                        flags_at_level[0] |= PteFlags::R;
-                        flags_at_level[0] |= PteFlags::W;
-                    }
-                    if ph_flags.is_execute() {
-                        flags_at_level[0] |= PteFlags::X;
+            if ph.get_type().map_err(|_| Errno::ENOEXEC)? != xmas_elf::program::Type::Load {
+                continue;
+            }
+            let offset = ph.offset() as usize;
+            let file_size = ph.file_size() as usize;
+            let mem_size = ph.mem_size() as usize;
+            let data = offset.checked_add(file_size)
+                .and_then(|data_end| elf_data.get(offset..data_end))
+                .ok_or(Errno::ENOEXEC)?;
+            if file_size > mem_size {
+                return Err(Errno::ENOEXEC);
+            }
+            let start_va = ph.virtual_addr() as usize;
+            let end_va = match start_va.checked_add(mem_size) {
+                Some(end_va) if end_va <= USER_SPACE_END => end_va,
+                _ => return Err(Errno::ENOEXEC),
+            };
+            if start_va < USER_STACK_REGION_END.0 && USER_STACK_REGION_START.0 < end_va {
+                return Err(Errno::ENOEXEC);
+            }
+            if mem_size == 0 {
+                continue;
+            }
+            if segments.iter().any(|&(start, end)| start < end_va && start_va < end) {
+                return Err(Errno::ENOEXEC);
+            }
+            segments.push((start_va, end_va));
+
+            let flags_at_level = {
+                let mut flags_at_level = [
+                    PteFlags::user_leaf(),
+                    PteFlags::user_inner(),
+                    PteFlags::user_inner(),
+                ];
+                let ph_flags = ph.flags();
+                if ph_flags.is_read() {
+                    flags_at_level[0] |= PteFlags::R;
+                }
+                if ph_flags.is_write() {
+                    // W imply R
+                    flags_at_level[0] |= PteFlags::R;
+                    flags_at_level[0] |= PteFlags::W;
+                }
+                if ph_flags.is_execute() {
+                    flags_at_level[0] |= PteFlags::X;
+                }
+                flags_at_level
+            };
+            let start_vpn = VirtAddr::new(start_va).vpn();
+            let end_vpn = VPN((end_va + PAGE_SIZE - 1) / PAGE_SIZE);
+            // Only the first and the last page can be shared, since the bytes don't overlap.
+            let is_shared = |addr_space: &Self, vpn: usize| addr_space.vmas.iter().any(|v| v.contains(VPN(vpn)));
+            let mut vma_start = start_vpn;
+            let mut vma_end = end_vpn;
+            if is_shared(&addr_space, vma_start.0) {
+                addr_space.widen_vma_page(vma_start, flags_at_level[0]);
+                vma_start.0 += 1;
+            }
+            if vma_start.0 < vma_end.0 && is_shared(&addr_space, vma_end.0 - 1) {
+                addr_space.widen_vma_page(VPN(vma_end.0 - 1), flags_at_level[0]);
+                vma_end.0 -= 1;
+            }
+            if vma_start.0 < vma_end.0 {
+                let vma = Vma::new(vma_start, vma_end, flags_at_level[0], VmaKind::Elf);
+                if !addr_space.add_vma(vma) {
+                    return Err(Errno::ENOEXEC);
+                }
+            }
+
+            // Where data[0] is in the first page.
+            let page_offset = start_va % PAGE_SIZE;
+            for (i, vpn) in (start_vpn.0..end_vpn.0).enumerate() {
+                let frame = match addr_space.leaf_pte_mut(VPN(vpn)) {
+                    // Loaded with another segment, whose bytes are elsewhere in the page.
+                    Some(pte) => {
+                        pte.set_flags(pte.flags() | flags_at_level[0]);
+                        pte.ppn()
                     }
-                    flags_at_level
-                };
-                let mut mapped_size = 0;
-                let mut mapped_va = start_va;
-                while mapped_va.0 < end_va.0 {
-                    let frame = addr_space.alloc_frame();
-                    if mapped_size < ph.file_size() {
+                    None => {
+                        let frame = addr_space.alloc_frame()?;
+                        // The rest of the last page and the bss are zeros.
                         unsafe {
-                            core::ptr::copy_nonoverlapping(
-                                &elf_data[(ph.offset() + mapped_size) as usize] as *const u8,
-                                frame.as_pa().0 as *mut u8,
-                                core::cmp::min(ph.file_size().checked_sub(mapped_size).unwrap(), 4096) as usize
-                            );
+                            core::ptr::write_bytes(frame.as_pa().0 as *mut u8, 0, PAGE_SIZE);
                         }
+                        addr_space.build_mapping(VPN(vpn), frame, flags_at_level)?;
+                        frame
                     }
-                    addr_space.build_mapping(mapped_va.vpn(), frame, flags_at_level);
-                    mapped_va.0 += 4096;
-                    mapped_size += 4096;
+                };
+                let page = unsafe {
+                    core::slice::from_raw_parts_mut(frame.as_pa().0 as *mut u8, PAGE_SIZE)
+                };
+                // The part of data in this page.
+                let page_start = (i * PAGE_SIZE).saturating_sub(page_offset);
+                let page_end = ((i + 1) * PAGE_SIZE - page_offset).min(data.len());
+                if page_start < page_end {
+                    let dst_start = (page_start + page_offset) % PAGE_SIZE;
+                    page[dst_start..dst_start + page_end - page_start]
+                        .copy_from_slice(&data[page_start..page_end]);
                 }
             }
         }
 
-        let mut mapped_size = 0;
-        while mapped_size < USER_STACK_SIZE {
-            addr_space.alloc_page_for(USER_STACK_VA.add(mapped_size).vpn());
-            mapped_size += PAGE_SIZE;
+        Ok((addr_space, entry_point))
+    }
+
+    /// Map the kernel stack of the thread, and reserve its user stack.
+    /// Return the top of the user stack, or ENOMEM if the place is taken or we run out of
+    /// frames.
+    pub fn alloc_thread_stacks(&mut self, tid: usize) -> Result<VirtAddr, Errno> {
+        // The user stack is mapped on demand.
+        let user_stack = Vma::new(
+            user_stack_va(tid).vpn(),
+            user_stack_va(tid).add(USER_STACK_SIZE).vpn(),
+            PteFlags::user_leaf() | PteFlags::R | PteFlags::W,
+            VmaKind::Stack,
+        );
+        if !self.add_vma(user_stack) {
+            return Err(Errno::ENOMEM);
         }
-        let usp = USER_STACK_VA.add(USER_STACK_SIZE);
 
         let mut mapped_size = 0;
-        let mut kstack_ppn = PPN(0);
         while mapped_size < KERNEL_STACK_SIZE {
-            kstack_ppn = addr_space.alloc_kernel_page_for(KERNEL_STACK_VA.add(mapped_size).vpn());
+            if let Err(err) = self.alloc_kernel_page_for(kernel_stack_va(tid).add(mapped_size).vpn()) {
+                self.remove_vma_range(user_stack.start, user_stack.end);
+                self.free_kernel_stack(tid, mapped_size);
+                return Err(err);
+            }
             mapped_size += PAGE_SIZE;
         }
+        Ok(user_stack_va(tid).add(USER_STACK_SIZE))
+    }
 
-        // let kstack =  unsafe { &mut *(kstack_ppn.as_pa().0 as *mut KernelStack) };
-        let trap_cx_ptr = 
-            (kstack_ppn.as_pa().0 + PAGE_SIZE - core::mem::size_of::<TrapContext>()) as *mut TrapContext;
-        let task_init_trap_cx = TrapContext::app_init_context(
-            entry_point as usize, usp.0
-        );
-        unsafe {
-            trap_cx_ptr.write(task_init_trap_cx);
+    /// Undo alloc_thread_stacks. The thread must not be running on the kernel stack.
+    pub fn free_thread_stacks(&mut self, tid: usize) {
+        let user_stack_start = user_stack_va(tid);
+        // It's fine if the user stack is gone already.
+        self.remove_vma_range(user_stack_start.vpn(), user_stack_start.add(USER_STACK_SIZE).vpn());
+        self.free_kernel_stack(tid, KERNEL_STACK_SIZE);
+    }
+
+    /// Unmap the first `size` bytes of the kernel stack of the thread.
+    fn free_kernel_stack(&mut self, tid: usize, size: usize) {
+        let mut freed_size = 0;
+        while freed_size < size {
+            let pte = self.leaf_pte_mut(kernel_stack_va(tid).add(freed_size).vpn())
+                .expect("missing kernel stack");
+            let ppn = pte.ppn();
+            *pte = PageTableEntry::zero();
+            self.free_frame(ppn);
+            freed_size += PAGE_SIZE;
         }
-        // kstack.push_context(task_init_trap_cx);
+        self.flush_tlb();
+    }
 
-        addr_space
+    /// The trap context of the thread, through the identity mapping of physical memory.
+    /// The kernel stack of the thread must be mapped.
+    #[allow(clippy::mut_from_ref)]
+    pub fn trap_cx_mut(&self, tid: usize) -> &mut TrapContext {
+        let trap_cx_ptr = self.translate(trap_cx_va(tid)).expect("missing kernel stack").0 as *mut TrapContext;
+        unsafe { &mut *trap_cx_ptr }
     }
 
     pub fn satp(&self) -> usize {
@@ -125,56 +245,72 @@ impl AddressSpace {
         satp
     }
 
-    pub fn alloc_page(&mut self) -> (VPN, PPN) {
+    pub fn alloc_page(&mut self) -> Result<(VPN, PPN), Errno> {
         let vpn = self.brk;
         self.brk.0 += 1;
 
-        (vpn, self.alloc_page_for(vpn))
+        Ok((vpn, self.alloc_page_for(vpn)?))
     }
 
-    pub fn alloc_page_for(&mut self, vpn: VPN) -> PPN {
-        let ppn = self.alloc_frame();
+    pub fn alloc_page_for(&mut self, vpn: VPN) -> Result<PPN, Errno> {
+        let ppn = self.alloc_frame()?;
         let flags_at_level = [
             PteFlags::V | PteFlags::R | PteFlags::W | PteFlags::X | PteFlags::U,
             PteFlags::V,
             PteFlags::V,
         ];
-        self.build_mapping(vpn, ppn, flags_at_level);
-        ppn
+        self.build_mapping(vpn, ppn, flags_at_level)?;
+        Ok(ppn)
     }
 
-    pub fn alloc_kernel_page(&mut self) -> (VPN, PPN) {
+    pub fn alloc_kernel_page(&mut self) -> Result<(VPN, PPN), Errno> {
         let vpn = self.brk;
         self.brk.0 += 1;
 
-        let ppn = self.alloc_kernel_page_for(vpn);
-        (vpn, ppn)
+        let ppn = self.alloc_kernel_page_for(vpn)?;
+        Ok((vpn, ppn))
     }
 
-    pub fn alloc_kernel_page_for(&mut self, vpn: VPN) -> PPN {
-        let ppn = self.alloc_frame();
+    pub fn alloc_kernel_page_for(&mut self, vpn: VPN) -> Result<PPN, Errno> {
+        let ppn = self.alloc_frame()?;
         let flags_at_level = [
             PteFlags::V | PteFlags::R | PteFlags::W | PteFlags::X,
             PteFlags::V,
             PteFlags::V,
         ];
-        self.build_mapping(vpn, ppn, flags_at_level);
-        ppn
+        self.build_mapping(vpn, ppn, flags_at_level)?;
+        Ok(ppn)
     }
 
-    pub fn alloc_frame(&mut self) -> PPN {
-        let ppn = frame_alloc();
-        self.allocated_frames.push(ppn);
-        ppn
+    /// Return ENOMEM if we run out of frames.
+    pub fn alloc_frame(&mut self) -> Result<PPN, Errno> {
+        let ppn = frame_alloc().ok_or(Errno::ENOMEM)?;
+        self.own_frame(ppn);
+        Ok(ppn)
     }
 
-    fn alloc_page_table(&mut self) -> PPN {
-        let ppn = frame_alloc();
+    fn alloc_page_table(&mut self) -> Result<PPN, Errno> {
+        let ppn = self.alloc_frame()?;
         unsafe {
             ppn.as_page_table_mut().clear();
         }
+        Ok(ppn)
+    }
+
+    /// The frame is freed along with the address space.
+    fn own_frame(&mut self, ppn: PPN) {
         self.allocated_frames.push(ppn);
-        ppn
+        self.peak_frames = self.peak_frames.max(self.allocated_frames.len());
+    }
+
+    /// Frames owned now, including the page tables and the kernel stacks.
+    pub fn resident_frames(&self) -> usize {
+        self.allocated_frames.len()
+    }
+
+    /// The most frames it has ever owned.
+    pub fn peak_frames(&self) -> usize {
+        self.peak_frames
     }
 
     pub fn translate(&self, va: VirtAddr) -> Option<PhysAddr> {
@@ -183,14 +319,16 @@ impl AddressSpace {
         }
     }
 
-    pub fn build_mapping(&mut self, vpn: VPN, ppn: PPN, flags_at_level: [PteFlags; 3]) {
+    /// Map vpn to ppn. It fails with ENOMEM if a page table can't be allocated, and
+    /// nothing is mapped then.
+    pub fn build_mapping(&mut self, vpn: VPN, ppn: PPN, flags_at_level: [PteFlags; 3]) -> Result<(), Errno> {
         let root_table = unsafe { self.page_table.as_page_table_mut() };
         let root_pte = {
             let index = vpn.level(2);
             if root_table.0[index].is_valid() {
                 root_table.0[index]
             } else {
-                let frame = self.alloc_page_table();
+                let frame = self.alloc_page_table()?;
                 PageTableEntry::inner(frame, flags_at_level[2])
             }
         };
@@ -201,7 +339,7 @@ impl AddressSpace {
             if sub_table.0[index].is_valid() {
                 sub_table.0[index]
             } else {
-                let frame = self.alloc_page_table();
+                let frame = self.alloc_page_table()?;
                 PageTableEntry::inner(frame, flags_at_level[1])
             }
         };
@@ -215,12 +353,27 @@ impl AddressSpace {
             root_table.set_entry(vpn.level(2), root_pte);
         }
         // println!("map 0x{:x} to 0x{:x}", vpn.as_va().0, ppn.as_pa().0);
+        Ok(())
     }
 
-    pub fn dup(&self, asid: usize) -> Self {
-        let mut new = Self::new(asid) ;
+    /// Duplicate the address space for fork. User pages are shared copy-on-write.
+    /// It fails with ENOMEM if we run out of frames for the page tables or the kernel stacks.
+    pub fn dup(&mut self, asid: usize) -> Result<Self, Errno> {
+        let mut new = Self::new(asid)?;
         new.brk = self.brk;
+        new.vmas = self.vmas.clone();
+
+        let shared = self.share_pages_with(&mut new);
+        // The parent may have cached the writable mappings, even if it fails halfway.
+        self.flush_tlb();
+        // crate::println!("dup ok");
 
+        shared.map(|_| new)
+    }
+
+    /// Map the pages of self into the new address space for dup. Those shared so far are
+    /// released along with it on failure.
+    fn share_pages_with(&mut self, new: &mut Self) -> Result<(), Errno> {
         let global_index = {
             let global_ptes = GLOBAL_PTES.lock();
             [global_ptes.kernel_pte_index, global_ptes.memory_pte_index]
@@ -233,7 +386,7 @@ impl AddressSpace {
             }
             let sub_table = unsafe { root_pte.as_page_table() };
             let new_sub_table = {
-                let new_sub_ppn = new.alloc_page_table();
+                let new_sub_ppn = new.alloc_page_table()?;
                 unsafe {
                     new_root_table.set_entry(index, root_pte.with_ppn(new_sub_ppn));
                     new_sub_ppn.as_page_table_mut()
@@ -244,36 +397,331 @@ impl AddressSpace {
                 if !sub_pte.is_valid() {
                     continue;
                 }
-                let leaf_table = unsafe { sub_pte.as_page_table() };
+                let leaf_table = unsafe { sub_pte.as_page_table_mut() };
                 let new_leaf_table = {
-                    let new_leaf_ppn = new.alloc_page_table();
+                    let new_leaf_ppn = new.alloc_page_table()?;
                     unsafe {
                         new_sub_table.set_entry(index, sub_pte.with_ppn(new_leaf_ppn));
                         new_leaf_ppn.as_page_table_mut()
                     }
                 };
-                for (index, leaf_pte) in leaf_table.0.iter().enumerate() {
+                for (index, leaf_pte) in leaf_table.0.iter_mut().enumerate() {
                     if !leaf_pte.is_valid() {
                         continue;
                     }
                     let leaf_page = leaf_pte.ppn();
-                    let new_leaf_page = new.alloc_frame();
+                    let flags = leaf_pte.flags();
+                    if !flags.contains(PteFlags::U) {
+                        // Kernel pages in user space (i.e. the kernel stack) are written by the
+                        // kernel, which can't handle page faults. So copy them now.
+                        let new_leaf_page = new.alloc_frame()?;
+                        unsafe {
+                            // Use the identity mapping of physical memory
+                            core::ptr::copy_nonoverlapping(
+                                leaf_page.as_pa().0 as *const u8,
+                                new_leaf_page.as_pa().0 as *mut u8,
+                                PAGE_SIZE
+                            );
+                            new_leaf_table.set_entry(index, leaf_pte.with_ppn(new_leaf_page));
+                        }
+                        continue;
+                    }
+
+                    // Share the frame. Writable pages become read-only in both address spaces,
+                    // and will be copied on the first write.
+                    if flags.contains(PteFlags::W) {
+                        leaf_pte.set_flags((flags - PteFlags::W) | PteFlags::COW);
+                    }
+                    frame_share(leaf_page);
+                    new.own_frame(leaf_page);
                     unsafe {
-                        // Use the identity mapping of physical memory
-                        // crate::println!("leaf_page 0x{:x}\nnew_leaf_page 0x{:x}", leaf_page.as_pa().0, new_leaf_page.as_pa().0);
-                        core::ptr::copy_nonoverlapping(
-                            leaf_page.as_pa().0 as *const u8, 
-                            new_leaf_page.as_pa().0 as *mut u8,
-                            4096
-                        );
-                        new_leaf_table.set_entry(index, leaf_pte.with_ppn(new_leaf_page))
+                        new_leaf_table.set_entry(index, *leaf_pte);
                     }
                 }
             }
         }
-        // crate::println!("dup ok");
+        Ok(())
+    }
+}
+
+impl AddressSpace {
+    /// Flush the TLB after changing the mappings. The task may have run on other harts,
+    /// which could still cache the old mappings of this asid.
+    fn flush_tlb(&self) {
+        unsafe {
+            riscv::asm::sfence_vma_all();
+        }
+        let other_harts = smp::other_harts();
+        if other_harts != 0 {
+            sbi::remote_sfence_vma_asid(other_harts, 0, usize::MAX, self.asid);
+        }
+    }
+
+    fn leaf_pte_mut(&mut self, vpn: VPN) -> Option<&mut PageTableEntry> {
+        let mut page_table = unsafe { self.page_table.as_page_table_mut() };
+        for level in (1..=2).rev() {
+            let pte = page_table.0[vpn.level(level)];
+            if !pte.is_valid() || pte.is_leaf() {
+                return None;
+            }
+            page_table = unsafe { pte.as_page_table_mut() };
+        }
+        let pte = &mut page_table.0[vpn.level(0)];
+        pte.is_valid().then_some(pte)
+    }
+
+    fn free_frame(&mut self, ppn: PPN) {
+        let pos = self.allocated_frames.iter()
+            .position(|frame| frame.0 == ppn.0)
+            .expect("frame not owned");
+        self.allocated_frames.swap_remove(pos);
+        frame_free(ppn);
+    }
+
+    /// Reserve the pages in the vma. Return false if it overlaps with other vmas.
+    pub fn add_vma(&mut self, vma: Vma) -> bool {
+        if self.vmas.iter().any(|v| v.overlaps(vma.start, vma.end)) {
+            return false;
+        }
+        self.vmas.push(vma);
+        true
+    }
+
+    /// Add the flags to the page of a vma, which is split for it.
+    fn widen_vma_page(&mut self, vpn: VPN, flags: PteFlags) {
+        let pos = self.vmas.iter().position(|v| v.contains(vpn)).expect("page not reserved");
+        let vma = self.vmas.swap_remove(pos);
+        let next = VPN(vpn.0 + 1);
+        if vma.start.0 < vpn.0 {
+            self.vmas.push(Vma::new(vma.start, vpn, vma.flags, vma.kind));
+        }
+        self.vmas.push(Vma::new(vpn, next, vma.flags | flags, vma.kind));
+        if next.0 < vma.end.0 {
+            self.vmas.push(Vma::new(next, vma.end, vma.flags, vma.kind));
+        }
+    }
+
+    /// Undo mmap. Return false if any page in [start, end) isn't mmapped, so that the
+    /// user can't unmap the segments or the stacks, which the kernel relies on.
+    pub fn munmap(&mut self, start: VPN, end: VPN) -> bool {
+        let mmapped = (start.0..end.0).all(|vpn| {
+            self.vmas.iter().any(|v| v.kind == VmaKind::Mmap && v.contains(VPN(vpn)))
+        });
+        mmapped && self.remove_vma_range(start, end)
+    }
+
+    /// Unmap [start, end) and free the frames.
+    /// Return false if any page in it isn't reserved by a vma.
+    pub fn remove_vma_range(&mut self, start: VPN, end: VPN) -> bool {
+        let covered = (start.0..end.0).all(|vpn| {
+            self.vmas.iter().any(|v| v.contains(VPN(vpn)))
+        });
+        if !covered {
+            return false;
+        }
+
+        let mut vmas = Vec::new();
+        for vma in self.vmas.drain(..) {
+            if !vma.overlaps(start, end) {
+                vmas.push(vma);
+                continue;
+            }
+            if vma.start.0 < start.0 {
+                vmas.push(Vma::new(vma.start, start, vma.flags, vma.kind));
+            }
+            if end.0 < vma.end.0 {
+                vmas.push(Vma::new(end, vma.end, vma.flags, vma.kind));
+            }
+        }
+        self.vmas = vmas;
+
+        for vpn in start.0..end.0 {
+            if let Some(pte) = self.leaf_pte_mut(VPN(vpn)) {
+                let ppn = pte.ppn();
+                *pte = PageTableEntry::zero();
+                self.free_frame(ppn);
+            }
+        }
+        self.flush_tlb();
+        true
+    }
+
+    /// Resolve a page fault at va from the user. Return false if it's a real bad access,
+    /// or ENOMEM if there is no frame for it.
+    pub fn handle_page_fault(&mut self, va: VirtAddr, access: MemAccess) -> Result<bool, Errno> {
+        let vpn = va.vpn();
+        if let Some(pte) = self.leaf_pte_mut(vpn) {
+            // The page is there, so it's either COW or a permission violation.
+            let pte = *pte;
+            if access == MemAccess::Write && pte.flags().contains(PteFlags::COW) {
+                self.resolve_cow(vpn, pte)?;
+                return Ok(true);
+            }
+            return Ok(false);
+        }
+
+        let vma = match self.vmas.iter().find(|v| v.contains(vpn)) {
+            Some(vma) if vma.permits(access) => *vma,
+            _ => return Ok(false),
+        };
+        let frame = self.alloc_frame()?;
+        unsafe {
+            core::ptr::write_bytes(frame.as_pa().0 as *mut u8, 0, PAGE_SIZE);
+        }
+        let flags_at_level = [
+            vma.flags,
+            PteFlags::user_inner(),
+            PteFlags::user_inner(),
+        ];
+        if let Err(err) = self.build_mapping(vpn, frame, flags_at_level) {
+            self.free_frame(frame);
+            return Err(err);
+        }
+        self.flush_tlb();
+        Ok(true)
+    }
+
+    fn resolve_cow(&mut self, vpn: VPN, pte: PageTableEntry) -> Result<(), Errno> {
+        let flags = (pte.flags() - PteFlags::COW) | PteFlags::W;
+        let old_page = pte.ppn();
+        if frame_ref_count(old_page) == 1 {
+            // We are the last owner, just take it.
+            self.leaf_pte_mut(vpn).unwrap().set_flags(flags);
+        } else {
+            let new_page = self.alloc_frame()?;
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    old_page.as_pa().0 as *const u8,
+                    new_page.as_pa().0 as *mut u8,
+                    PAGE_SIZE
+                );
+            }
+            *self.leaf_pte_mut(vpn).unwrap() = PageTableEntry::leaf(new_page, flags);
+            self.free_frame(old_page);
+        }
+        self.flush_tlb();
+        Ok(())
+    }
+
+    /// Translate a user va for the access. The page is faulted in if needed,
+    /// since the kernel can't handle page faults by itself.
+    pub fn translate_user(&mut self, va: usize, access: MemAccess) -> Result<PhysAddr, AccessError> {
+        let va = VirtAddr::try_new(va).ok_or(BadAddress)?;
+        let user_pte = |addr_space: &mut Self| {
+            addr_space.leaf_pte_mut(va.vpn())
+                .map(|pte| *pte)
+                .filter(|pte| pte.flags().contains(PteFlags::U) && access.permitted_by(pte.flags()))
+        };
+        let pte = match user_pte(self) {
+            Some(pte) => pte,
+            None => {
+                self.handle_page_fault(va, access).map_err(|_| NoMemory)?;
+                user_pte(self).ok_or(BadAddress)?
+            }
+        };
+        Ok(PhysAddr::new(pte.ppn().as_pa().0 | va.offset()))
+    }
+
+    /// Write to the user memory regardless of the permissions, e.g. to patch the code.
+    /// A shared frame is copied first, so that the other address spaces don't see it.
+    pub fn poke(&mut self, va: usize, bytes: &[u8]) -> Result<(), AccessError> {
+        let end = va.checked_add(bytes.len()).ok_or(BadAddress)?;
+        let mut cur = va;
+        while cur < end {
+            let piece_len = core::cmp::min(PAGE_SIZE - cur % PAGE_SIZE, end - cur);
+            // Fault it in, and make sure it's a user page.
+            self.translate_user(cur, MemAccess::Read)?;
+            let vpn = VirtAddr::try_new(cur).ok_or(BadAddress)?.vpn();
+            let pte = *self.leaf_pte_mut(vpn).unwrap();
+            let mut page = pte.ppn();
+            if frame_ref_count(page) > 1 {
+                let new_page = self.alloc_frame().map_err(|_| NoMemory)?;
+                unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        page.as_pa().0 as *const u8,
+                        new_page.as_pa().0 as *mut u8,
+                        PAGE_SIZE
+                    );
+                }
+                *self.leaf_pte_mut(vpn).unwrap() = PageTableEntry::leaf(new_page, pte.flags());
+                self.free_frame(page);
+                self.flush_tlb();
+                page = new_page;
+            }
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    bytes[cur - va..].as_ptr(),
+                    (page.as_pa().0 + cur % PAGE_SIZE) as *mut u8,
+                    piece_len
+                );
+            }
+            cur += piece_len;
+        }
+        // The code may have been patched.
+        unsafe {
+            core::arch::asm!("fence.i");
+        }
+        let other_harts = smp::other_harts();
+        if other_harts != 0 {
+            sbi::remote_fence_i(other_harts);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemAccess {
+    Read,
+    Write,
+    Execute,
+}
+
+impl MemAccess {
+    fn permitted_by(self, flags: PteFlags) -> bool {
+        match self {
+            Self::Read => flags.contains(PteFlags::R),
+            Self::Write => flags.contains(PteFlags::W),
+            Self::Execute => flags.contains(PteFlags::X),
+        }
+    }
+}
+
+/// Who set up the vma.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaKind {
+    /// A loadable segment of the ELF.
+    Elf,
+    /// The user stack of a thread.
+    Stack,
+    /// By the mmap syscall. Only these can be unmapped by the user.
+    Mmap,
+}
+
+/// A reserved range [start, end) of user pages. They are mapped on the first touch.
+#[derive(Debug, Clone, Copy)]
+pub struct Vma {
+    pub start: VPN,
+    pub end: VPN,
+    /// Flags of the leaf ptes.
+    pub flags: PteFlags,
+    pub kind: VmaKind,
+}
+
+impl Vma {
+    pub fn new(start: VPN, end: VPN, flags: PteFlags, kind: VmaKind) -> Self {
+        Self { start, end, flags, kind }
+    }
+
+    pub fn contains(&self, vpn: VPN) -> bool {
+        self.start.0 <= vpn.0 && vpn.0 < self.end.0
+    }
+
+    pub fn overlaps(&self, start: VPN, end: VPN) -> bool {
+        self.start.0 < end.0 && start.0 < self.end.0
+    }
 
-        new
+    fn permits(&self, access: MemAccess) -> bool {
+        access.permitted_by(self.flags)
     }
 }
 
@@ -1,22 +1,75 @@
 use super::*;
 use lazy_static::lazy_static;
 use buddy_system_allocator::LockedFrameAllocator;
+use alloc::collections::BTreeMap;
+use spin::Mutex;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 lazy_static! {
     pub static ref FRAME_ALLOCATOR: LockedFrameAllocator = LockedFrameAllocator::new();
+    // Reference counts of the frames shared by multiple address spaces (for COW).
+    // A frame that isn't in it has only one owner.
+    static ref FRAME_REF_COUNTS: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
 }
 
+static TOTAL_FRAMES: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStats {
+    pub total: usize,
+    pub allocated: usize,
+    /// Frames with more than one owner.
+    pub shared: usize,
+}
+
+/// Add the frames [frame_start, frame_end). It's called for each memory region.
 pub fn init(frame_start: PPN, frame_end: PPN) {
     FRAME_ALLOCATOR.lock()
         .add_frame(frame_start.0, frame_end.0);
+    TOTAL_FRAMES.fetch_add(frame_end.0 - frame_start.0, Ordering::Relaxed);
 }
 
-pub fn frame_alloc() -> PPN {
-    let frame = FRAME_ALLOCATOR.lock().alloc(1).expect("We run out of physical page frame. QAQ");
+/// Return None if we run out of physical page frames.
+pub fn frame_alloc() -> Option<PPN> {
+    frame_alloc_contiguous(1)
+}
+
+/// Allocate `count` physically contiguous frames and return the first one.
+pub fn frame_alloc_contiguous(count: usize) -> Option<PPN> {
+    let frame = FRAME_ALLOCATOR.lock().alloc(count)?;
     // crate::println!("frame alloc: 0x{:x}", frame);
-    PPN(frame)
+    ALLOCATED_FRAMES.fetch_add(count, Ordering::Relaxed);
+    Some(PPN(frame))
+}
+
+pub fn frame_stats() -> FrameStats {
+    FrameStats {
+        total: TOTAL_FRAMES.load(Ordering::Relaxed),
+        allocated: ALLOCATED_FRAMES.load(Ordering::Relaxed),
+        shared: FRAME_REF_COUNTS.lock().len(),
+    }
+}
+
+/// Add an owner to the frame. It will only be freed after all the owners free it.
+pub fn frame_share(ppn: PPN) {
+    *FRAME_REF_COUNTS.lock().entry(ppn.0).or_insert(1) += 1;
+}
+
+pub fn frame_ref_count(ppn: PPN) -> usize {
+    FRAME_REF_COUNTS.lock().get(&ppn.0).copied().unwrap_or(1)
 }
 
 pub fn frame_free(ppn: PPN) {
+    let mut ref_counts = FRAME_REF_COUNTS.lock();
+    if let Some(count) = ref_counts.get_mut(&ppn.0) {
+        *count -= 1;
+        if *count == 1 {
+            ref_counts.remove(&ppn.0);
+        }
+        return;
+    }
+    drop(ref_counts);
     FRAME_ALLOCATOR.lock().dealloc(ppn.0, 1);
+    ALLOCATED_FRAMES.fetch_sub(1, Ordering::Relaxed);
 }
@@ -4,6 +4,7 @@ use bitflags::bitflags;
 use spin::Mutex;
 use crate::config::*;
 use riscv::register::satp;
+use core::ops::Range;
 
 const PAGE_TABLE_ENTRIES: usize = 1 << 9;
 // const PAGE_TABLE_SIZE: usize = PAGE_TABLE_ENTRIES * 8;
@@ -26,7 +27,9 @@ pub struct GlobalPtes {
     pub memory_pte: PageTableEntry,
 }
 
-pub fn init() {
+/// memory_pa is any pa in the identity mapped memory, whose mapping is shared by all the address
+/// spaces.
+pub fn init(memory_pa: PhysAddr) {
     let current_page_table = unsafe {
         PPN(satp::read().ppn()).as_page_table()
     };
@@ -35,11 +38,43 @@ pub fn init() {
     global_ptes.kernel_pte_index = KERNEL_BASE_ADDRESS.vpn().level(2);
     global_ptes.kernel_pte = *current_page_table.pte_of(KERNEL_BASE_ADDRESS, 2);
 
-    let memory_va = VirtAddr::new(QEMU_MEMORY_START);
+    let memory_va = VirtAddr::new(memory_pa.0);
     global_ptes.memory_pte_index = memory_va.vpn().level(2);
     global_ptes.memory_pte = *current_page_table.pte_of(memory_va, 2);
 }
 
+/// The physical memory reached by the identity mapping.
+pub fn identity_mapped_range() -> Range<usize> {
+    let start = GLOBAL_PTES.lock().memory_pte_index * IDENTITY_MAP_SIZE;
+    start..start + IDENTITY_MAP_SIZE
+}
+
+/// Map the MMIO region [pa, pa + size) into the kernel sub page table, which is shared by
+/// all the address spaces, and return the va of pa.
+/// It uses 2 MiB pages, so it's fine to remap an overlapped region.
+pub fn ioremap(pa: usize, size: usize) -> VirtAddr {
+    const TWO_MIB: usize = 2 * 1024 * 1024;
+    assert!(pa + size <= MMIO_MAX_PA, "MMIO region out of range");
+
+    let kernel_table = unsafe {
+        GLOBAL_PTES.lock().kernel_pte.as_page_table_mut()
+    };
+    let mut mapped_pa = pa / TWO_MIB * TWO_MIB;
+    while mapped_pa < pa + size {
+        let va = MMIO_BASE_VA.add(mapped_pa);
+        let pte = PageTableEntry::leaf(PhysAddr::new(mapped_pa).ppn(), PteFlags::kernel_mmio());
+        unsafe {
+            kernel_table.set_entry(va.vpn().level(1), pte);
+        }
+        mapped_pa += TWO_MIB;
+    }
+    unsafe {
+        riscv::asm::sfence_vma_all();
+    }
+
+    MMIO_BASE_VA.add(pa)
+}
+
 #[derive(Debug, Clone)]
 #[repr(C, align(4096))]
 pub struct PageTable(pub [PageTableEntry; PAGE_TABLE_ENTRIES]);
@@ -164,7 +199,12 @@ impl PageTableEntry {
     }
 
     pub fn set_flags(&mut self, flags: PteFlags) {
-        self.0.set_bits(0..=7, flags.bits());
+        // Including the RSW bits.
+        self.0.set_bits(0..=9, flags.bits());
+    }
+
+    pub fn flags(self) -> PteFlags {
+        PteFlags::from_bits_truncate(self.0.get_bits(0..=9))
     }
 
     pub fn ppn(self) -> PPN {
@@ -197,6 +237,10 @@ bitflags! {
         const A = 1 << 6;
         const D = 1 << 7;
 
+        // RSW bits for the software.
+        // A shared page that should be copied on write.
+        const COW = 1 << 8;
+
         // const USER_PAGE = 0b11111111;
         // // All except user.
         // const KERNEL_PAGE = 0b11101111;
@@ -209,6 +253,10 @@ impl PteFlags {
         // PteFlags::V | PteFlags::R | PteFlags::W | PteFlags::X | PteFlags::D | PteFlags::A
     }
 
+    pub fn kernel_mmio() -> Self {
+        PteFlags::V | PteFlags::R | PteFlags::W | PteFlags::G | PteFlags::D | PteFlags::A
+    }
+
     pub fn kernel_inner() -> Self {
         PteFlags::V
     }
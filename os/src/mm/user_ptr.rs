@@ -0,0 +1,181 @@
+//! Access user memory from the kernel.
+//!
+//! Everything goes through the page table of the given address space and the identity
+//! mapping of physical memory, so a bad user pointer can't crash the kernel.
+
+use super::address_space::{AddressSpace, MemAccess};
+use crate::config::PAGE_SIZE;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem::{size_of, MaybeUninit};
+
+/// Why an access to the user memory failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessError {
+    /// The user passed a pointer it isn't allowed to access.
+    BadAddress,
+    /// There is no frame left to fault the page in.
+    NoMemory,
+}
+
+pub use AccessError::{BadAddress, NoMemory};
+
+pub type Result<T> = core::result::Result<T, AccessError>;
+
+/// Call `f(pa, offset, len)` for each in-page piece of the user range [start, start + len),
+/// where offset is the offset of the piece in the range.
+fn for_each_user_piece(
+    addr_space: &mut AddressSpace,
+    start: usize,
+    len: usize,
+    access: MemAccess,
+    mut f: impl FnMut(usize, usize, usize),
+) -> Result<()> {
+    let end = start.checked_add(len).ok_or(BadAddress)?;
+    let mut va = start;
+    while va < end {
+        let piece_len = core::cmp::min(PAGE_SIZE - va % PAGE_SIZE, end - va);
+        let pa = addr_space.translate_user(va, access)?;
+        f(pa.0, va - start, piece_len);
+        va += piece_len;
+    }
+    Ok(())
+}
+
+/// Copy `src.len()` bytes to the user memory at `dst`.
+pub fn copy_to_user(addr_space: &mut AddressSpace, dst: usize, src: &[u8]) -> Result<()> {
+    for_each_user_piece(addr_space, dst, src.len(), MemAccess::Write, |pa, offset, len| unsafe {
+        core::ptr::copy_nonoverlapping(src[offset..].as_ptr(), pa as *mut u8, len);
+    })
+}
+
+/// Copy `dst.len()` bytes from the user memory at `src`.
+pub fn copy_from_user(addr_space: &mut AddressSpace, dst: &mut [u8], src: usize) -> Result<()> {
+    for_each_user_piece(addr_space, src, dst.len(), MemAccess::Read, |pa, offset, len| unsafe {
+        core::ptr::copy_nonoverlapping(pa as *const u8, dst[offset..].as_mut_ptr(), len);
+    })
+}
+
+/// Copy a nul-terminated string from the user memory at `src`, without the nul.
+/// Return None if there is no nul in the first `max_len` bytes.
+pub fn copy_cstr_from_user(addr_space: &mut AddressSpace, src: usize, max_len: usize) -> Result<Option<Vec<u8>>> {
+    let mut bytes = Vec::new();
+    let mut va = src;
+    while bytes.len() < max_len {
+        let piece_len = core::cmp::min(PAGE_SIZE - va % PAGE_SIZE, max_len - bytes.len());
+        let pa = addr_space.translate_user(va, MemAccess::Read)?;
+        let piece = unsafe { core::slice::from_raw_parts(pa.0 as *const u8, piece_len) };
+        match piece.iter().position(|&c| c == 0) {
+            Some(nul) => {
+                bytes.extend_from_slice(&piece[..nul]);
+                return Ok(Some(bytes));
+            }
+            None => bytes.extend_from_slice(piece),
+        }
+        va = va.checked_add(piece_len).ok_or(BadAddress)?;
+    }
+    Ok(None)
+}
+
+/// Types that can be read from the user memory as they are.
+///
+/// # Safety
+///
+/// Any bytes must make a valid value, e.g. integers, and arrays and `repr(C)` structs of
+/// them. Not bool, char, enums or references.
+pub unsafe trait Pod: Copy {}
+
+macro_rules! impl_pod {
+    ($($ty:ty),*) => {
+        $(unsafe impl Pod for $ty {})*
+    };
+}
+
+impl_pod!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+/// A typed pointer to the user memory.
+#[derive(Debug)]
+pub struct UserPtr<T> {
+    addr: usize,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T> Clone for UserPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for UserPtr<T> {}
+
+impl<T: Copy> UserPtr<T> {
+    pub fn new(addr: usize) -> Self {
+        Self {
+            addr,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn addr(self) -> usize {
+        self.addr
+    }
+
+    pub fn write(self, addr_space: &mut AddressSpace, val: &T) -> Result<()> {
+        let bytes = unsafe {
+            core::slice::from_raw_parts(val as *const T as *const u8, size_of::<T>())
+        };
+        copy_to_user(addr_space, self.addr, bytes)
+    }
+}
+
+impl<T: Pod> UserPtr<T> {
+    pub fn read(self, addr_space: &mut AddressSpace) -> Result<T> {
+        let mut val = MaybeUninit::<T>::uninit();
+        let bytes = unsafe {
+            core::slice::from_raw_parts_mut(val.as_mut_ptr() as *mut u8, size_of::<T>())
+        };
+        copy_from_user(addr_space, bytes, self.addr)?;
+        // Whatever the user put there is a valid T.
+        Ok(unsafe { val.assume_init() })
+    }
+}
+
+/// A user buffer [addr, addr + len).
+#[derive(Debug, Clone, Copy)]
+pub struct UserSlice {
+    addr: usize,
+    len: usize,
+}
+
+impl UserSlice {
+    pub fn new(addr: usize, len: usize) -> Self {
+        Self { addr, len }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Check that the whole buffer is accessible, faulting in all the pages.
+    pub fn check(&self, addr_space: &mut AddressSpace, access: MemAccess) -> Result<()> {
+        for_each_user_piece(addr_space, self.addr, self.len, access, |_, _, _| {})
+    }
+
+    /// Fill `buf` with the bytes at `offset` of the user buffer.
+    pub fn read(&self, addr_space: &mut AddressSpace, offset: usize, buf: &mut [u8]) -> Result<()> {
+        assert!(offset + buf.len() <= self.len, "read out of the user buffer");
+        copy_from_user(addr_space, buf, self.addr.checked_add(offset).ok_or(BadAddress)?)
+    }
+
+    /// Write `buf` to `offset` of the user buffer.
+    pub fn write(&self, addr_space: &mut AddressSpace, offset: usize, buf: &[u8]) -> Result<()> {
+        assert!(offset + buf.len() <= self.len, "write out of the user buffer");
+        copy_to_user(addr_space, self.addr.checked_add(offset).ok_or(BadAddress)?, buf)
+    }
+}
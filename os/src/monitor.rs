@@ -0,0 +1,212 @@
+//! A debugging monitor on the SBI console. It's entered by typing Ctrl-] twice on the console,
+//! and by a panic. The other harts keep running meanwhile.
+
+use crate::backtrace::print_backtrace;
+use crate::fdt::machine;
+use crate::fs::tty;
+use crate::mm::frame_allocator::frame_stats;
+use crate::mm::page_table::identity_mapped_range;
+use crate::mm::VirtAddr;
+use crate::sbi::{console_getchar, shutdown};
+use crate::smp::hart_id;
+use crate::task::{ProcessControlBlock, TaskStatus};
+use crate::{print, println};
+use alloc::sync::Weak;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+const MAGIC: &[u8] = b"\x1d\x1d";
+/// Chars of MAGIC typed so far.
+static MAGIC_MATCHED: AtomicUsize = AtomicUsize::new(0);
+
+/// One hart at a time. A hart panicking meanwhile waits here.
+static MONITOR: Mutex<()> = Mutex::new(());
+
+const MAX_LINE: usize = 80;
+const MAX_DUMP: usize = 4096;
+
+const HELP: &str = "\
+help                  show this
+ps                    list the processes and their threads
+translate <pid> <va>  translate the address in the address space of the process
+mem <pa> [len]        dump the physical memory
+frames                show the frame allocator stats
+bt                    print the backtrace of the monitor
+continue              leave the monitor, unless it's entered by a panic
+shutdown              shut down the machine";
+
+/// Feed a char received by the console tty. The monitor is entered once MAGIC is typed.
+/// The chars are still delivered to the reader.
+pub fn check_magic(c: u8) {
+    let matched = MAGIC_MATCHED.load(Ordering::Relaxed);
+    let matched = if c == MAGIC[matched] {
+        matched + 1
+    } else if c == MAGIC[0] {
+        1
+    } else {
+        0
+    };
+    if matched == MAGIC.len() {
+        MAGIC_MATCHED.store(0, Ordering::Relaxed);
+        enter(true);
+    } else {
+        MAGIC_MATCHED.store(matched, Ordering::Relaxed);
+    }
+}
+
+/// Run the commands until `continue`, which is refused if not `resumable`.
+/// Don't call it with any lock held, or the commands that need it hang.
+pub fn enter(resumable: bool) {
+    let _guard = MONITOR.lock();
+    set_tty_input(false);
+    println!("[monitor] on hart {}, type `help` for the commands", hart_id());
+    let mut line = [0; MAX_LINE];
+    loop {
+        print!("monitor> ");
+        let len = read_line(&mut line);
+        let line = core::str::from_utf8(&line[..len]).unwrap_or("");
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => continue,
+        };
+        let args = (words.next(), words.next());
+        match (command, args) {
+            ("help", _) => println!("{}", HELP),
+            ("ps", _) => list_processes(),
+            ("translate", (Some(pid), Some(va))) => match (parse_number(pid), parse_number(va)) {
+                (Some(pid), Some(va)) => translate(pid, va),
+                _ => println!("invalid number"),
+            },
+            ("mem", (Some(pa), len)) => match (parse_number(pa), len.map_or(Some(64), parse_number)) {
+                (Some(pa), Some(len)) => dump_memory(pa, len),
+                _ => println!("invalid number"),
+            },
+            ("frames", _) => {
+                let stats = frame_stats();
+                println!("total: {}, allocated: {}, shared: {}", stats.total, stats.allocated, stats.shared);
+            }
+            ("bt", _) => print_backtrace(),
+            ("continue", _) if resumable => {
+                set_tty_input(true);
+                return;
+            }
+            ("continue", _) => println!("can't continue after a panic"),
+            ("shutdown", _) => shutdown(),
+            _ => println!("unknown command or missing arguments, see `help`"),
+        }
+    }
+}
+
+/// The tty leaves the input to us meanwhile.
+fn set_tty_input(enabled: bool) {
+    if let Some(tty) = tty::get() {
+        tty.set_input_enabled(enabled);
+    }
+}
+
+/// Read a line with echo. Return its length, which is at most MAX_LINE.
+fn read_line(line: &mut [u8; MAX_LINE]) -> usize {
+    let mut len = 0;
+    loop {
+        // 0 or -1 if there is no char yet.
+        let c = console_getchar();
+        match c as u8 {
+            _ if c == 0 || c == usize::MAX => continue,
+            b'\r' | b'\n' => {
+                println!();
+                return len;
+            }
+            0x08 | 0x7f => {
+                if len > 0 {
+                    len -= 1;
+                    print!("\x08 \x08");
+                }
+            }
+            c if c.is_ascii_graphic() || c == b' ' => {
+                if len < MAX_LINE {
+                    line[len] = c;
+                    len += 1;
+                    print!("{}", c as char);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Decimal, or hex with 0x.
+fn parse_number(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn list_processes() {
+    for process in ProcessControlBlock::all() {
+        let inner = process.lock();
+        let ppid = inner.parent.as_ref().and_then(Weak::upgrade).map_or(0, |parent| parent.pid.0);
+        println!(
+            "pid {} ppid {}{} frames {}",
+            process.pid.0,
+            ppid,
+            if inner.is_zombie { " zombie" } else { "" },
+            inner.addr_space.resident_frames(),
+        );
+        drop(inner);
+        for thread in process.threads().iter().filter_map(Weak::upgrade) {
+            let thread_inner = thread.lock();
+            let status = match thread_inner.status {
+                TaskStatus::Ready => "ready",
+                TaskStatus::Running => "running",
+                TaskStatus::Blocked => "blocked",
+                TaskStatus::Zombie => "exited",
+            };
+            println!("    tid {} {} priority {}", thread.tid, status, thread_inner.sched.priority);
+        }
+    }
+}
+
+fn translate(pid: usize, va: usize) {
+    let process = match ProcessControlBlock::find(pid) {
+        Some(process) => process,
+        None => {
+            println!("no such process");
+            return;
+        }
+    };
+    let va = match VirtAddr::try_new(va) {
+        Some(va) => va,
+        None => {
+            println!("invalid address");
+            return;
+        }
+    };
+    match process.lock().addr_space.translate(va) {
+        Some(pa) => println!("{:#x} -> {:#x}", va.0, pa.0),
+        None => println!("{:#x} isn't mapped", va.0),
+    }
+}
+
+/// The physical memory is reached by the identity mapping.
+fn dump_memory(pa: usize, len: usize) {
+    let len = len.min(MAX_DUMP);
+    let mapped = identity_mapped_range();
+    let end = match machine().memory_containing(pa) {
+        Some(memory) => memory.end().min(mapped.end),
+        None => 0,
+    };
+    if pa < mapped.start || pa.saturating_add(len) > end {
+        println!("out of the identity mapped memory {:#x?}", mapped);
+        return;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(pa as *const u8, len) };
+    for (i, row) in bytes.chunks(16).enumerate() {
+        print!("{:#x}:", pa + i * 16);
+        for byte in row {
+            print!(" {:02x}", byte);
+        }
+        println!();
+    }
+}
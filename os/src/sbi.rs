@@ -12,6 +12,11 @@ mod constants {
     pub const SBI_REMOTE_SFENCE_VMA: usize = 6;
     pub const SBI_REMOTE_SFENCE_VMA_ASID: usize = 7;
     pub const SBI_SHUTDOWN: usize = 8;
+
+    // Hart state management extension, which isn't in the legacy ones.
+    pub const SBI_EXT_HSM: usize = 0x48534d;
+    pub const SBI_HSM_HART_START: usize = 0;
+    pub const SBI_HSM_HART_GET_STATUS: usize = 2;
 }
 
 #[inline(always)]
@@ -30,6 +35,23 @@ fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
     ret
 }
 
+/// Call a function of an SBI v0.2+ extension. Return (error, value).
+#[inline(always)]
+fn sbi_call_ext(eid: usize, fid: usize, arg0: usize, arg1: usize, arg2: usize) -> (isize, usize) {
+    let (error, value);
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("x10") arg0 => error,
+            inlateout("x11") arg1 => value,
+            in("x12") arg2,
+            in("x16") fid,
+            in("x17") eid,
+        );
+    }
+    (error, value)
+}
+
 pub fn console_getchar() -> usize {
     sbi_call(SBI_CONSOLE_GETCHAR, 0, 0, 0)
 }
@@ -46,3 +68,43 @@ pub fn shutdown() -> ! {
 pub fn set_timer(t: usize) {
     sbi_call(SBI_SET_TIMER, t, 0, 0);
 }
+
+/// Send an IPI to the harts in the mask, where bit i is for hart i.
+pub fn send_ipi(hart_mask: usize) {
+    // The legacy call takes the address of the mask.
+    sbi_call(SBI_SEND_IPI, &hart_mask as *const usize as usize, 0, 0);
+}
+
+/// Flush the instruction caches of the harts in the mask.
+pub fn remote_fence_i(hart_mask: usize) {
+    sbi_call(SBI_REMOTE_FENCE_I, &hart_mask as *const usize as usize, 0, 0);
+}
+
+/// Flush the TLB entries of [start, start + size) tagged with asid on the harts in the mask.
+pub fn remote_sfence_vma_asid(hart_mask: usize, start: usize, size: usize, asid: usize) {
+    let args = [&hart_mask as *const usize as usize, start, size, asid];
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("x10") args[0] => _,
+            in("x11") args[1],
+            in("x12") args[2],
+            in("x13") args[3],
+            in("x16") 0usize,
+            in("x17") SBI_REMOTE_SFENCE_VMA_ASID,
+        );
+    }
+}
+
+/// Start the hart at the physical address start_addr, with a0 = hartid and a1 = opaque.
+/// Return false if there is no such hart, or it's already started.
+pub fn hart_start(hartid: usize, start_addr: usize, opaque: usize) -> bool {
+    let (error, _) = sbi_call_ext(SBI_EXT_HSM, SBI_HSM_HART_START, hartid, start_addr, opaque);
+    error == 0
+}
+
+/// Return None if there is no such hart.
+pub fn hart_get_status(hartid: usize) -> Option<usize> {
+    let (error, status) = sbi_call_ext(SBI_EXT_HSM, SBI_HSM_HART_GET_STATUS, hartid, 0, 0);
+    (error == 0).then_some(status)
+}
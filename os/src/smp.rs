@@ -0,0 +1,69 @@
+//! Bring up the harts and keep track of them.
+
+use crate::config::{BOOT_STACK_SIZE, KERNEL_BASE_ADDRESS, MAX_HARTS, PAGE_SIZE};
+use crate::fdt;
+use crate::mm::frame_allocator::frame_alloc_contiguous;
+use crate::mm::PhysAddr;
+use crate::println;
+use crate::sbi;
+use core::arch::asm;
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+use riscv::register::satp;
+
+/// Bit i is set if hart i is running the kernel.
+static ONLINE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+/// The secondary harts turn on paging with it. It's read in _secondary_start.
+#[no_mangle]
+static mut SECONDARY_SATP: usize = 0;
+
+extern "C" {
+    fn _secondary_start();
+}
+
+/// The hart we are running on, which the kernel keeps in tp.
+pub fn hart_id() -> usize {
+    let hartid;
+    unsafe {
+        asm!("mv {}, tp", out(reg) hartid);
+    }
+    hartid
+}
+
+/// Mark the current hart online. It must be called before anything calls hart_id().
+pub fn init_hart(hartid: usize) {
+    assert!(hartid < MAX_HARTS, "hart {} is out of MAX_HARTS", hartid);
+    unsafe {
+        asm!("mv tp, {}", in(reg) hartid);
+    }
+    ONLINE_HARTS.fetch_or(1 << hartid, Ordering::SeqCst);
+}
+
+pub fn online_harts() -> usize {
+    ONLINE_HARTS.load(Ordering::SeqCst)
+}
+
+/// The online harts except the current one.
+pub fn other_harts() -> usize {
+    online_harts() & !(1 << hart_id())
+}
+
+/// Start the other harts of the device tree with the page table of the current hart.
+/// They start with paging off, so we need to know where the kernel is loaded.
+pub fn start_secondary_harts(kernel_pa: PhysAddr) {
+    unsafe {
+        SECONDARY_SATP = satp::read().bits();
+    }
+    fence(Ordering::SeqCst);
+
+    let start_pa = _secondary_start as usize - KERNEL_BASE_ADDRESS.0 + kernel_pa.0;
+    let harts = fdt::machine().harts;
+    for hartid in (0..MAX_HARTS).filter(|&hartid| hartid != hart_id() && harts & (1 << hartid) != 0) {
+        // Physical memory is identity mapped, so the pa works as the va.
+        let stack = frame_alloc_contiguous(BOOT_STACK_SIZE / PAGE_SIZE).expect("no memory for the boot stack");
+        let stack_top = stack.as_pa().0 + BOOT_STACK_SIZE;
+        if !sbi::hart_start(hartid, start_pa, stack_top) {
+            println!("[kernel] failed to start hart {}", hartid);
+        }
+    }
+}
@@ -0,0 +1,54 @@
+mod condvar;
+mod deadlock;
+mod mutex;
+mod semaphore;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::syscall::Errno;
+
+pub use condvar::Condvar;
+pub use deadlock::{DeadlockDetector, Resource};
+pub use mutex::{MutexBlocking, MutexSpin};
+pub use semaphore::Semaphore;
+
+/// A mutex for the user, held by the thread which locked it. It can be shared by the
+/// processes forked after it's created, but only the holder can unlock it.
+pub trait UserMutex: Send + Sync {
+    /// A signal interrupts it with EINTR.
+    fn lock(&self) -> Result<(), Errno>;
+    /// For relocking in a condvar wait, which can't fail.
+    fn lock_uninterruptible(&self);
+    /// Return false if the current thread doesn't hold it.
+    fn unlock(&self) -> bool;
+    /// Whether the current thread holds it.
+    fn is_held(&self) -> bool;
+}
+
+impl core::fmt::Debug for dyn UserMutex {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("UserMutex")
+    }
+}
+
+/// Synchronization objects of a process, indexed by their ids.
+/// They are shared with the children like the fds.
+#[derive(Debug, Default)]
+pub struct SyncTable {
+    pub mutexes: Vec<Arc<dyn UserMutex>>,
+    pub semaphores: Vec<Arc<Semaphore>>,
+    pub condvars: Vec<Arc<Condvar>>,
+    pub detector: DeadlockDetector,
+}
+
+impl SyncTable {
+    /// The objects are shared, but the detector only follows the forking thread.
+    pub fn fork(&self, tid: usize) -> Self {
+        Self {
+            mutexes: self.mutexes.clone(),
+            semaphores: self.semaphores.clone(),
+            condvars: self.condvars.clone(),
+            detector: self.detector.fork(tid),
+        }
+    }
+}
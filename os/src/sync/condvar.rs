@@ -0,0 +1,36 @@
+use super::UserMutex;
+use crate::syscall::Errno;
+use crate::task::WaitQueue;
+
+#[derive(Debug, Default)]
+pub struct Condvar {
+    wait_queue: WaitQueue,
+}
+
+impl Condvar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wake up one of the waiters, if any. It's not remembered if there is none.
+    pub fn signal(&self) {
+        self.wait_queue.wake_one();
+    }
+
+    /// Unlock the mutex and sleep until signaled, then lock the mutex again.
+    /// If a signal interrupts the sleep, it fails with EINTR, still with the mutex locked.
+    pub fn wait(&self, mutex: &dyn UserMutex) -> Result<(), Errno> {
+        let mut unlocked = false;
+        let ret = self.wait_queue.wait_until_interruptible(|| {
+            if unlocked {
+                return Some(());
+            }
+            // The queue is locked here, so a signal after the unlock can't be missed.
+            mutex.unlock();
+            unlocked = true;
+            None
+        });
+        mutex.lock_uninterruptible();
+        ret
+    }
+}
@@ -0,0 +1,115 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Resource {
+    Mutex(usize),
+    Semaphore(usize),
+}
+
+/// Deadlock detection over the sync objects of a process, with the banker's safety check.
+/// The need of a thread is what it's blocked on, so the check tells if the threads could
+/// still all finish after the request.
+///
+/// Only the threads of the process are known, so a resource shared with another process
+/// is taken as held by no one. Semaphores used for signaling look like deadlocks as well,
+/// since no one holds what the waiter needs.
+#[derive(Debug, Clone, Default)]
+pub struct DeadlockDetector {
+    /// Reject the unsafe requests. The resources are tracked even if it's disabled.
+    pub enabled: bool,
+    available: BTreeMap<Resource, usize>,
+    /// Keyed by (tid, resource).
+    allocation: BTreeMap<(usize, Resource), usize>,
+    need: BTreeMap<(usize, Resource), usize>,
+}
+
+impl DeadlockDetector {
+    pub fn add_resource(&mut self, resource: Resource, count: usize) {
+        self.available.insert(resource, count);
+    }
+
+    /// The child keeps the semaphores the forking thread holds, and nothing of the others.
+    /// The mutexes are still held by the thread of the parent.
+    pub fn fork(&self, tid: usize) -> Self {
+        Self {
+            enabled: self.enabled,
+            available: self.available.clone(),
+            allocation: self.allocation.iter()
+                .filter(|((t, resource), _)| *t == tid && matches!(resource, Resource::Semaphore(_)))
+                .map(|(key, count)| (*key, *count))
+                .collect(),
+            need: BTreeMap::new(),
+        }
+    }
+
+    /// Record that the thread is going to wait for the resource.
+    /// Return false if it could deadlock, and the request is dropped.
+    pub fn request(&mut self, tid: usize, resource: Resource) -> bool {
+        *self.need.entry((tid, resource)).or_insert(0) += 1;
+        if self.enabled && !self.is_safe() {
+            self.cancel(tid, resource);
+            return false;
+        }
+        true
+    }
+
+    /// The thread has got the resource it requested.
+    pub fn acquired(&mut self, tid: usize, resource: Resource) {
+        self.cancel(tid, resource);
+        *self.allocation.entry((tid, resource)).or_insert(0) += 1;
+        let available = self.available.entry(resource).or_insert(0);
+        *available = available.saturating_sub(1);
+    }
+
+    /// The resource is given back. A semaphore may be given back by a thread which didn't
+    /// take it, but a mutex only counts if the thread took it.
+    pub fn released(&mut self, tid: usize, resource: Resource) {
+        match self.allocation.get_mut(&(tid, resource)) {
+            Some(count) => {
+                *count -= 1;
+                if *count == 0 {
+                    self.allocation.remove(&(tid, resource));
+                }
+            }
+            None if matches!(resource, Resource::Mutex(_)) => return,
+            None => {}
+        }
+        *self.available.entry(resource).or_insert(0) += 1;
+    }
+
+    /// Drop a request, e.g. the wait is interrupted.
+    pub fn cancel(&mut self, tid: usize, resource: Resource) {
+        if let Some(count) = self.need.get_mut(&(tid, resource)) {
+            *count -= 1;
+            if *count == 0 {
+                self.need.remove(&(tid, resource));
+            }
+        }
+    }
+
+    /// Whether there is an order in which all the threads get what they need.
+    fn is_safe(&self) -> bool {
+        let mut work = self.available.clone();
+        let threads: BTreeSet<usize> = self.need.keys().chain(self.allocation.keys())
+            .map(|&(tid, _)| tid)
+            .collect();
+        let mut finished = BTreeSet::new();
+        loop {
+            let can_finish = threads.iter().copied().find(|tid| {
+                !finished.contains(tid)
+                    && self.need.iter()
+                        .filter(|((t, _), _)| t == tid)
+                        .all(|((_, resource), count)| work.get(resource).copied().unwrap_or(0) >= *count)
+            });
+            let Some(tid) = can_finish else {
+                break;
+            };
+            // It finishes and gives back everything.
+            for ((_, resource), count) in self.allocation.iter().filter(|((t, _), _)| *t == tid) {
+                *work.entry(*resource).or_insert(0) += count;
+            }
+            finished.insert(tid);
+        }
+        finished.len() == threads.len()
+    }
+}
@@ -0,0 +1,111 @@
+use super::UserMutex;
+use crate::syscall::Errno;
+use crate::task::signal::has_signal_to_deliver;
+use crate::task::{current_process, current_task, run_next_task, WaitQueue};
+use spin::Mutex;
+
+/// The thread holding a mutex, as (pid, tid), since the mutex may be shared by processes.
+type Owner = (usize, usize);
+
+fn current_owner() -> Owner {
+    let task = current_task();
+    (task.process.pid.0, task.tid)
+}
+
+/// Take the mutex for the current thread if it's free.
+fn try_take(owner: &Mutex<Option<Owner>>) -> Option<()> {
+    let mut owner = owner.lock();
+    if owner.is_some() {
+        None
+    } else {
+        *owner = Some(current_owner());
+        Some(())
+    }
+}
+
+/// Free the mutex. Return false if the current thread doesn't hold it.
+fn give_back(owner: &Mutex<Option<Owner>>) -> bool {
+    let mut owner = owner.lock();
+    if *owner != Some(current_owner()) {
+        return false;
+    }
+    *owner = None;
+    true
+}
+
+/// Yield until the mutex is free.
+#[derive(Debug, Default)]
+pub struct MutexSpin {
+    owner: Mutex<Option<Owner>>,
+}
+
+impl MutexSpin {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock_with(&self, interruptible: bool) -> Result<(), Errno> {
+        while try_take(&self.owner).is_none() {
+            if interruptible && has_signal_to_deliver(&current_process()) {
+                return Err(Errno::EINTR);
+            }
+            run_next_task();
+        }
+        Ok(())
+    }
+}
+
+impl UserMutex for MutexSpin {
+    fn lock(&self) -> Result<(), Errno> {
+        self.lock_with(true)
+    }
+
+    fn lock_uninterruptible(&self) {
+        // It never fails.
+        let _ = self.lock_with(false);
+    }
+
+    fn unlock(&self) -> bool {
+        give_back(&self.owner)
+    }
+
+    fn is_held(&self) -> bool {
+        *self.owner.lock() == Some(current_owner())
+    }
+}
+
+/// Sleep until the mutex is free.
+#[derive(Debug, Default)]
+pub struct MutexBlocking {
+    owner: Mutex<Option<Owner>>,
+    wait_queue: WaitQueue,
+}
+
+impl MutexBlocking {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl UserMutex for MutexBlocking {
+    fn lock(&self) -> Result<(), Errno> {
+        self.wait_queue.wait_until_interruptible(|| try_take(&self.owner))
+    }
+
+    fn lock_uninterruptible(&self) {
+        self.wait_queue.wait_until(|| try_take(&self.owner));
+    }
+
+    fn unlock(&self) -> bool {
+        if !give_back(&self.owner) {
+            return false;
+        }
+        // The woken one may still lose the race to a new comer, and sleep again.
+        self.wait_queue.wake_one();
+        true
+    }
+
+    fn is_held(&self) -> bool {
+        *self.owner.lock() == Some(current_owner())
+    }
+}
@@ -0,0 +1,38 @@
+use crate::syscall::Errno;
+use crate::task::WaitQueue;
+use spin::Mutex;
+
+/// A counting semaphore.
+#[derive(Debug, Default)]
+pub struct Semaphore {
+    count: Mutex<usize>,
+    wait_queue: WaitQueue,
+}
+
+impl Semaphore {
+    pub fn new(count: usize) -> Self {
+        Self {
+            count: Mutex::new(count),
+            wait_queue: WaitQueue::new(),
+        }
+    }
+
+    pub fn up(&self) {
+        *self.count.lock() += 1;
+        self.wait_queue.wake_one();
+    }
+
+    /// Sleep until the count is positive, and decrease it.
+    /// It fails with EINTR if a signal comes first, and the count is left alone.
+    pub fn down(&self) -> Result<(), Errno> {
+        self.wait_queue.wait_until_interruptible(|| {
+            let mut count = self.count.lock();
+            if *count == 0 {
+                None
+            } else {
+                *count -= 1;
+                Some(())
+            }
+        })
+    }
+}
@@ -1,29 +1,62 @@
+mod fs;
+mod errno;
+mod signal;
+mod thread;
+mod sync;
+mod rusage;
+mod proc;
+
 use alloc::sync::Arc;
 
-use crate::print;
 // use crate::println;
 use crate::task::run_next_task;
 use crate::task::exit_and_run_next;
 use crate::task::record_syscall;
+use crate::task::sleep_until;
 use crate::task::TaskStatus;
 use crate::task::TaskControlBlock;
 use crate::time;
+use crate::smp;
 use crate::mm::*;
-use crate::task::PROCESSOR;
-use crate::sbi::console_getchar;
-use core::ffi::CStr;
-use crate::task::get_app_data;
+use crate::mm::address_space::{AddressSpace, Vma, VmaKind};
+use crate::mm::user_ptr::{copy_cstr_from_user, copy_from_user, Pod, UserPtr};
+use crate::config::{PAGE_SIZE, USER_SPACE_END, USER_STACK_REGION_END, USER_STACK_REGION_START};
+use crate::task::{current_process, current_task};
+use crate::task::load_app;
+use alloc::string::String;
 // use crate::task::TaskControlBlock;
 use crate::task::TASK_MANAGER;
+use fs::*;
+use signal::*;
+use thread::*;
+use sync::*;
+use rusage::*;
+use proc::*;
+pub use errno::{Errno, SysResult};
 
 pub const FD_STDIN: usize = 0;
 pub const FD_STDOUT: usize = 1;
 pub const MAX_SYSCALL_NUM: usize = 500;
 
+/// Max length of the paths from the user, including the nul.
+pub const PATH_MAX: usize = 256;
+
+pub const SYSCALL_DUP: usize = 23;
+pub const SYSCALL_IOCTL: usize = 29;
+pub const SYSCALL_DUP3: usize = 24;
+pub const SYSCALL_OPENAT: usize = 56;
+pub const SYSCALL_CLOSE: usize = 57;
+pub const SYSCALL_PIPE2: usize = 59;
 pub const SYSCALL_READ: usize = 63;
 pub const SYSCALL_WRITE: usize = 64;
 pub const SYSCALL_EXIT: usize = 93;
+pub const SYSCALL_NANOSLEEP: usize = 101;
 pub const SYSCALL_YIELD: usize = 124;
+pub const SYSCALL_KILL: usize = 129;
+pub const SYSCALL_SIGACTION: usize = 134;
+pub const SYSCALL_SIGPROCMASK: usize = 135;
+pub const SYSCALL_SIGRETURN: usize = 139;
+pub const SYSCALL_GETRUSAGE: usize = 165;
 pub const SYSCALL_GET_TIME: usize = 169;
 pub const SYSCALL_FORK: usize = 220;
 pub const SYSCALL_EXEC: usize = 221;
@@ -33,294 +66,344 @@ pub const SYSCALL_SPAWN: usize = 400;
 pub const SYSCALL_MUNMAP: usize = 215;
 pub const SYSCALL_MMAP: usize = 222;
 pub const SYSCALL_TASK_INFO: usize = 410;
+pub const SYSCALL_ENABLE_DEADLOCK_DETECT: usize = 469;
 pub const SYSCALL_SET_PRIORITY: usize = 140;
+pub const SYSCALL_SCHED_SETAFFINITY: usize = 122;
+pub const SYSCALL_THREAD_CREATE: usize = 1000;
+pub const SYSCALL_GETTID: usize = 1001;
+pub const SYSCALL_WAITTID: usize = 1002;
+pub const SYSCALL_MUTEX_CREATE: usize = 1010;
+pub const SYSCALL_MUTEX_LOCK: usize = 1011;
+pub const SYSCALL_MUTEX_UNLOCK: usize = 1012;
+pub const SYSCALL_SEMAPHORE_CREATE: usize = 1020;
+pub const SYSCALL_SEMAPHORE_UP: usize = 1021;
+pub const SYSCALL_SEMAPHORE_DOWN: usize = 1022;
+pub const SYSCALL_CONDVAR_CREATE: usize = 1030;
+pub const SYSCALL_CONDVAR_SIGNAL: usize = 1031;
+pub const SYSCALL_CONDVAR_WAIT: usize = 1032;
+pub const SYSCALL_GET_PROCESS_LIST: usize = 1100;
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Default)]
 struct TimeVal {
     pub sec: usize,
     pub usec: usize,
 }
 
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct TimeSpec {
+    pub sec: isize,
+    pub nsec: isize,
+}
+
+unsafe impl Pod for TimeSpec {}
+
 #[allow(dead_code)]
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 struct TaskInfo {
     pub status: TaskStatus,
     pub syscall_times: [u32; MAX_SYSCALL_NUM],
     pub time: usize
 }
 
-// TODO:
-// check all user-passed args to ensure that the user has enough permission
-// to access that memory address.
+/// Run f with the address space of the current process, which is where the user pointers
+/// point to. Don't call it with the current process locked.
+fn with_user_space<R>(f: impl FnOnce(&mut AddressSpace) -> R) -> R {
+    let current_process = current_process();
+    let mut process_inner = current_process.lock();
+    f(&mut process_inner.addr_space)
+}
 
+fn copy_path_from_user(ptr: usize) -> Result<String, Errno> {
+    let bytes = with_user_space(|addr_space| copy_cstr_from_user(addr_space, ptr, PATH_MAX))?
+        .ok_or(Errno::ENAMETOOLONG)?;
+    String::from_utf8(bytes).map_err(|_| Errno::EINVAL)
+}
+
+/// Return the result, or `-errno` on failure.
 pub fn syscall(id: usize, args: [usize; 3]) -> isize {
     record_syscall(id);
 
-    match id {
-        SYSCALL_READ => {
-            let fd = args[0];
-            let buffer = args[1] as *mut u8;
-            let len = args[2];
-
-            if fd != FD_STDIN {
-                panic!("unsupported fd in syscall read");
-            }
-            assert_eq!(len, 1, "Only support len = 1 in sys_read!");
-            let mut c: usize;
-            loop {
-                c = console_getchar();
-                if c == 0 {
-                    run_next_task();
-                    continue;
-                } else {
-                    break;
-                }
-            }
-            // We don't need to translate the buffer, since we didn't switch satp
-            unsafe {
-                buffer.write_volatile(c as u8);
-            }
+    match dispatch(id, args) {
+        Ok(ret) => ret,
+        Err(errno) => -(errno as isize),
+    }
+}
 
-            1
-        }
+fn dispatch(id: usize, args: [usize; 3]) -> SysResult {
+    match id {
+        SYSCALL_OPENAT => sys_openat(args[0] as isize, args[1], args[2] as u32),
+        SYSCALL_CLOSE => sys_close(args[0]),
+        SYSCALL_PIPE2 => sys_pipe2(args[0], args[1] as u32),
+        SYSCALL_DUP => sys_dup(args[0]),
+        SYSCALL_DUP3 => sys_dup3(args[0], args[1], args[2] as u32),
+        SYSCALL_IOCTL => sys_ioctl(args[0], args[1], args[2]),
+        SYSCALL_READ => sys_read(args[0], args[1], args[2]),
         SYSCALL_EXIT => {
             let exit_code = args[0] as i32;
             exit_and_run_next(exit_code);
-            0
+            Ok(0)
         }
-        SYSCALL_WRITE => {
-            let fd = args[0];
-            if fd != FD_STDOUT {
-                panic!("unsupported fd in syscall write");
+        SYSCALL_WRITE => sys_write(args[0], args[1], args[2]),
+        SYSCALL_NANOSLEEP => {
+            let req = with_user_space(|addr_space| UserPtr::<TimeSpec>::new(args[0]).read(addr_space))?;
+            if req.sec < 0 || !(0..time::NANO_PER_SEC as isize).contains(&req.nsec) {
+                return Err(Errno::EINVAL);
             }
-
-            let buffer_ptr = args[1];
-            let buffer_size = args[2];
-            let buffer = unsafe { core::slice::from_raw_parts(buffer_ptr as *const u8, args[2]) };
-
-            print!(
-                "{}",
-                core::str::from_utf8(buffer).expect("try to print non-utf8 str")
-            );
-            buffer_size as isize
+            let clocks = (req.sec as usize).saturating_mul(time::clocks_per_sec())
+                .saturating_add(req.nsec as usize * time::clocks_per_milli_sec() / time::NANO_PER_MILLI_SEC);
+            let deadline = time::get_time().saturating_add(clocks);
+            if let Err(err) = sleep_until(deadline) {
+                // Like Linux, tell how much is left through args[1], if it isn't null.
+                if args[1] != 0 {
+                    let left = deadline.saturating_sub(time::get_time());
+                    let rem = TimeSpec {
+                        sec: (left / time::clocks_per_sec()) as isize,
+                        nsec: (left % time::clocks_per_sec() * time::NANO_PER_SEC / time::clocks_per_sec()) as isize,
+                    };
+                    with_user_space(|addr_space| UserPtr::<TimeSpec>::new(args[1]).write(addr_space, &rem))?;
+                }
+                return Err(err);
+            }
+            Ok(0)
         }
         SYSCALL_YIELD => {
             // crate::println!("\nyield..");
             run_next_task();
-            0
+            Ok(0)
         }
+        SYSCALL_KILL => sys_kill(args[0] as isize, args[1]),
+        SYSCALL_SIGACTION => sys_sigaction(args[0], args[1], args[2]),
+        SYSCALL_SIGPROCMASK => sys_sigprocmask(args[0], args[1], args[2]),
+        SYSCALL_SIGRETURN => sys_sigreturn(),
         SYSCALL_EXEC => {
-            let current_task = PROCESSOR.lock().current().expect("missing current").clone();
+            let elf_name = copy_path_from_user(args[0])?;
+            let elf_data = load_app(&elf_name)?;
+            // exec doesn't return on success.
+            drop(elf_name);
 
-            let elf_name: &'static str = unsafe {
-                CStr::from_ptr(args[0] as *const i8).to_str().expect("invalid app name")
-            };
-            let elf_data = match get_app_data(elf_name) {
-                Some(elf_data) => elf_data,
-                None => return -1,
-            };
-            current_task.exec(elf_data);
-
-            unreachable!()
+            let current_task = current_task();
+            Err(current_task.exec(elf_data))
         }
         SYSCALL_FORK => {
-            let current_task = PROCESSOR.lock().current().expect("missing current").clone();
-            current_task.fork() as isize
+            let current_task = current_task();
+            Ok(current_task.fork()? as isize)
         }
         SYSCALL_GETPID => {
-            let current_task = PROCESSOR.lock().current().expect("missing current").clone();
-            current_task.pid.0 as isize
+            let current_process = current_process();
+            Ok(current_process.pid.0 as isize)
         }
         SYSCALL_WAITPID => {
             let pid = args[0] as isize; 
-            let exit_code_ptr = args[1] as *mut i32;
-
-            let current_task = PROCESSOR.lock().current().expect("missing current").clone();
-            let mut current_inner = current_task.lock();
-
-            let mut ret = -1;
-            let mut found: Option<usize> = None;
-            for (idx, ch) in current_inner.children.iter().enumerate() {
-                if pid == -1 || ch.pid.0 == pid as usize {
-                    ret = -2;
-
-                    if ch.is_zombie() {
-                        ret = ch.pid.0 as isize;
-                        found = Some(idx);
-                        break;
+            let exit_code_ptr = UserPtr::<i32>::new(args[1]);
+
+            let current_process = current_process();
+            // Sleep until a child exits, or a signal comes.
+            current_process.child_exit.wait_until_interruptible(|| {
+                let mut process_inner = current_process.lock();
+
+                // No such child.
+                let mut ret = Err(Errno::ECHILD);
+                let mut found: Option<usize> = None;
+                for (idx, ch) in process_inner.children.iter().enumerate() {
+                    if pid == -1 || ch.pid.0 == pid as usize {
+                        // Not exited yet.
+                        ret = Err(Errno::EAGAIN);
+
+                        if ch.is_zombie() {
+                            ret = Ok(ch.pid.0 as isize);
+                            found = Some(idx);
+                            break;
+                        }
                     }
                 }
-            }
-            if let Some(found) = found {
-                let exit_child = current_inner.children.remove(found);
-                // crate::println!("strong: {} weak: {}", Arc::strong_count(&exit_child), Arc::weak_count(&exit_child));
-                unsafe {
-                    *exit_code_ptr = exit_child.lock().exit_code;
+                if let Some(found) = found {
+                    // A null exit_code_ptr means the caller doesn't care about it.
+                    if exit_code_ptr.addr() != 0 {
+                        let exit_code = process_inner.children[found].lock().exit_code;
+                        if let Err(err) = exit_code_ptr.write(&mut process_inner.addr_space, &exit_code) {
+                            return Some(Err(err.into()));
+                        }
+                    }
+                    let exit_child = process_inner.children.remove(found);
+                    let child_usage = exit_child.usage().merge(&exit_child.children_usage.snapshot());
+                    current_process.children_usage.accumulate(&child_usage);
+                    // crate::println!("strong: {} weak: {}", Arc::strong_count(&exit_child), Arc::weak_count(&exit_child));
                 }
-            }
 
-            ret
+                (ret != Err(Errno::EAGAIN)).then_some(ret)
+            })?
         }
         SYSCALL_SPAWN => {
-            let current_task = PROCESSOR.lock().current().expect("missing current").clone();
-            let mut current_inner = current_task.lock();
-
-            let elf_name: &'static str = unsafe {
-                CStr::from_ptr(args[0] as *const i8).to_str().expect("invalid app name")
-            };
-            let elf_data = match get_app_data(elf_name) {
-                Some(elf_data) => elf_data,
-                None => return -1,
-            };
+            let elf_name = copy_path_from_user(args[0])?;
+            let elf_data = load_app(&elf_name)?;
 
-            let child_task = TaskControlBlock::load_from_elf(elf_data, Some(Arc::downgrade(&current_task)));
-            current_inner.children.push(child_task.clone());
+            let current_process = current_process();
+            // Loading takes a while, so the parent is only locked to adopt the child.
+            let child_task = TaskControlBlock::load_from_elf(&elf_data, Some(Arc::downgrade(&current_process)))?;
+            current_process.lock().children.push(Arc::clone(&child_task.process));
 
-            let ret = child_task.pid.0 as isize;
+            let ret = child_task.process.pid.0 as isize;
 
-            drop(current_inner);
-            drop(current_task);
+            drop(current_process);
             TASK_MANAGER.lock().add(child_task);
 
-            ret
+            Ok(ret)
         }
         SYSCALL_GET_TIME => {
             let t = time::get_time();
-            let time_val = unsafe { &mut *(args[0] as *mut TimeVal)};
-            time_val.sec = t / time::CLOCKS_PER_SEC;
-            // This funny formula is to work around percision issue of the CLOCKS_PER_MICRO_SEC,
-            // which should be 12.5 instead of 12.
-            time_val.usec = t % time::CLOCKS_PER_SEC / time::CLOCKS_PER_MILLI_SEC * 1000;
-
-            0
+            let clocks_per_sec = time::clocks_per_sec();
+            let time_val = TimeVal {
+                sec: t / clocks_per_sec,
+                // The clocks per microsecond may not be an integer, e.g. 12.5.
+                usec: t % clocks_per_sec * 1_000_000 / clocks_per_sec,
+            };
+            with_user_space(|addr_space| UserPtr::new(args[0]).write(addr_space, &time_val))?;
+            Ok(0)
         }
         SYSCALL_MMAP => {
-            // TODO: check start
-            let start = VirtAddr::new(args[0]);
+            let start = VirtAddr::try_new(args[0]).ok_or(Errno::EINVAL)?;
             let len = args[1];
             let prot = args[2];
 
-            if start.offset() != 0 {
-                return -1;
-            }
-            if len == 0 {
-                return 0;
+            if start.offset() != 0 || len == 0 {
+                return Err(Errno::EINVAL);
             }
             if prot & !7 != 0 || prot & 7 == 0 {
-                return -1;
+                return Err(Errno::EINVAL);
+            }
+            // Only the lower half is for the user, except the stacks.
+            let end = match start.0.checked_add(len) {
+                Some(end) if end <= USER_SPACE_END => end,
+                _ => return Err(Errno::EINVAL),
+            };
+            if start.0 < USER_STACK_REGION_END.0 && USER_STACK_REGION_START.0 < end {
+                return Err(Errno::EINVAL);
             }
 
-            let processor = PROCESSOR.lock();
-            let current_task = processor.current().expect("missing current");
-            let mut current_inner = current_task.lock();
-            let addr_space = &mut current_inner.addr_space;
+            let current_process = current_process();
+            let mut process_inner = current_process.lock();
+            let addr_space = &mut process_inner.addr_space;
 
+            // Global mappings aren't in the vmas, check them as well.
             let mut checked_len = 0;
             while checked_len < len {
                 let checked_va = VirtAddr::new(start.0 + checked_len);
                 if addr_space.translate(checked_va).is_some() {
-                    return -1;
+                    return Err(Errno::EEXIST);
                 }
-                checked_len += 4096;
+                checked_len += PAGE_SIZE;
             }
 
-            let mut flags_at_level = [
-                PteFlags::user_leaf(),
-                PteFlags::user_inner(),
-                PteFlags::user_inner(),
-            ];
+            let mut flags = PteFlags::user_leaf();
             if prot & 0b1 != 0 {
-                flags_at_level[0] |= PteFlags::R;
+                flags |= PteFlags::R;
             }
             if prot & 0b10 != 0 {
                 // W imply R
-                flags_at_level[0] |= PteFlags::R | PteFlags::W;
+                flags |= PteFlags::R | PteFlags::W;
             }
             if prot & 0b100 != 0 {
-                flags_at_level[0] |= PteFlags::X;
+                flags |= PteFlags::X;
             }
-            let mut mapped_len = 0;
-            while mapped_len < len {
-                let mapped_va = VirtAddr::new(start.0 + mapped_len);
-                let frame = addr_space.alloc_frame();
-                addr_space.build_mapping(mapped_va.vpn(), frame, flags_at_level);
-                mapped_len += 4096;
+            let vma = Vma::new(start.vpn(), VPN((end + PAGE_SIZE - 1) / PAGE_SIZE), flags, VmaKind::Mmap);
+            // Frames are allocated on the first touch.
+            if !addr_space.add_vma(vma) {
+                return Err(Errno::EEXIST);
             }
 
-            unsafe {
-                riscv::asm::sfence_vma_all();
-            }
-
-            0
+            Ok(0)
         }
         SYSCALL_MUNMAP => {
-            let start = VirtAddr::new(args[0]);
+            let start = VirtAddr::try_new(args[0]).ok_or(Errno::EINVAL)?;
             let len = args[1];
-            if start.offset() != 0 {
-                return -1;
+            if start.offset() != 0 || len == 0 {
+                return Err(Errno::EINVAL);
             }
 
-            let processor = PROCESSOR.lock();
-            let current_task = processor.current().expect("missing current");
-            let mut current_inner = current_task.lock();
-            let addr_space = &mut current_inner.addr_space;
-
-            let mut checked_len = 0;
-            while checked_len < len {
-                let checked_va = VirtAddr::new(start.0 + checked_len);
-                if addr_space.translate(checked_va).is_none() {
-                    return -1;
-                }
-                checked_len += 4096;
-            }
-            let flags_at_level = [
-                PteFlags::empty(),
-                PteFlags::user_inner(),
-                PteFlags::user_inner(),
-            ];
-
-            let mut unmapped_len = 0;
-            while unmapped_len < len {
-                // TODO: free frame
-                let unmapped_va = VirtAddr::new(start.0 + unmapped_len);
-                let frame = addr_space.translate(unmapped_va).unwrap().ppn();
-                addr_space.build_mapping(unmapped_va.vpn(), frame, flags_at_level);
-                unmapped_len += 4096;
-            }
+            let current_process = current_process();
+            let mut process_inner = current_process.lock();
+            let addr_space = &mut process_inner.addr_space;
 
-            unsafe {
-                riscv::asm::sfence_vma_all();
+            // Only the lower half is for the user.
+            let end = match start.0.checked_add(len) {
+                Some(end) if end <= USER_SPACE_END => end,
+                _ => return Err(Errno::EINVAL),
+            };
+            if !addr_space.munmap(start.vpn(), VPN((end + PAGE_SIZE - 1) / PAGE_SIZE)) {
+                return Err(Errno::EINVAL);
             }
 
-            0
+            Ok(0)
         }
         SYSCALL_TASK_INFO => {
-            let task_info = unsafe { &mut *(args[0] as *mut TaskInfo) };
-
-            let processor = PROCESSOR.lock();
-            let current_task = processor.current().expect("missing current");
+            let current_task = current_task();
             let current_inner = current_task.lock();
             let stat = &current_inner.stats;
 
-            task_info.status = current_inner.status;
-            task_info.syscall_times = stat.syscall_times;
-            task_info.time = stat.real_time() / time::CLOCKS_PER_MILLI_SEC;
-
-            0
+            let task_info = TaskInfo {
+                status: current_inner.status,
+                syscall_times: stat.syscall_times,
+                time: stat.real_time() / time::clocks_per_milli_sec(),
+            };
+            drop(current_inner);
+            with_user_space(|addr_space| UserPtr::new(args[0]).write(addr_space, &task_info))?;
+            Ok(0)
         }
         SYSCALL_SET_PRIORITY => {
             let priority = args[0] as isize;
             if priority < 2 {
-                return -1;
+                return Err(Errno::EINVAL);
             }
 
-            let current_task = PROCESSOR.lock().current().expect("missing current").clone();
-            current_task.lock().priority = priority as u64;
+            let current_task = current_task();
+            TASK_MANAGER.lock().on_priority_change(&current_task, priority as u64);
 
-            priority
+            Ok(priority)
+        }
+        // Bit i of args[0] allows the current thread to run on hart i.
+        // It isn't inherited by the threads and processes created later.
+        SYSCALL_SCHED_SETAFFINITY => {
+            // The arguments of Linux: (pid, cpusetsize, mask). Only the calling thread
+            // (pid 0) can be set, since the tids are per process here.
+            let (pid, mask_size, mask_ptr) = (args[0], args[1], args[2]);
+            if pid != 0 {
+                return Err(Errno::ESRCH);
+            }
+            // Bits beyond a usize are for harts that can't exist, which Linux ignores too.
+            let mut mask = [0; core::mem::size_of::<usize>()];
+            let mask_len = mask_size.min(mask.len());
+            with_user_space(|addr_space| copy_from_user(addr_space, &mut mask[..mask_len], mask_ptr))?;
+            let harts = usize::from_le_bytes(mask);
+            if harts & smp::online_harts() == 0 {
+                return Err(Errno::EINVAL);
+            }
+
+            let current_task = current_task();
+            current_task.lock().sched.harts = harts;
+            // Move to an allowed hart.
+            if harts & (1 << smp::hart_id()) == 0 {
+                run_next_task();
+            }
+            Ok(0)
         }
-        unknown => panic!("unknown syscall `{}`", unknown),
+        SYSCALL_GET_PROCESS_LIST => sys_get_process_list(args[0], args[1]),
+        SYSCALL_GETRUSAGE => sys_getrusage(args[0] as isize, args[1]),
+        SYSCALL_THREAD_CREATE => sys_thread_create(args[0], args[1]),
+        SYSCALL_GETTID => sys_gettid(),
+        SYSCALL_WAITTID => sys_waittid(args[0], args[1]),
+        SYSCALL_MUTEX_CREATE => sys_mutex_create(args[0] != 0),
+        SYSCALL_MUTEX_LOCK => sys_mutex_lock(args[0]),
+        SYSCALL_MUTEX_UNLOCK => sys_mutex_unlock(args[0]),
+        SYSCALL_SEMAPHORE_CREATE => sys_semaphore_create(args[0]),
+        SYSCALL_SEMAPHORE_UP => sys_semaphore_up(args[0]),
+        SYSCALL_SEMAPHORE_DOWN => sys_semaphore_down(args[0]),
+        SYSCALL_CONDVAR_CREATE => sys_condvar_create(),
+        SYSCALL_CONDVAR_SIGNAL => sys_condvar_signal(args[0]),
+        SYSCALL_CONDVAR_WAIT => sys_condvar_wait(args[0], args[1]),
+        SYSCALL_ENABLE_DEADLOCK_DETECT => sys_enable_deadlock_detect(args[0]),
+        _ => Err(Errno::ENOSYS),
     }
 }
@@ -0,0 +1,60 @@
+use crate::mm::user_ptr::AccessError;
+
+/// Error numbers of the syscalls. The values are the same as Linux.
+/// A failed syscall returns `-errno`.
+#[allow(clippy::upper_case_acronyms)]
+#[repr(isize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Errno {
+    /// Operation not permitted
+    EPERM = 1,
+    /// No such file or directory
+    ENOENT = 2,
+    /// No such process
+    ESRCH = 3,
+    /// Interrupted system call
+    EINTR = 4,
+    /// Exec format error
+    ENOEXEC = 8,
+    /// Bad file number
+    EBADF = 9,
+    /// No child processes
+    ECHILD = 10,
+    /// Try again
+    EAGAIN = 11,
+    /// Out of memory
+    ENOMEM = 12,
+    /// Bad address
+    EFAULT = 14,
+    /// Device or resource busy
+    EBUSY = 16,
+    /// File exists
+    EEXIST = 17,
+    /// Invalid argument
+    EINVAL = 22,
+    /// Too many open files
+    EMFILE = 24,
+    /// Not a typewriter
+    ENOTTY = 25,
+    /// No space left on device
+    ENOSPC = 28,
+    /// Broken pipe
+    EPIPE = 32,
+    /// Resource deadlock would occur
+    EDEADLK = 35,
+    /// File name too long
+    ENAMETOOLONG = 36,
+    /// Invalid system call number
+    ENOSYS = 38,
+}
+
+pub type SysResult = Result<isize, Errno>;
+
+impl From<AccessError> for Errno {
+    fn from(err: AccessError) -> Self {
+        match err {
+            AccessError::BadAddress => Self::EFAULT,
+            AccessError::NoMemory => Self::ENOMEM,
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use alloc::sync::Arc;
+use alloc::vec;
+use crate::config::PAGE_SIZE;
+use crate::fs::{alloc_fd, make_pipe, open_file, File, OpenFlags, FD_LIMIT};
+use crate::fs::tty::{tty, Termios};
+use crate::task::current_process;
+use crate::mm::address_space::MemAccess;
+use crate::mm::user_ptr::{UserPtr, UserSlice};
+use super::{copy_path_from_user, with_user_space, Errno, SysResult};
+
+/// Special value of dirfd, which means the current working directory.
+/// We don't have cwd yet, so it's always the root directory.
+pub const AT_FDCWD: isize = -100;
+
+// Requests of ioctl, the same as Linux.
+pub const TCGETS: usize = 0x5401;
+pub const TCSETS: usize = 0x5402;
+
+fn get_file(fd: usize) -> Option<Arc<dyn File>> {
+    let current_process = current_process();
+    let process_inner = current_process.lock();
+    process_inner.fd_table.get(fd).cloned().flatten()
+}
+
+pub fn sys_openat(dirfd: isize, path: usize, flags: u32) -> SysResult {
+    let path = copy_path_from_user(path)?;
+    if dirfd != AT_FDCWD && !path.starts_with('/') {
+        // TODO: support directory fd
+        return Err(Errno::EBADF);
+    }
+    let flags = OpenFlags::from_bits(flags).ok_or(Errno::EINVAL)?;
+    let inode = open_file(&path, flags)?;
+
+    let current_process = current_process();
+    let mut process_inner = current_process.lock();
+    let fd = alloc_fd(&mut process_inner.fd_table, inode).ok_or(Errno::EMFILE)?;
+    Ok(fd as isize)
+}
+
+pub fn sys_close(fd: usize) -> SysResult {
+    let current_process = current_process();
+    let mut process_inner = current_process.lock();
+    let file = process_inner.fd_table.get_mut(fd).and_then(Option::take).ok_or(Errno::EBADF)?;
+    drop(process_inner);
+    // Closing a pipe end wakes up the other side, so it's done without the lock.
+    drop(file);
+    Ok(0)
+}
+
+pub fn sys_pipe2(fds: usize, flags: u32) -> SysResult {
+    if flags != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let (read_end, write_end) = make_pipe();
+
+    let current_process = current_process();
+    let mut process_inner = current_process.lock();
+    let read_fd = alloc_fd(&mut process_inner.fd_table, read_end).ok_or(Errno::EMFILE)?;
+    let write_fd = match alloc_fd(&mut process_inner.fd_table, write_end) {
+        Some(write_fd) => write_fd,
+        None => {
+            process_inner.fd_table[read_fd] = None;
+            return Err(Errno::EMFILE);
+        }
+    };
+    let fds_ptr = UserPtr::<[i32; 2]>::new(fds);
+    if let Err(err) = fds_ptr.write(&mut process_inner.addr_space, &[read_fd as i32, write_fd as i32]) {
+        process_inner.fd_table[read_fd] = None;
+        process_inner.fd_table[write_fd] = None;
+        return Err(err.into());
+    }
+    Ok(0)
+}
+
+pub fn sys_dup(fd: usize) -> SysResult {
+    let current_process = current_process();
+    let mut process_inner = current_process.lock();
+    let file = process_inner.fd_table.get(fd).cloned().flatten().ok_or(Errno::EBADF)?;
+    let new_fd = alloc_fd(&mut process_inner.fd_table, file).ok_or(Errno::EMFILE)?;
+    Ok(new_fd as isize)
+}
+
+/// Make new_fd a copy of old_fd, closing the file at new_fd first.
+pub fn sys_dup3(old_fd: usize, new_fd: usize, flags: u32) -> SysResult {
+    if flags != 0 || old_fd == new_fd {
+        return Err(Errno::EINVAL);
+    }
+    if new_fd >= FD_LIMIT {
+        return Err(Errno::EBADF);
+    }
+    let current_process = current_process();
+    let mut process_inner = current_process.lock();
+    let fd_table = &mut process_inner.fd_table;
+    let file = fd_table.get(old_fd).cloned().flatten().ok_or(Errno::EBADF)?;
+    if fd_table.len() <= new_fd {
+        fd_table.resize(new_fd + 1, None);
+    }
+    let old_file = fd_table[new_fd].replace(file);
+    drop(process_inner);
+    drop(old_file);
+    Ok(new_fd as isize)
+}
+
+// The user buffer is copied through a kernel buffer of this size at most, since the file
+// may block, and we can't hold the process lock meanwhile.
+const IO_CHUNK_SIZE: usize = PAGE_SIZE;
+
+/// It reads at most IO_CHUNK_SIZE bytes at a time, since a second read could block
+/// even if we've got some data (e.g. from a pipe).
+pub fn sys_read(fd: usize, buffer: usize, len: usize) -> SysResult {
+    let file = match get_file(fd) {
+        Some(file) if file.readable() => file,
+        _ => return Err(Errno::EBADF),
+    };
+    // Only the part we may fill this time.
+    let user_buf = UserSlice::new(buffer, len.min(IO_CHUNK_SIZE));
+    // Don't consume the data if we can't deliver it.
+    with_user_space(|addr_space| user_buf.check(addr_space, MemAccess::Write))?;
+
+    let mut kernel_buf = vec![0; user_buf.len()];
+    let n = file.read(&mut kernel_buf)?;
+    with_user_space(|addr_space| user_buf.write(addr_space, 0, &kernel_buf[..n]))?;
+    Ok(n as isize)
+}
+
+/// The user buffer is checked chunk by chunk. If a chunk is bad, what has been written
+/// before it is reported, or it fails with EFAULT if nothing has.
+pub fn sys_write(fd: usize, buffer: usize, len: usize) -> SysResult {
+    let file = match get_file(fd) {
+        Some(file) if file.writable() => file,
+        _ => return Err(Errno::EBADF),
+    };
+    let user_buf = UserSlice::new(buffer, len);
+
+    let mut kernel_buf = vec![0; len.min(IO_CHUNK_SIZE)];
+    let mut written = 0;
+    while written < len {
+        // The chunks end at page boundaries, so the count stops right at the bad page.
+        let page_left = PAGE_SIZE - buffer.wrapping_add(written) % PAGE_SIZE;
+        let chunk = &mut kernel_buf[..(len - written).min(IO_CHUNK_SIZE).min(page_left)];
+        match with_user_space(|addr_space| user_buf.read(addr_space, written, chunk)) {
+            Ok(()) => {}
+            Err(_) if written > 0 => break,
+            Err(err) => return Err(err.into()),
+        }
+        let n = match file.write(chunk) {
+            Ok(n) => n,
+            // Report what has been written, and the error is left to the next write.
+            Err(_) if written > 0 => break,
+            Err(err) => return Err(err),
+        };
+        written += n;
+        if n < chunk.len() {
+            break;
+        }
+    }
+    Ok(written as isize)
+}
+
+/// Only the terminal attributes of the console, which are shared by all its fds.
+pub fn sys_ioctl(fd: usize, request: usize, arg: usize) -> SysResult {
+    let file = get_file(fd).ok_or(Errno::EBADF)?;
+    if !file.is_tty() {
+        return Err(Errno::ENOTTY);
+    }
+    let termios_ptr = UserPtr::<Termios>::new(arg);
+    match request {
+        TCGETS => {
+            let termios = tty().termios();
+            with_user_space(|addr_space| termios_ptr.write(addr_space, &termios))?;
+        }
+        TCSETS => {
+            let termios = with_user_space(|addr_space| termios_ptr.read(addr_space))?;
+            tty().set_termios(termios);
+        }
+        _ => return Err(Errno::EINVAL),
+    }
+    Ok(0)
+}
@@ -0,0 +1,77 @@
+use alloc::sync::Weak;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use crate::mm::user_ptr::UserPtr;
+use crate::task::{ProcessControlBlock, TaskStatus};
+use crate::time::clocks_per_milli_sec;
+use super::{with_user_space, SysResult};
+
+/// An entry of the process list. The same layout as `ProcessInfo` of the user.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct ProcessInfo {
+    pid: usize,
+    /// 0 if there is no parent, i.e. initproc.
+    ppid: usize,
+    /// Threads that haven't exited.
+    threads: usize,
+    /// Scheduling state of the thread with the smallest tid.
+    priority: u64,
+    pass: u64,
+    /// User and kernel time of all the threads.
+    cpu_ms: usize,
+    /// Frames owned by the address space now.
+    frames: usize,
+    /// Same letters as ps: R (running or ready), S (sleeping) or Z (zombie).
+    state: u8,
+}
+
+fn process_info(process: &ProcessControlBlock) -> ProcessInfo {
+    let inner = process.lock();
+    let ppid = inner.parent.as_ref().and_then(Weak::upgrade).map_or(0, |parent| parent.pid.0);
+    let frames = inner.addr_space.resident_frames();
+    let is_zombie = inner.is_zombie;
+    drop(inner);
+
+    let usage = process.usage.snapshot();
+    let mut info = ProcessInfo {
+        pid: process.pid.0,
+        ppid,
+        cpu_ms: (usage.user_clocks + usage.kernel_clocks) / clocks_per_milli_sec(),
+        frames,
+        state: if is_zombie { b'Z' } else { b'S' },
+        ..Default::default()
+    };
+    // The process isn't locked here, since dropping the last reference of a thread locks it.
+    for thread in process.threads().iter().filter_map(Weak::upgrade) {
+        let thread_inner = thread.lock();
+        if thread_inner.status == TaskStatus::Zombie {
+            continue;
+        }
+        if info.threads == 0 {
+            info.priority = thread_inner.sched.priority;
+            info.pass = thread_inner.sched.pass.unwrap_or(0);
+        }
+        info.threads += 1;
+        if !is_zombie && matches!(thread_inner.status, TaskStatus::Running | TaskStatus::Ready) {
+            info.state = b'R';
+        }
+    }
+    info
+}
+
+/// Fill the buffer with the processes, ordered by the pid. Return the number of all the
+/// processes, which may be more than `len`.
+pub fn sys_get_process_list(buf: usize, len: usize) -> SysResult {
+    let processes = ProcessControlBlock::all();
+    let infos: Vec<ProcessInfo> = processes.iter().map(|process| process_info(process)).collect();
+    let count = infos.len();
+    drop(processes);
+
+    with_user_space(|addr_space| {
+        infos.iter().take(len).enumerate().try_for_each(|(i, info)| {
+            UserPtr::new(buf.wrapping_add(i * size_of::<ProcessInfo>())).write(addr_space, info)
+        })
+    })?;
+    Ok(count as isize)
+}
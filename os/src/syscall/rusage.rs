@@ -0,0 +1,67 @@
+use crate::config::PAGE_SIZE;
+use crate::mm::user_ptr::UserPtr;
+use crate::task::{current_process, UsageSnapshot};
+use crate::time::clocks_per_sec;
+use super::{with_user_space, Errno, SysResult, TimeVal};
+
+pub const RUSAGE_SELF: isize = 0;
+pub const RUSAGE_CHILDREN: isize = -1;
+
+/// Same layout as the Linux `struct rusage`. The fields we don't track are 0.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct RUsage {
+    utime: TimeVal,
+    stime: TimeVal,
+    /// Peak resident set size in KiB.
+    maxrss: usize,
+    ixrss: usize,
+    idrss: usize,
+    isrss: usize,
+    /// Page faults resolved without I/O, which are all of ours.
+    minflt: usize,
+    majflt: usize,
+    nswap: usize,
+    inblock: usize,
+    oublock: usize,
+    msgsnd: usize,
+    msgrcv: usize,
+    nsignals: usize,
+    nvcsw: usize,
+    nivcsw: usize,
+}
+
+fn clocks_to_time_val(clocks: usize) -> TimeVal {
+    TimeVal {
+        sec: clocks / clocks_per_sec(),
+        usec: clocks % clocks_per_sec() * 1_000_000 / clocks_per_sec(),
+    }
+}
+
+impl From<UsageSnapshot> for RUsage {
+    fn from(usage: UsageSnapshot) -> Self {
+        Self {
+            utime: clocks_to_time_val(usage.user_clocks),
+            stime: clocks_to_time_val(usage.kernel_clocks),
+            maxrss: usage.max_frames * PAGE_SIZE / 1024,
+            minflt: usage.page_faults,
+            nvcsw: usage.voluntary_switches,
+            nivcsw: usage.involuntary_switches,
+            ..Default::default()
+        }
+    }
+}
+
+/// Usage of the current process, summed over its threads, or of its reaped children.
+pub fn sys_getrusage(who: isize, usage_ptr: usize) -> SysResult {
+    let current_process = current_process();
+    let usage = match who {
+        RUSAGE_SELF => current_process.usage(),
+        RUSAGE_CHILDREN => current_process.children_usage.snapshot(),
+        _ => return Err(Errno::EINVAL),
+    };
+    drop(current_process);
+    let rusage = RUsage::from(usage);
+    with_user_space(|addr_space| UserPtr::new(usage_ptr).write(addr_space, &rusage))?;
+    Ok(0)
+}
@@ -0,0 +1,101 @@
+use crate::mm::user_ptr::UserPtr;
+use crate::task::find_process;
+use crate::task::signal::{
+    force_signal, is_valid, send_signal, sigreturn, SignalAction, SignalActionFlags, SignalSet, SIGSEGV,
+    SIG_DFL, SIG_IGN,
+};
+use crate::task::current_process;
+use super::{Errno, SysResult};
+
+/// Values of `how` of sigprocmask, same as Linux.
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+/// Signal 0 sends nothing, but still checks the pid.
+/// The signal is delivered when the target returns to the user mode. An interruptible
+/// blocking syscall of the target fails with EINTR for it, e.g. reading a pipe.
+pub fn sys_kill(pid: isize, sig: usize) -> SysResult {
+    // There are no process groups.
+    if pid <= 0 {
+        return Err(Errno::EINVAL);
+    }
+    if sig != 0 && !is_valid(sig) {
+        return Err(Errno::EINVAL);
+    }
+    let process = find_process(pid as usize).ok_or(Errno::ESRCH)?;
+    if sig != 0 {
+        send_signal(&process, sig);
+    }
+    Ok(0)
+}
+
+/// Both pointers can be null.
+pub fn sys_sigaction(sig: usize, act: usize, old_act: usize) -> SysResult {
+    if !is_valid(sig) {
+        return Err(Errno::EINVAL);
+    }
+    let current_process = current_process();
+    let mut process_inner = current_process.lock();
+
+    let new_action = if act != 0 {
+        let action = UserPtr::<SignalAction>::new(act).read(&mut process_inner.addr_space)?;
+        if SignalSet::UNCATCHABLE.contains(sig) || SignalActionFlags::from_bits(action.flags).is_none() {
+            return Err(Errno::EINVAL);
+        }
+        // The handler would return to nowhere.
+        if !matches!(action.handler, SIG_DFL | SIG_IGN) && action.restorer == 0 {
+            return Err(Errno::EINVAL);
+        }
+        Some(action)
+    } else {
+        None
+    };
+    if old_act != 0 {
+        let action = process_inner.signals.actions[sig];
+        UserPtr::new(old_act).write(&mut process_inner.addr_space, &action)?;
+    }
+    if let Some(action) = new_action {
+        process_inner.signals.actions[sig] = action;
+        // An ignored signal is discarded, even if it's blocked.
+        if action.handler == SIG_IGN {
+            process_inner.signals.pending.remove(sig);
+        }
+    }
+    Ok(0)
+}
+
+/// Both pointers can be null. SIGKILL and SIGSTOP are silently left unblocked.
+pub fn sys_sigprocmask(how: usize, set: usize, old_set: usize) -> SysResult {
+    let current_process = current_process();
+    let mut process_inner = current_process.lock();
+
+    let new_set = if set != 0 {
+        Some(UserPtr::<SignalSet>::new(set).read(&mut process_inner.addr_space)?)
+    } else {
+        None
+    };
+    if old_set != 0 {
+        let blocked = process_inner.signals.blocked;
+        UserPtr::new(old_set).write(&mut process_inner.addr_space, &blocked)?;
+    }
+    if let Some(new_set) = new_set {
+        let blocked = process_inner.signals.blocked;
+        let blocked = match how {
+            SIG_BLOCK => blocked.union(new_set),
+            SIG_UNBLOCK => blocked.difference(new_set),
+            SIG_SETMASK => new_set,
+            _ => return Err(Errno::EINVAL),
+        };
+        process_inner.signals.blocked = blocked.blockable();
+    }
+    Ok(0)
+}
+
+/// A bad frame kills the process, since there is no context to return to.
+pub fn sys_sigreturn() -> SysResult {
+    sigreturn().map_err(|_| {
+        force_signal(SIGSEGV);
+        Errno::EFAULT
+    })
+}
@@ -0,0 +1,148 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::sync::{
+    Condvar, DeadlockDetector, MutexBlocking, MutexSpin, Resource, Semaphore, SyncTable, UserMutex,
+};
+use crate::task::{current_process, current_task};
+use super::{Errno, SysResult};
+
+/// Run f with the sync objects of the current process.
+fn with_sync_table<R>(f: impl FnOnce(&mut SyncTable) -> R) -> R {
+    let current_process = current_process();
+    let mut process_inner = current_process.lock();
+    f(&mut process_inner.sync_table)
+}
+
+fn with_detector<R>(f: impl FnOnce(&mut DeadlockDetector) -> R) -> R {
+    with_sync_table(|table| f(&mut table.detector))
+}
+
+/// Return the id of the new object.
+fn push<T>(objects: &mut Vec<T>, object: T) -> usize {
+    objects.push(object);
+    objects.len() - 1
+}
+
+/// Look up an object of the current process. It's used without the process lock,
+/// since it may block.
+fn get_object<T: Clone>(table: impl FnOnce(&SyncTable) -> &Vec<T>, id: usize) -> Result<T, Errno> {
+    with_sync_table(|sync_table| table(sync_table).get(id).cloned().ok_or(Errno::EINVAL))
+}
+
+fn get_mutex(id: usize) -> Result<Arc<dyn UserMutex>, Errno> {
+    get_object(|table| &table.mutexes, id)
+}
+
+/// Fail with EDEADLK instead of blocking, if the detection is enabled and the request
+/// could deadlock.
+fn request(tid: usize, resource: Resource) -> Result<(), Errno> {
+    if with_detector(|detector| detector.request(tid, resource)) {
+        Ok(())
+    } else {
+        Err(Errno::EDEADLK)
+    }
+}
+
+/// A blocking mutex sleeps while waiting, and the other one yields.
+pub fn sys_mutex_create(blocking: bool) -> SysResult {
+    let mutex: Arc<dyn UserMutex> = if blocking {
+        Arc::new(MutexBlocking::new())
+    } else {
+        Arc::new(MutexSpin::new())
+    };
+    let id = with_sync_table(|table| {
+        let id = push(&mut table.mutexes, mutex);
+        table.detector.add_resource(Resource::Mutex(id), 1);
+        id
+    });
+    Ok(id as isize)
+}
+
+pub fn sys_mutex_lock(id: usize) -> SysResult {
+    let mutex = get_mutex(id)?;
+    let tid = current_task().tid;
+    request(tid, Resource::Mutex(id))?;
+    if let Err(err) = mutex.lock() {
+        with_detector(|detector| detector.cancel(tid, Resource::Mutex(id)));
+        return Err(err);
+    }
+    with_detector(|detector| detector.acquired(tid, Resource::Mutex(id)));
+    Ok(0)
+}
+
+/// Only the thread holding the mutex can unlock it, or it fails with EPERM.
+pub fn sys_mutex_unlock(id: usize) -> SysResult {
+    if !get_mutex(id)?.unlock() {
+        return Err(Errno::EPERM);
+    }
+    let tid = current_task().tid;
+    with_detector(|detector| detector.released(tid, Resource::Mutex(id)));
+    Ok(0)
+}
+
+pub fn sys_semaphore_create(count: usize) -> SysResult {
+    let semaphore = Arc::new(Semaphore::new(count));
+    let id = with_sync_table(|table| {
+        let id = push(&mut table.semaphores, semaphore);
+        table.detector.add_resource(Resource::Semaphore(id), count);
+        id
+    });
+    Ok(id as isize)
+}
+
+pub fn sys_semaphore_up(id: usize) -> SysResult {
+    get_object(|table| &table.semaphores, id)?.up();
+    let tid = current_task().tid;
+    with_detector(|detector| detector.released(tid, Resource::Semaphore(id)));
+    Ok(0)
+}
+
+pub fn sys_semaphore_down(id: usize) -> SysResult {
+    let semaphore = get_object(|table| &table.semaphores, id)?;
+    let tid = current_task().tid;
+    request(tid, Resource::Semaphore(id))?;
+    if let Err(err) = semaphore.down() {
+        with_detector(|detector| detector.cancel(tid, Resource::Semaphore(id)));
+        return Err(err);
+    }
+    with_detector(|detector| detector.acquired(tid, Resource::Semaphore(id)));
+    Ok(0)
+}
+
+pub fn sys_condvar_create() -> SysResult {
+    let condvar = Arc::new(Condvar::new());
+    let id = with_sync_table(|table| push(&mut table.condvars, condvar));
+    Ok(id as isize)
+}
+
+pub fn sys_condvar_signal(id: usize) -> SysResult {
+    get_object(|table| &table.condvars, id)?.signal();
+    Ok(0)
+}
+
+/// The mutex must be locked by the caller, or it fails with EPERM.
+pub fn sys_condvar_wait(id: usize, mutex_id: usize) -> SysResult {
+    let condvar = get_object(|table| &table.condvars, id)?;
+    let mutex = get_mutex(mutex_id)?;
+    if !mutex.is_held() {
+        return Err(Errno::EPERM);
+    }
+    let tid = current_task().tid;
+    // The mutex is given back meanwhile. Taking it again isn't checked, since the
+    // caller has no way to back off here. It's taken again even if interrupted.
+    with_detector(|detector| detector.released(tid, Resource::Mutex(mutex_id)));
+    let ret = condvar.wait(&*mutex);
+    with_detector(|detector| detector.acquired(tid, Resource::Mutex(mutex_id)));
+    ret.map(|_| 0)
+}
+
+/// 1 to enable the detection, and 0 to disable it.
+pub fn sys_enable_deadlock_detect(enabled: usize) -> SysResult {
+    let enabled = match enabled {
+        0 => false,
+        1 => true,
+        _ => return Err(Errno::EINVAL),
+    };
+    with_detector(|detector| detector.enabled = enabled);
+    Ok(0)
+}
@@ -0,0 +1,55 @@
+use alloc::sync::Arc;
+use crate::config::USER_SPACE_END;
+use crate::mm::user_ptr::UserPtr;
+use crate::task::current_task;
+use super::{Errno, SysResult};
+
+/// The thread starts at `entry` with `arg` in a0, on a user stack of its own.
+/// It should exit by itself, since there is nowhere to return to.
+/// It fails with EAGAIN if the process has MAX_THREADS threads already.
+pub fn sys_thread_create(entry: usize, arg: usize) -> SysResult {
+    if entry >= USER_SPACE_END {
+        return Err(Errno::EINVAL);
+    }
+    let current_task = current_task();
+    Ok(current_task.create_thread(entry, arg)? as isize)
+}
+
+pub fn sys_gettid() -> SysResult {
+    Ok(current_task().tid as isize)
+}
+
+/// Wait for the thread of the current process to exit. Return the tid.
+/// Each thread can only be waited for once. It fails with EINTR if a signal comes first.
+pub fn sys_waittid(tid: usize, exit_code_ptr: usize) -> SysResult {
+    let current_task = current_task();
+    if tid == current_task.tid {
+        return Err(Errno::EDEADLK);
+    }
+    let exit_code_ptr = UserPtr::<i32>::new(exit_code_ptr);
+    let process = Arc::clone(&current_task.process);
+    drop(current_task);
+
+    process.thread_exit.wait_until_interruptible(|| {
+        let mut process_inner = process.lock();
+        let slot = match process_inner.threads.get_mut(tid).and_then(Option::as_mut) {
+            Some(slot) if !slot.waited => slot,
+            _ => return Some(Err(Errno::ESRCH)),
+        };
+        // Not exited yet.
+        let exit_code = slot.exit_code?;
+        // A null exit_code_ptr means the caller doesn't care about it.
+        if exit_code_ptr.addr() != 0 {
+            if let Err(err) = exit_code_ptr.write(&mut process_inner.addr_space, &exit_code) {
+                return Some(Err(err.into()));
+            }
+        }
+        let slot = process_inner.threads[tid].as_mut().unwrap();
+        slot.waited = true;
+        // The tid is free once the thread is released as well.
+        if slot.released {
+            process_inner.threads[tid] = None;
+        }
+        Some(Ok(tid as isize))
+    })?
+}
@@ -2,31 +2,47 @@ mod stack;
 mod pid;
 mod elf_loader;
 mod processor;
+mod wait_queue;
+mod timer;
+mod process;
+mod rusage;
+mod scheduler;
+pub mod signal;
 
 use lazy_static::lazy_static;
 use core::arch::global_asm;
 use spin::Mutex;
 use spin::MutexGuard;
-use alloc::vec::Vec;
+use alloc::boxed::Box;
 // use alloc::collections::BinaryHeap;
 // use crate::mm::*;
 // use crate::config::*;
 use alloc::sync::Arc;
 use alloc::sync::Weak;
-use pid::{ Pid, pid_alloc };
-pub use processor::PROCESSOR;
-use crate::trap::TRAP_CX_VA;
+use pid::pid_alloc;
+pub use processor::{current_process, current_task, processor};
+pub use wait_queue::{interrupt, WaitQueue};
+pub use timer::{sleep_until, wake_expired};
+use signal::SignalState;
+pub use process::{ProcessControlBlock, ProcessControlBlockInner};
+pub use rusage::{ResourceUsage, UsageSnapshot};
+pub use scheduler::{new_scheduler, SchedEntity, Scheduler};
+use crate::trap::trap_cx_va;
 use crate::trap::TrapContext;
+use crate::syscall::Errno;
 // use crate::config::*;
 
 pub use stack::KernelStack;
 use crate::sbi;
+use crate::smp::hart_id;
 // use crate::println;
 use crate::trap::__restore;
 use crate::time;
 use crate::syscall::MAX_SYSCALL_NUM;
 use crate::mm::address_space::AddressSpace;
-pub use elf_loader::get_app_data;
+use crate::fs::new_fd_table;
+use crate::sync::SyncTable;
+pub use elf_loader::{get_app_data, load_app};
 
 
 // global_asm!(include_str!("link_app.S"));
@@ -34,8 +50,6 @@ pub use elf_loader::get_app_data;
 //     static _num_app: usize;
 // }
 
-const STRIDE: u64 = 10007;
-
 global_asm!(include_str!("task/switch.S"));
 extern "C" {
     fn __switch(current_cx: *mut TaskContext, next_cx: *const TaskContext);
@@ -44,9 +58,19 @@ extern "C" {
 
 lazy_static! {
     pub static ref TASK_MANAGER: Mutex<TaskManager> = Mutex::new(TaskManager::new());
-    pub static ref INITPROC: Arc<TaskControlBlock> = {
-        let initproc_elf = get_app_data("ch5b_initproc").expect("missing initproc");
-        TaskControlBlock::load_from_elf(initproc_elf, None)
+    /// Its main thread is put into the ready queue on the first access.
+    pub static ref INITPROC: Arc<ProcessControlBlock> = {
+        // Fall back to the embedded one, so that we can still boot without a disk.
+        let main_thread = match load_app("ch5b_initproc") {
+            Ok(initproc_elf) => TaskControlBlock::load_from_elf(&initproc_elf, None),
+            Err(_) => {
+                let initproc_elf = get_app_data("ch5b_initproc").expect("missing initproc");
+                TaskControlBlock::load_from_elf(initproc_elf, None)
+            }
+        }.expect("invalid initproc");
+        let initproc = Arc::clone(&main_thread.process);
+        TASK_MANAGER.lock().add(main_thread);
+        initproc
     };
 }
 
@@ -58,6 +82,8 @@ pub enum TaskStatus {
     Running = 1,
     // Exited = 3,
     Zombie = 2,
+    /// Sleeping on a wait queue.
+    Blocked = 3,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -69,11 +95,34 @@ pub struct TaskContext {
     s0_11: [usize; 12],
 }
 
+impl TaskContext {
+    pub const fn zero() -> Self {
+        Self {
+            ra: 0,
+            sp: 0,
+            satp: 0,
+            s0_11: [0; 12],
+        }
+    }
+
+    /// Start from the trap context of the thread, which returns to the user mode.
+    fn trap_return(tid: usize, satp: usize) -> Self {
+        Self {
+            ra: __restore as usize,
+            sp: trap_cx_va(tid).0,
+            satp,
+            s0_11: [0; 12],
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TaskStat {
     pub cpu_clocks: usize,
     pub first_scheduled: Option<usize>,
     pub last_scheduled: Option<usize>,
+    /// When the thread last entered or left the user mode, or was scheduled.
+    mode_stamp: usize,
     pub syscall_times: [u32; MAX_SYSCALL_NUM],
 }
 
@@ -85,16 +134,33 @@ impl TaskStat {
         } else {
             self.last_scheduled = Some(time::get_time());
         }
+        self.mode_stamp = self.last_scheduled.unwrap();
     }
 
-    pub fn record_schedule_end(&mut self) {
+    /// Return the clocks spent since the last call, or since scheduled.
+    pub fn take_mode_clocks(&mut self) -> usize {
+        let now = time::get_time();
+        let clocks = now.checked_sub(self.mode_stamp).expect("time goes backward");
+        self.mode_stamp = now;
+        clocks
+    }
+
+    /// Return the clocks spent since scheduled.
+    pub fn record_schedule_end(&mut self) -> usize {
         if let Some(last_scheduled) = self.last_scheduled {
-            self.cpu_clocks += time::get_time().checked_sub(last_scheduled).expect("time goes backward");
+            let clocks = time::get_time().checked_sub(last_scheduled).expect("time goes backward");
+            self.cpu_clocks += clocks;
+            clocks
+        } else {
+            0
         }
     }
 
     pub fn record_syscall(&mut self, syscall: usize) {
-        self.syscall_times[syscall] += 1;
+        // Unknown syscalls aren't counted.
+        if let Some(times) = self.syscall_times.get_mut(syscall) {
+            *times += 1;
+        }
     }
 
     pub fn real_time(&self) -> usize {
@@ -112,6 +178,7 @@ impl Default for TaskStat {
             cpu_clocks: 0, 
             first_scheduled: None,
             last_scheduled: None,
+            mode_stamp: 0,
             syscall_times: [0; MAX_SYSCALL_NUM],
         }
     }
@@ -121,179 +188,283 @@ impl Default for TaskStat {
 pub struct TaskControlBlockInner {
     pub status: TaskStatus,
     cx: TaskContext,
-    // TODO: no pub
-    pub addr_space: AddressSpace,
     pub stats: TaskStat,
 
-    pub children: Vec<Arc<TaskControlBlock>>,
-    pub parent: Option<Weak<TaskControlBlock>>,
-    pub exit_code: i32,
+    pub sched: SchedEntity,
 
-    // Stride scheduling
-    pub priority: u64,
-    pass: u64,
+    /// The task is running on a hart, or still switching out of it.
+    /// Only the hart may put it back to the ready queue meanwhile.
+    on_cpu: bool,
+    /// Blocked in WaitQueue::wait_until_interruptible, so a signal can wake it up.
+    interruptible: bool,
 }
 
 impl TaskControlBlockInner {
     fn schedule_begin(&mut self) -> *const TaskContext {
         assert_eq!(self.status, TaskStatus::Ready);
         self.status = TaskStatus::Running;
+        self.on_cpu = true;
         self.stats.record_schedule_begin();
 
         &self.cx as *const TaskContext
     }
 
-    fn schedule_end(&mut self) -> *mut TaskContext {
-        assert!(matches!(self.status, TaskStatus::Running | TaskStatus::Zombie));
-        self.stats.record_schedule_end();
+    /// It's switched out in the kernel, which is charged to `usage`.
+    fn schedule_end(&mut self, usage: &ResourceUsage) -> *mut TaskContext {
+        // It may have been woken up by another hart before switching out.
+        assert!(matches!(
+            self.status,
+            TaskStatus::Running | TaskStatus::Zombie | TaskStatus::Blocked | TaskStatus::Ready
+        ));
+        usage.add_kernel_clocks(self.stats.take_mode_clocks());
+        match self.status {
+            TaskStatus::Running => usage.add_switch(false),
+            TaskStatus::Blocked | TaskStatus::Ready => usage.add_switch(true),
+            _ => {}
+        }
+        self.sched.last_run = self.stats.record_schedule_end();
         if self.status == TaskStatus::Running {
             self.status = TaskStatus::Ready;
         }
-        self.pass += STRIDE / self.priority;
 
         &mut self.cx as *mut TaskContext
     }
 }
 
+/// A thread, which is what the harts schedule. What it shares with the other threads
+/// is in its process.
 #[derive(Debug)]
 pub struct TaskControlBlock {
-    pub pid: Pid,
+    /// Index in the threads of the process. The main thread is 0.
+    pub tid: usize,
+    pub process: Arc<ProcessControlBlock>,
     inner: Mutex<TaskControlBlockInner>,
 }
 
 impl TaskControlBlock {
-    pub fn load_from_elf(elf_data: &[u8], parent: Option<Weak<TaskControlBlock>>) -> Arc<Self> {
-        let pid = pid_alloc();
-        let addr_space = AddressSpace::from_elf(elf_data, pid.0);
-        let mut cx = TaskContext::default();
-        cx.sp = TRAP_CX_VA.0;
-        cx.ra = __restore as usize;
-        cx.satp = addr_space.satp();
-        // crate::println!("satp: 0x{:x}", cx.satp);
-
+    /// The stacks of the thread must be allocated, with the trap context in place.
+    fn new(process: Arc<ProcessControlBlock>, tid: usize, satp: usize) -> Arc<Self> {
         let inner = TaskControlBlockInner {
             status: TaskStatus::Ready,
-            cx,
-            addr_space,
+            cx: TaskContext::trap_return(tid, satp),
             stats: TaskStat::default(),
-            children: Vec::new(),
-            parent,
-            exit_code: 0,
-            priority: 16,
-            pass: 0,
+            sched: SchedEntity::default(),
+            on_cpu: false,
+            interruptible: false,
         };
-        Arc::new(Self {
-            pid,
+        let task = Arc::new(Self {
+            tid,
+            process,
             inner: Mutex::new(inner),
-        })
+        });
+        let mut process_inner = task.process.lock();
+        process_inner.threads[tid].as_mut().expect("missing thread slot").task = Arc::downgrade(&task);
+        drop(process_inner);
+        task
     }
 
-    pub fn lock<'a>(&'a self) -> MutexGuard<'a, TaskControlBlockInner> {
-        self.inner.lock()
+    /// Create a process from the elf. Return its main thread, which isn't in the ready queue yet.
+    /// It fails with ENOEXEC if the elf can't be loaded, or ENOMEM if we run out of frames.
+    pub fn load_from_elf(elf_data: &[u8], parent: Option<Weak<ProcessControlBlock>>) -> Result<Arc<Self>, Errno> {
+        let pid = pid_alloc();
+        let (mut addr_space, entry_point) = AddressSpace::from_elf(elf_data, pid.0)?;
+        let ustack_top = addr_space.alloc_thread_stacks(0)?;
+        *addr_space.trap_cx_mut(0) = TrapContext::app_init_context(entry_point, ustack_top.0);
+        let satp = addr_space.satp();
+
+        let process = ProcessControlBlock::new(
+            pid,
+            addr_space,
+            parent,
+            new_fd_table(),
+            SignalState::default(),
+            SyncTable::default(),
+            0,
+        );
+        Ok(Self::new(process, 0, satp))
     }
 
-    pub fn is_zombie(&self) -> bool {
-        self.lock().status == TaskStatus::Zombie
+    pub fn lock<'a>(&'a self) -> MutexGuard<'a, TaskControlBlockInner> {
+        self.inner.lock()
     }
 
     pub fn is_ready(&self) -> bool {
         self.lock().status == TaskStatus::Ready
     }
 
-    pub fn exec(self: Arc<Self>, elf_data: &[u8]) {
-        let mut inner = self.lock();
-        inner.schedule_end();
-
-        let addr_space = AddressSpace::from_elf(elf_data, self.pid.0);
-        inner.cx.sp = TRAP_CX_VA.0;
-        inner.cx.ra = __restore as usize;
-        inner.cx.satp = addr_space.satp();
-        inner.addr_space = addr_space;
+    /// Replace the image of the process, keeping the tid of the thread.
+    /// It only returns on failure: EBUSY if other threads are still alive, which would lose
+    /// their stacks, ENOEXEC if the elf can't be loaded, or ENOMEM if we run out of frames.
+    pub fn exec<D: AsRef<[u8]>>(self: Arc<Self>, elf_data: D) -> Errno {
+        let mut process_inner = self.process.lock();
+        if process_inner.has_other_threads(self.tid) {
+            return Errno::EBUSY;
+        }
+        // Nothing is changed until the new image is loaded.
+        let (mut addr_space, entry_point) = match AddressSpace::from_elf(elf_data.as_ref(), self.process.pid.0) {
+            Ok(loaded) => loaded,
+            Err(err) => return err,
+        };
+        let ustack_top = match addr_space.alloc_thread_stacks(self.tid) {
+            Ok(ustack_top) => ustack_top,
+            Err(err) => return err,
+        };
+        // Exited threads that haven't been waited for go with the old image.
+        for (tid, slot) in process_inner.threads.iter_mut().enumerate() {
+            if tid != self.tid {
+                *slot = None;
+            }
+        }
 
-        let cx = inner.schedule_begin();
+        *addr_space.trap_cx_mut(self.tid) = TrapContext::app_init_context(entry_point, ustack_top.0);
+        let satp = addr_space.satp();
+        let old_addr_space = core::mem::replace(&mut process_inner.addr_space, addr_space);
+        #[cfg(feature = "gdbstub")]
+        crate::gdbstub::on_image_gone(self.process.pid.0);
+        self.process.usage.record_frames(old_addr_space.peak_frames());
+        process_inner.signals.exec();
+        // The ids are meaningless to the new image.
+        process_inner.sync_table = SyncTable::default();
+        drop(process_inner);
 
+        let mut inner = self.lock();
+        inner.schedule_end(&self.process.usage);
+        inner.cx = TaskContext::trap_return(self.tid, satp);
         drop(inner);
+
+        // We are still on the kernel stack in the old address space, so the hart drops it
+        // after switching out.
+        let mut this_processor = processor().lock();
+        this_processor.retire_addr_space(old_addr_space);
+        let idle_cx = this_processor.idle_cx_ptr();
+        drop(this_processor);
+
         drop(self);
+        // We never come back, so drop everything now.
+        drop(elf_data);
         let mut unused = TaskContext::default();
         unsafe {
-            __switch(&mut unused, cx);
+            __switch(&mut unused, idle_cx);
         }
+        unreachable!();
     }
 
-    pub fn fork(self: &Arc<Self>) -> usize {
+    /// Fork the process with only this thread, which keeps its tid in the child.
+    /// Return the child pid, or ENOMEM if the address space can't be duplicated.
+    pub fn fork(&self) -> Result<usize, Errno> {
         let child_pid = pid_alloc();
-        let mut parent_inner = self.lock();
-
-        let child_addr_space = parent_inner.addr_space.dup(child_pid.0);
-        let mut child_cx = TaskContext::default();
-        child_cx.sp = TRAP_CX_VA.0;
-        child_cx.ra = __restore as usize;
-        child_cx.satp = child_addr_space.satp();
-
-        let child_trap_cx = child_addr_space.translate(TRAP_CX_VA).expect("failed to translate trap cx va").0 as *mut TrapContext;
-        unsafe {
-            // return 0 for syscall fork
-            (*child_trap_cx).x[10] = 0;
+        // The breakpoints of gdb mustn't change while the code is copied.
+        #[cfg(feature = "gdbstub")]
+        let breakpoints = crate::gdbstub::hold_breakpoints(self.process.pid.0);
+        let mut parent_inner = self.process.lock();
+
+        let mut child_addr_space = parent_inner.addr_space.dup(child_pid.0)?;
+        #[cfg(feature = "gdbstub")]
+        breakpoints.restore_code(&mut child_addr_space);
+        // The other threads don't exist in the child.
+        for (tid, slot) in parent_inner.threads.iter().enumerate() {
+            if tid != self.tid && slot.as_ref().map_or(false, |slot| !slot.released) {
+                child_addr_space.free_thread_stacks(tid);
+            }
         }
+        // return 0 for syscall fork
+        child_addr_space.trap_cx_mut(self.tid).x[10] = 0;
+        let satp = child_addr_space.satp();
 
-        let child_inner = TaskControlBlockInner {
-            status: TaskStatus::Ready,
-            cx: child_cx,
-            addr_space: child_addr_space,
-            parent: Some(Arc::downgrade(self)),
-            children: Vec::new(),
-            exit_code: 0,
-            stats: TaskStat::default(),
-            priority: 16,
-            pass: 0,
-        };
         let ret = child_pid.0;
-        let child = Arc::new(TaskControlBlock {
-            pid: child_pid,
-            inner: Mutex::new(child_inner)
-        });
-
+        let child = ProcessControlBlock::new(
+            child_pid,
+            child_addr_space,
+            Some(Arc::downgrade(&self.process)),
+            parent_inner.fd_table.clone(),
+            parent_inner.signals.fork(),
+            parent_inner.sync_table.fork(self.tid),
+            self.tid,
+        );
         parent_inner.children.push(Arc::clone(&child));
-        TASK_MANAGER.lock().add(child);
+        drop(parent_inner);
+        #[cfg(feature = "gdbstub")]
+        drop(breakpoints);
 
-        ret
+        TASK_MANAGER.lock().add(Self::new(child, self.tid, satp));
+        Ok(ret)
+    }
+
+    /// Create a thread in the process, which calls `entry(arg)` on its own user stack.
+    /// Return its tid, or EAGAIN if there are too many threads.
+    pub fn create_thread(&self, entry: usize, arg: usize) -> Result<usize, Errno> {
+        let mut process_inner = self.process.lock();
+        let tid = process_inner.alloc_tid().ok_or(Errno::EAGAIN)?;
+        let ustack_top = match process_inner.addr_space.alloc_thread_stacks(tid) {
+            Ok(ustack_top) => ustack_top,
+            Err(err) => {
+                process_inner.threads[tid] = None;
+                return Err(err);
+            }
+        };
+        let mut trap_cx = TrapContext::app_init_context(entry, ustack_top.0);
+        trap_cx.x[10] = arg;
+        *process_inner.addr_space.trap_cx_mut(tid) = trap_cx;
+        let satp = process_inner.addr_space.satp();
+        drop(process_inner);
+
+        TASK_MANAGER.lock().add(Self::new(Arc::clone(&self.process), tid, satp));
+        Ok(tid)
     }
 }
 
+impl Drop for TaskControlBlock {
+    fn drop(&mut self) {
+        // The thread never runs again, so no one is on its kernel stack.
+        let mut process_inner = self.process.lock();
+        process_inner.addr_space.free_thread_stacks(self.tid);
+        let slot = process_inner.threads[self.tid].as_mut().expect("missing thread slot");
+        slot.released = true;
+        if slot.waited {
+            process_inner.threads[self.tid] = None;
+        }
+    }
+}
+
+/// The ready queue shared by all the harts. The order is up to the scheduler.
 pub struct TaskManager {
-    ready_queue: Vec<Arc<TaskControlBlock>>,
+    scheduler: Box<dyn Scheduler>,
 }
 
 impl TaskManager {
-    pub const fn new() -> Self {
-        Self { ready_queue: Vec::new() }
+    pub fn new() -> Self {
+        Self { scheduler: new_scheduler() }
     }
 
     pub fn add(&mut self, task: Arc<TaskControlBlock>) {
-        if task.inner.lock().status != TaskStatus::Ready {
+        let inner = task.inner.lock();
+        if inner.status != TaskStatus::Ready {
             panic!("try to add a non-ready task");
         }
-        self.ready_queue.push(task);
+        let harts = inner.sched.harts;
+        drop(inner);
+        self.scheduler.add(task);
+        processor::kick_idle_hart(harts);
     }
 
+    /// Take a task for the current hart.
     pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        // It's pretty awkward to do it in functional style.
-        let mut min_pass = u64::MAX;
-        let mut index = None;
-        for (i, t) in self.ready_queue.iter().enumerate() {
-            let inner = t.inner.lock();
-            if inner.pass < min_pass {
-                min_pass = inner.pass;
-                index = Some(i);
-            }
-        }
-        if let Some(index) = index {
-            let task = self.ready_queue.remove(index);
-            Some(task)
-        } else {
-            None
-        }
+        self.scheduler.fetch(hart_id())
+    }
+
+    /// Return true if the running task should be preempted.
+    pub fn on_tick(&mut self, task: &TaskControlBlock) -> bool {
+        self.scheduler.on_tick(task)
     }
+
+    pub fn on_priority_change(&mut self, task: &TaskControlBlock, priority: u64) {
+        self.scheduler.on_priority_change(task, priority);
+    }
+}
+
+/// Zombies that haven't been waited for are found as well.
+pub fn find_process(pid: usize) -> Option<Arc<ProcessControlBlock>> {
+    ProcessControlBlock::find(pid)
 }
 
 // fn finish() -> ! {
@@ -304,76 +475,198 @@ impl TaskManager {
 pub fn set_next_trigger() {
     const TICKS_PER_SEC: usize = 100;
     let current_time = time::get_time();
-    let delta = time::CLOCK_FREQ / TICKS_PER_SEC;
-    // crate::println!("set timer to {}", current_time + delta);
-    sbi::set_timer(current_time + delta);
+    let delta = time::clock_freq() / TICKS_PER_SEC;
+    // Wake up the nearest sleeper on time.
+    let next_trigger = match timer::next_deadline() {
+        Some(deadline) => deadline.min(current_time + delta),
+        None => current_time + delta,
+    };
+    // crate::println!("set timer to {}", next_trigger);
+    sbi::set_timer(next_trigger);
 }
 
 pub fn record_syscall(syscall: usize) {
-    let processor = PROCESSOR.lock();
-    let current_task = processor.current().expect("missing current");
-    current_task.inner.lock().stats.record_syscall(syscall);
+    current_task().inner.lock().stats.record_syscall(syscall);
 }
 
-pub fn run_initproc() {
-    let initproc = Arc::clone(&*INITPROC);
-    let initproc_cx = initproc.inner.lock().schedule_begin();
+/// The current thread traps from the user mode. The time since it returned there is
+/// charged as the user time.
+pub fn record_trap_enter() {
+    let current_task = current_task();
+    let clocks = current_task.inner.lock().stats.take_mode_clocks();
+    current_task.process.usage.add_user_clocks(clocks);
+}
 
-    assert!(PROCESSOR.lock().set_current(initproc).is_none());
+/// The current thread returns to the user mode. The time since it trapped, or was
+/// scheduled, is charged as the kernel time.
+pub fn record_trap_exit() {
+    let current_task = current_task();
+    let clocks = current_task.inner.lock().stats.take_mode_clocks();
+    current_task.process.usage.add_kernel_clocks(clocks);
+}
 
-    set_next_trigger();
-    let mut unused = TaskContext::default();
-    unsafe {
-        __switch(&mut unused, initproc_cx);
-    }
+pub fn add_initproc() {
+    lazy_static::initialize(&INITPROC);
 }
 
+/// Exit the current thread. The process exits along with its main thread.
 pub fn exit_and_run_next(exit_code: i32) {
-    let processor = PROCESSOR.lock();
-    let current_task = processor.current().expect("missing current task");
-    let mut inner = current_task.lock();
-    inner.status = TaskStatus::Zombie;
+    let current_task = current_task();
+    let process = Arc::clone(&current_task.process);
+    if current_task.tid == 0 {
+        exit_process(&process, exit_code);
+    }
+
+    current_task.lock().status = TaskStatus::Zombie;
+    let mut process_inner = process.lock();
+    process_inner.threads[current_task.tid].as_mut().expect("missing thread slot").exit_code = Some(exit_code);
+    drop(process_inner);
+    process.thread_exit.wake_all();
+    drop(process);
+    drop(current_task);
+
+    run_next_task();
+}
+
+/// Kill the current process, e.g. by a fatal signal, along with the current thread.
+pub fn exit_process_and_run_next(exit_code: i32) {
+    exit_process(&current_task().process, exit_code);
+    exit_and_run_next(exit_code);
+}
+
+/// Make the process a zombie, unless it's one already. Its threads exit the next time
+/// they return to the user mode, and the ones in interruptible waits are woken up for it.
+fn exit_process(process: &Arc<ProcessControlBlock>, exit_code: i32) {
+    let mut inner = process.lock();
+    if inner.is_zombie {
+        return;
+    }
+    inner.is_zombie = true;
     inner.exit_code = exit_code;
-    inner.schedule_end();
+    #[cfg(feature = "gdbstub")]
+    crate::gdbstub::on_image_gone(process.pid.0);
+    // Closing files may wake up other tasks, so do it after unlocking.
+    let fd_table = core::mem::take(&mut inner.fd_table);
+    let children = core::mem::take(&mut inner.children);
+    let parent = inner.parent.as_ref().and_then(Weak::upgrade);
+    drop(inner);
+    drop(fd_table);
+    process.interrupt_threads();
 
     // reparent to initproc
-    let initproc = Arc::clone(&*INITPROC);
-    let mut initproc_inner = initproc.inner.lock();
-    for ch in inner.children.drain(..) {
-        ch.inner.lock().parent.replace(Arc::downgrade(&initproc));
-        initproc_inner.children.push(ch);
+    let reparented = !children.is_empty();
+    if reparented {
+        let initproc = Arc::clone(&*INITPROC);
+        let mut initproc_inner = initproc.lock();
+        for ch in children {
+            ch.lock().parent.replace(Arc::downgrade(&initproc));
+            initproc_inner.children.push(ch);
+        }
+        drop(initproc_inner);
+        // Some of the reparented children may have exited already.
+        initproc.child_exit.wake_all();
     }
+    if let Some(parent) = parent {
+        parent.child_exit.wake_all();
+    }
+}
 
-    drop(inner);
-    drop(initproc_inner);
-    drop(initproc);
-    drop(processor);
-
-    run_next_task();
+/// Fetch the next task, waiting for the sleepers if there is no ready task.
+fn fetch_or_idle() -> Arc<TaskControlBlock> {
+    loop {
+        if let Some(task) = TASK_MANAGER.lock().fetch() {
+            return task;
+        }
+        // Check again after being marked idle, so that we won't miss the IPI of a new task.
+        processor::set_idle(true);
+        if let Some(task) = TASK_MANAGER.lock().fetch() {
+            processor::set_idle(false);
+            return task;
+        }
+        // If there is no sleeper, all the tasks are blocked by each other (e.g. on pipes),
+        // or running on other harts, and we can only wait.
+        let deadline = timer::next_deadline();
+        if deadline.map_or(true, |deadline| time::get_time() < deadline) {
+            // Clear the stale timer interrupt if there is no deadline.
+            sbi::set_timer(deadline.unwrap_or(usize::MAX));
+            // The interrupts are disabled in the kernel, but they still wake us up.
+            unsafe {
+                riscv::asm::wfi();
+            }
+        }
+        processor::set_idle(false);
+        clear_ipi();
+        // They aren't taken as traps in the kernel.
+        crate::drivers::irq::handle_pending();
+        wake_expired();
+    }
 }
 
-pub fn run_next_task() {
-    // crate::println!("run next");
-    let mut processor = PROCESSOR.lock();
-    let current_task = processor.take_current().expect("missing current task");
-    let current_cx = current_task.inner.lock().schedule_end();
-
-    let mut task_mgr = TASK_MANAGER.lock();
-    if current_task.is_ready() {
-        task_mgr.add(current_task);
+/// A timer interrupt hits the current task. It goes on running until the scheduler
+/// says its time slice is used up.
+pub fn on_timer_tick() {
+    wake_expired();
+    #[cfg(feature = "gdbstub")]
+    crate::gdbstub::poll();
+    let current_task = current_task();
+    let preempt = TASK_MANAGER.lock().on_tick(&current_task);
+    drop(current_task);
+    if preempt {
+        run_next_task();
     } else {
-        drop(current_task);
+        set_next_trigger();
     }
-    let next_task = task_mgr.fetch().unwrap();
-    let next_cx = next_task.inner.lock().schedule_begin();
+}
 
-    processor.set_current(next_task);
+pub fn clear_ipi() {
+    unsafe {
+        core::arch::asm!("csrci sip, 2");
+    }
+}
 
-    drop(task_mgr);
-    drop(processor);
+/// Switch from the current task to the scheduling loop of the hart.
+/// It returns when the task is scheduled again, maybe on another hart.
+pub fn run_next_task() {
+    let mut this_processor = processor().lock();
+    let current_task = this_processor.current().expect("missing current task");
+    let current_cx = current_task.inner.lock().schedule_end(&current_task.process.usage);
+    let idle_cx = this_processor.idle_cx_ptr();
+    drop(this_processor);
 
-    set_next_trigger();
     unsafe {
-        __switch(current_cx, next_cx);
+        __switch(current_cx, idle_cx);
+    }
+}
+
+/// The scheduling loop of the current hart.
+pub fn run_tasks() -> ! {
+    loop {
+        let next_task = fetch_or_idle();
+        let next_cx = next_task.inner.lock().schedule_begin();
+
+        let mut this_processor = processor().lock();
+        this_processor.set_current(next_task);
+        let idle_cx = this_processor.idle_cx_ptr();
+        drop(this_processor);
+
+        set_next_trigger();
+        unsafe {
+            __switch(idle_cx, next_cx);
+        }
+
+        // The task has switched out, and no one else is using its kernel stack now.
+        let mut this_processor = processor().lock();
+        let prev_task = this_processor.take_current().expect("missing current task");
+        let retired_addr_space = this_processor.take_retired_addr_space();
+        drop(this_processor);
+        drop(retired_addr_space);
+        let mut prev_inner = prev_task.inner.lock();
+        prev_inner.on_cpu = false;
+        // Blocked ones are put back by whoever wakes them up.
+        let ready = prev_inner.status == TaskStatus::Ready;
+        drop(prev_inner);
+        if ready {
+            TASK_MANAGER.lock().add(prev_task);
+        }
     }
 }
@@ -2,7 +2,10 @@ use core::arch::global_asm;
 use core::ffi::CStr;
 use core::ffi::c_char;
 use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
 use lazy_static::lazy_static;
+use crate::fs::{open_file, OpenFlags};
+use crate::syscall::Errno;
 
 global_asm!(include_str!("../link_app.S"));
 extern "C" {
@@ -14,10 +17,17 @@ lazy_static! {
     static ref ELF_LOADER: ElfLoader = unsafe { ElfLoader::new() };
 }
 
+/// Get the app embedded in the kernel image by link_app.S.
 pub fn get_app_data(name: &str) -> Option<&'static [u8]> {
     ELF_LOADER.get_elf(name)
 }
 
+/// Read the whole app from the root fs. `path` is resolved from the root directory.
+pub fn load_app(path: &str) -> Result<Vec<u8>, Errno> {
+    let inode = open_file(path, OpenFlags::RDONLY)?;
+    Ok(inode.read_all())
+}
+
 struct ElfLoader {
     elfs: BTreeMap<&'static str, &'static [u8]>
 }
@@ -0,0 +1,177 @@
+use super::pid::Pid;
+use super::rusage::{ResourceUsage, UsageSnapshot};
+use super::signal::SignalState;
+use super::{interrupt, TaskControlBlock, WaitQueue};
+use crate::config::MAX_THREADS;
+use crate::fs::FdTable;
+use crate::mm::address_space::AddressSpace;
+use crate::sync::SyncTable;
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::{Mutex, MutexGuard};
+
+/// All the processes that haven't been dropped, keyed by the pid, including the zombies.
+static PROCESS_TABLE: Mutex<BTreeMap<usize, Weak<ProcessControlBlock>>> = Mutex::new(BTreeMap::new());
+
+/// What the threads of a process share.
+#[derive(Debug)]
+pub struct ProcessControlBlock {
+    pub pid: Pid,
+    /// Where the threads sleep in waitpid until a child exits.
+    pub child_exit: WaitQueue,
+    /// Where the threads sleep in waittid until a thread exits.
+    pub thread_exit: WaitQueue,
+    /// Charged by the threads as they run.
+    pub usage: ResourceUsage,
+    /// Of the reaped children, and their reaped children in turn.
+    pub children_usage: ResourceUsage,
+    inner: Mutex<ProcessControlBlockInner>,
+}
+
+#[derive(Debug)]
+pub struct ProcessControlBlockInner {
+    /// Set when the main thread exits, or the process is killed.
+    /// The other threads exit the next time they return to the user mode.
+    pub is_zombie: bool,
+    pub addr_space: AddressSpace,
+
+    pub children: Vec<Arc<ProcessControlBlock>>,
+    pub parent: Option<Weak<ProcessControlBlock>>,
+    pub exit_code: i32,
+
+    pub fd_table: FdTable,
+
+    pub signals: SignalState,
+
+    pub sync_table: SyncTable,
+
+    /// Indexed by the tid. None if the tid is free.
+    pub threads: Vec<Option<ThreadSlot>>,
+}
+
+/// A tid in use. It's freed after the thread is both waited for and released.
+#[derive(Debug, Default)]
+pub struct ThreadSlot {
+    /// Set when the thread exits, and taken by waittid.
+    pub exit_code: Option<i32>,
+    pub waited: bool,
+    /// The stacks of the thread are freed, which is done once it never runs again.
+    pub released: bool,
+    /// Set once the thread is created.
+    pub task: Weak<TaskControlBlock>,
+}
+
+impl ProcessControlBlock {
+    /// The process starts with the thread `tid`, whose stacks are in `addr_space`.
+    pub fn new(
+        pid: Pid,
+        addr_space: AddressSpace,
+        parent: Option<Weak<ProcessControlBlock>>,
+        fd_table: FdTable,
+        signals: SignalState,
+        sync_table: SyncTable,
+        tid: usize,
+    ) -> Arc<Self> {
+        let mut threads: Vec<Option<ThreadSlot>> = vec![];
+        threads.resize_with(tid + 1, || None);
+        threads[tid] = Some(ThreadSlot::default());
+
+        let inner = ProcessControlBlockInner {
+            is_zombie: false,
+            addr_space,
+            children: Vec::new(),
+            parent,
+            exit_code: 0,
+            fd_table,
+            signals,
+            sync_table,
+            threads,
+        };
+        let process = Arc::new(Self {
+            pid,
+            child_exit: WaitQueue::new(),
+            thread_exit: WaitQueue::new(),
+            usage: ResourceUsage::default(),
+            children_usage: ResourceUsage::default(),
+            inner: Mutex::new(inner),
+        });
+        PROCESS_TABLE.lock().insert(process.pid.0, Arc::downgrade(&process));
+        process
+    }
+
+    /// Find the process in the process table.
+    pub fn find(pid: usize) -> Option<Arc<Self>> {
+        let table = PROCESS_TABLE.lock();
+        table.get(&pid).and_then(Weak::upgrade)
+    }
+
+    /// All the processes, ordered by the pid.
+    pub fn all() -> Vec<Arc<Self>> {
+        let table = PROCESS_TABLE.lock();
+        table.values().filter_map(Weak::upgrade).collect()
+    }
+
+    /// Threads that haven't been released. They may be gone already.
+    pub fn threads(&self) -> Vec<Weak<TaskControlBlock>> {
+        self.lock().threads.iter()
+            .flatten()
+            .filter(|slot| !slot.released)
+            .map(|slot| slot.task.clone())
+            .collect()
+    }
+
+    pub fn lock<'a>(&'a self) -> MutexGuard<'a, ProcessControlBlockInner> {
+        self.inner.lock()
+    }
+
+    pub fn is_zombie(&self) -> bool {
+        self.lock().is_zombie
+    }
+
+    /// Wake up the threads in interruptible waits, to see a signal or the exit.
+    /// The process must not be locked.
+    pub fn interrupt_threads(&self) {
+        let threads: Vec<_> = self.lock().threads.iter()
+            .filter_map(|slot| slot.as_ref()?.task.upgrade())
+            .collect();
+        threads.iter().for_each(interrupt);
+    }
+
+    /// Usage of the process itself, with the peak of the current address space.
+    pub fn usage(&self) -> UsageSnapshot {
+        let mut usage = self.usage.snapshot();
+        usage.max_frames = usage.max_frames.max(self.lock().addr_space.peak_frames());
+        usage
+    }
+}
+
+impl Drop for ProcessControlBlock {
+    fn drop(&mut self) {
+        PROCESS_TABLE.lock().remove(&self.pid.0);
+    }
+}
+
+impl ProcessControlBlockInner {
+    /// Take the smallest free tid. Return None if there are MAX_THREADS already.
+    pub fn alloc_tid(&mut self) -> Option<usize> {
+        let tid = match self.threads.iter().position(Option::is_none) {
+            Some(tid) => tid,
+            None if self.threads.len() < MAX_THREADS => {
+                self.threads.push(None);
+                self.threads.len() - 1
+            }
+            None => return None,
+        };
+        self.threads[tid] = Some(ThreadSlot::default());
+        Some(tid)
+    }
+
+    /// Threads that may still run, or are still switching out, other than `tid`.
+    pub fn has_other_threads(&self, tid: usize) -> bool {
+        self.threads.iter().enumerate().any(|(other, slot)| {
+            other != tid && slot.as_ref().map_or(false, |slot| !slot.released)
+        })
+    }
+}
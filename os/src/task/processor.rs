@@ -1,15 +1,39 @@
-use super::TaskControlBlock;
+use super::{ProcessControlBlock, TaskContext, TaskControlBlock};
+use crate::config::MAX_HARTS;
+use crate::mm::address_space::AddressSpace;
+use crate::sbi;
+use crate::smp::hart_id;
 use spin::Mutex;
 use core::option::Option;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use alloc::sync::Arc;
 
-pub static PROCESSOR: Mutex<Processor> = Mutex::new(Processor{ current: None });
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_PROCESSOR: Mutex<Processor> = Mutex::new(Processor::new());
+// Indexed by the hart id. Each one is only touched by its own hart.
+static PROCESSORS: [Mutex<Processor>; MAX_HARTS] = [EMPTY_PROCESSOR; MAX_HARTS];
+
+/// Bit i is set if hart i is waiting for a ready task.
+static IDLE_HARTS: AtomicUsize = AtomicUsize::new(0);
 
 pub struct Processor {
     current: Option<Arc<TaskControlBlock>>,
+    /// Where the hart runs the scheduling loop.
+    idle_cx: TaskContext,
+    /// Replaced by exec while the task was still on its kernel stack.
+    /// It's dropped once the task has switched out.
+    retired_addr_space: Option<AddressSpace>,
 }
 
 impl Processor {
+    const fn new() -> Self {
+        Self {
+            current: None,
+            idle_cx: TaskContext::zero(),
+            retired_addr_space: None,
+        }
+    }
+
     pub fn take_current(&mut self) -> Option<Arc<TaskControlBlock>> {
         // crate::println!("current taken");
         self.current.take()
@@ -23,4 +47,45 @@ impl Processor {
         // crate::println!("current set");
         self.current.replace(task)
     }
-}
\ No newline at end of file
+
+    pub fn retire_addr_space(&mut self, addr_space: AddressSpace) {
+        assert!(self.retired_addr_space.replace(addr_space).is_none());
+    }
+
+    pub fn take_retired_addr_space(&mut self) -> Option<AddressSpace> {
+        self.retired_addr_space.take()
+    }
+
+    pub fn idle_cx_ptr(&mut self) -> *mut TaskContext {
+        &mut self.idle_cx as *mut TaskContext
+    }
+}
+
+/// The processor of the current hart.
+pub fn processor() -> &'static Mutex<Processor> {
+    &PROCESSORS[hart_id()]
+}
+
+pub fn current_task() -> Arc<TaskControlBlock> {
+    processor().lock().current().expect("missing current").clone()
+}
+
+pub fn current_process() -> Arc<ProcessControlBlock> {
+    Arc::clone(&current_task().process)
+}
+
+pub fn set_idle(idle: bool) {
+    if idle {
+        IDLE_HARTS.fetch_or(1 << hart_id(), Ordering::SeqCst);
+    } else {
+        IDLE_HARTS.fetch_and(!(1 << hart_id()), Ordering::SeqCst);
+    }
+}
+
+/// Send an IPI to an idle hart among `harts`, if any, so that it picks up the new ready task.
+pub fn kick_idle_hart(harts: usize) {
+    let idle_harts = IDLE_HARTS.load(Ordering::SeqCst) & harts & !(1 << hart_id());
+    if idle_harts != 0 {
+        sbi::send_ipi(1 << idle_harts.trailing_zeros());
+    }
+}
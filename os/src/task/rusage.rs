@@ -0,0 +1,89 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Resource usage of a process, summed over its threads. The counters are atomic, so
+/// that a thread can be charged without the process lock, e.g. while switching out.
+#[derive(Debug, Default)]
+pub struct ResourceUsage {
+    user_clocks: AtomicUsize,
+    kernel_clocks: AtomicUsize,
+    /// Peak resident frames of the address spaces that are gone, e.g. by exec.
+    /// The current one keeps its own peak.
+    max_frames: AtomicUsize,
+    /// Page faults resolved by the kernel, i.e. lazy allocation and COW.
+    page_faults: AtomicUsize,
+    /// Switched out to wait for something.
+    voluntary_switches: AtomicUsize,
+    /// Preempted, or yielded.
+    involuntary_switches: AtomicUsize,
+}
+
+/// The counters of a ResourceUsage at some point.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UsageSnapshot {
+    pub user_clocks: usize,
+    pub kernel_clocks: usize,
+    pub max_frames: usize,
+    pub page_faults: usize,
+    pub voluntary_switches: usize,
+    pub involuntary_switches: usize,
+}
+
+impl ResourceUsage {
+    pub fn add_user_clocks(&self, clocks: usize) {
+        self.user_clocks.fetch_add(clocks, Ordering::Relaxed);
+    }
+
+    pub fn add_kernel_clocks(&self, clocks: usize) {
+        self.kernel_clocks.fetch_add(clocks, Ordering::Relaxed);
+    }
+
+    pub fn record_frames(&self, frames: usize) {
+        self.max_frames.fetch_max(frames, Ordering::Relaxed);
+    }
+
+    pub fn add_page_fault(&self) {
+        self.page_faults.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_switch(&self, voluntary: bool) {
+        let counter = if voluntary {
+            &self.voluntary_switches
+        } else {
+            &self.involuntary_switches
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> UsageSnapshot {
+        UsageSnapshot {
+            user_clocks: self.user_clocks.load(Ordering::Relaxed),
+            kernel_clocks: self.kernel_clocks.load(Ordering::Relaxed),
+            max_frames: self.max_frames.load(Ordering::Relaxed),
+            page_faults: self.page_faults.load(Ordering::Relaxed),
+            voluntary_switches: self.voluntary_switches.load(Ordering::Relaxed),
+            involuntary_switches: self.involuntary_switches.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Add the usage of a reaped child. The peak is the largest one, not the sum.
+    pub fn accumulate(&self, usage: &UsageSnapshot) {
+        self.add_user_clocks(usage.user_clocks);
+        self.add_kernel_clocks(usage.kernel_clocks);
+        self.record_frames(usage.max_frames);
+        self.page_faults.fetch_add(usage.page_faults, Ordering::Relaxed);
+        self.voluntary_switches.fetch_add(usage.voluntary_switches, Ordering::Relaxed);
+        self.involuntary_switches.fetch_add(usage.involuntary_switches, Ordering::Relaxed);
+    }
+}
+
+impl UsageSnapshot {
+    pub fn merge(mut self, other: &UsageSnapshot) -> Self {
+        self.user_clocks += other.user_clocks;
+        self.kernel_clocks += other.kernel_clocks;
+        self.max_frames = self.max_frames.max(other.max_frames);
+        self.page_faults += other.page_faults;
+        self.voluntary_switches += other.voluntary_switches;
+        self.involuntary_switches += other.involuntary_switches;
+        self
+    }
+}
@@ -0,0 +1,85 @@
+mod cfs;
+mod mlfq;
+mod round_robin;
+mod stride;
+
+use super::TaskControlBlock;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+
+pub use cfs::CfsScheduler;
+pub use mlfq::MlfqScheduler;
+pub use round_robin::RoundRobinScheduler;
+pub use stride::StrideScheduler;
+
+/// Priority of a new task. A larger one means more cpu time, for the policies that care.
+pub const DEFAULT_PRIORITY: u64 = 16;
+
+/// Scheduling state of a task. Each policy only uses some of the fields.
+#[derive(Debug, Clone)]
+pub struct SchedEntity {
+    pub priority: u64,
+    /// Clocks spent in the last run. It's charged when the task is added back.
+    pub last_run: usize,
+    /// Stride: the smallest one runs first. None until the task is first added.
+    pub pass: Option<u64>,
+    /// CFS: weighted cpu time. The smallest one runs first.
+    pub vruntime: u64,
+    /// MLFQ: the queue, where 0 is the highest.
+    pub level: usize,
+    /// MLFQ: ticks used at the level.
+    pub ticks: usize,
+    /// Bit i is set if the task may run on hart i.
+    pub harts: usize,
+}
+
+impl Default for SchedEntity {
+    fn default() -> Self {
+        Self {
+            priority: DEFAULT_PRIORITY,
+            last_run: 0,
+            pass: None,
+            vruntime: 0,
+            level: 0,
+            ticks: 0,
+            harts: usize::MAX,
+        }
+    }
+}
+
+/// A policy of picking the next ready task. Shared by all the harts.
+pub trait Scheduler: Send {
+    /// The task is ready. It may have just run, been woken up, or been created.
+    fn add(&mut self, task: Arc<TaskControlBlock>);
+
+    /// Take the next task to run on the hart, skipping the ones not allowed there.
+    fn fetch(&mut self, hart: usize) -> Option<Arc<TaskControlBlock>>;
+
+    /// A timer tick hits the running task. Return true to preempt it.
+    fn on_tick(&mut self, _task: &TaskControlBlock) -> bool {
+        true
+    }
+
+    /// The running task changes its priority.
+    fn on_priority_change(&mut self, task: &TaskControlBlock, priority: u64) {
+        task.lock().sched.priority = priority;
+    }
+}
+
+/// Whether the affinity of the task allows the hart.
+fn runs_on(task: &TaskControlBlock, hart: usize) -> bool {
+    task.lock().sched.harts & (1 << hart) != 0
+}
+
+/// Stride by default. The others are picked by the `sched-*` features.
+pub fn new_scheduler() -> Box<dyn Scheduler> {
+    if cfg!(feature = "sched-rr") {
+        Box::new(RoundRobinScheduler::new())
+    } else if cfg!(feature = "sched-cfs") {
+        Box::new(CfsScheduler::new())
+    } else if cfg!(feature = "sched-mlfq") {
+        Box::new(MlfqScheduler::new())
+    } else {
+        Box::new(StrideScheduler::new())
+    }
+}
@@ -0,0 +1,47 @@
+use super::{runs_on, Scheduler, DEFAULT_PRIORITY};
+use crate::task::TaskControlBlock;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+
+/// A task of the default priority gains vruntime as fast as the real time.
+const NICE_0_WEIGHT: u64 = DEFAULT_PRIORITY;
+
+/// Completely fair scheduling. The priority is the weight, and the task with the least
+/// weighted cpu time runs first.
+#[derive(Default)]
+pub struct CfsScheduler {
+    /// Keyed by (vruntime, seq), so ties are broken in FIFO order.
+    ready_queue: BTreeMap<(u64, u64), Arc<TaskControlBlock>>,
+    next_seq: u64,
+    /// Never goes backward. A task which has slept or is new starts from here,
+    /// instead of catching up for the time it wasn't ready.
+    min_vruntime: u64,
+}
+
+impl CfsScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Scheduler for CfsScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let mut inner = task.lock();
+        let sched = &mut inner.sched;
+        let ran = core::mem::take(&mut sched.last_run) as u64;
+        sched.vruntime += ran * NICE_0_WEIGHT / sched.priority;
+        sched.vruntime = sched.vruntime.max(self.min_vruntime);
+        let key = (sched.vruntime, self.next_seq);
+        drop(inner);
+
+        self.next_seq += 1;
+        self.ready_queue.insert(key, task);
+    }
+
+    fn fetch(&mut self, hart: usize) -> Option<Arc<TaskControlBlock>> {
+        let key = *self.ready_queue.iter().find(|(_, task)| runs_on(task, hart))?.0;
+        let task = self.ready_queue.remove(&key)?;
+        self.min_vruntime = self.min_vruntime.max(key.0);
+        Some(task)
+    }
+}
@@ -0,0 +1,73 @@
+use super::{runs_on, Scheduler};
+use crate::task::TaskControlBlock;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+const LEVELS: usize = 4;
+/// Every task is moved back to the top level this often, so the long-running ones
+/// don't starve.
+const BOOST_TICKS: usize = 100;
+
+/// Ticks a task may use at the level before it's moved down.
+fn time_slice(level: usize) -> usize {
+    1 << level
+}
+
+/// Multi-level feedback queue. New tasks start at the top level, and sink as they use
+/// up their time slices. The priority isn't used.
+#[derive(Default)]
+pub struct MlfqScheduler {
+    queues: [VecDeque<Arc<TaskControlBlock>>; LEVELS],
+    ticks_since_boost: usize,
+}
+
+impl MlfqScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn boost(&mut self) {
+        for level in 1..LEVELS {
+            let tasks = core::mem::take(&mut self.queues[level]);
+            for task in tasks {
+                let mut inner = task.lock();
+                inner.sched.level = 0;
+                inner.sched.ticks = 0;
+                drop(inner);
+                self.queues[0].push_back(task);
+            }
+        }
+        self.ticks_since_boost = 0;
+    }
+}
+
+impl Scheduler for MlfqScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let level = task.lock().sched.level.min(LEVELS - 1);
+        self.queues[level].push_back(task);
+    }
+
+    fn fetch(&mut self, hart: usize) -> Option<Arc<TaskControlBlock>> {
+        if self.ticks_since_boost >= BOOST_TICKS {
+            self.boost();
+        }
+        self.queues.iter_mut().find_map(|queue| {
+            let index = queue.iter().position(|task| runs_on(task, hart))?;
+            queue.remove(index)
+        })
+    }
+
+    fn on_tick(&mut self, task: &TaskControlBlock) -> bool {
+        self.ticks_since_boost += 1;
+        let mut inner = task.lock();
+        let sched = &mut inner.sched;
+        sched.ticks += 1;
+        if sched.ticks < time_slice(sched.level) {
+            return false;
+        }
+        // The time slice is used up, even if it yielded in between.
+        sched.ticks = 0;
+        sched.level = (sched.level + 1).min(LEVELS - 1);
+        true
+    }
+}
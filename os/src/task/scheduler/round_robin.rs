@@ -0,0 +1,27 @@
+use super::{runs_on, Scheduler};
+use crate::task::TaskControlBlock;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+/// First come, first served, one tick at a time.
+#[derive(Default)]
+pub struct RoundRobinScheduler {
+    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl RoundRobinScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Scheduler for RoundRobinScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.ready_queue.push_back(task);
+    }
+
+    fn fetch(&mut self, hart: usize) -> Option<Arc<TaskControlBlock>> {
+        let index = self.ready_queue.iter().position(|task| runs_on(task, hart))?;
+        self.ready_queue.remove(index)
+    }
+}
@@ -0,0 +1,100 @@
+use super::{runs_on, Scheduler};
+use crate::task::TaskControlBlock;
+use alloc::collections::BinaryHeap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp::{Ordering, Reverse};
+
+/// The pass of a task grows by STRIDE / priority each time it's picked.
+/// The priority is at least 2, so a stride is at most STRIDE / 2.
+const STRIDE: u64 = 10007;
+
+/// Compare the passes, which may have wrapped around.
+///
+/// The passes of the ready tasks are never more than a stride apart, since a task is
+/// picked only if it has the smallest pass, and a task coming back is moved up to the
+/// smallest one. So the wrapped difference tells which one is behind.
+fn cmp_pass(a: u64, b: u64) -> Ordering {
+    (a.wrapping_sub(b) as i64).cmp(&0)
+}
+
+struct Entry {
+    pass: u64,
+    /// Ties are broken in FIFO order.
+    seq: u64,
+    task: Arc<TaskControlBlock>,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        cmp_pass(self.pass, other.pass).then(self.seq.cmp(&other.seq))
+    }
+}
+
+/// The task with the smallest pass runs first.
+#[derive(Default)]
+pub struct StrideScheduler {
+    ready_queue: BinaryHeap<Reverse<Entry>>,
+    next_seq: u64,
+    /// Pass of the task picked last, which was the smallest one allowed on its hart.
+    min_pass: u64,
+}
+
+impl StrideScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Scheduler for StrideScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let mut inner = task.lock();
+        // A new task starts from the smallest pass instead of 0, or it would run alone
+        // until it catches up. So does a task that has slept, and it doesn't get the
+        // time it missed back.
+        let pass = match inner.sched.pass {
+            Some(pass) if cmp_pass(pass, self.min_pass).is_ge() => pass,
+            _ => self.min_pass,
+        };
+        inner.sched.pass = Some(pass);
+        drop(inner);
+
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.ready_queue.push(Reverse(Entry { pass, seq, task }));
+    }
+
+    fn fetch(&mut self, hart: usize) -> Option<Arc<TaskControlBlock>> {
+        // The skipped ones keep their places.
+        let mut skipped = Vec::new();
+        let found = loop {
+            match self.ready_queue.pop() {
+                Some(Reverse(entry)) if runs_on(&entry.task, hart) => break Some(entry),
+                Some(entry) => skipped.push(entry),
+                None => break None,
+            }
+        };
+        self.ready_queue.extend(skipped);
+        let entry = found?;
+        self.min_pass = entry.pass;
+        let mut inner = entry.task.lock();
+        let stride = STRIDE / inner.sched.priority;
+        inner.sched.pass = Some(entry.pass.wrapping_add(stride));
+        drop(inner);
+        Some(entry.task)
+    }
+}
@@ -0,0 +1,310 @@
+use super::{current_task, exit_and_run_next, exit_process_and_run_next, ProcessControlBlock};
+use crate::mm::user_ptr::{AccessError, Pod, UserPtr};
+use crate::trap::{trap_cx_va, TrapContext};
+use bitflags::bitflags;
+use core::mem::size_of;
+
+/// Signals are numbered from 1 to MAX_SIG. The numbers are the same as Linux.
+pub const MAX_SIG: usize = 31;
+
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGBUS: usize = 7;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGSTKFLT: usize = 16;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGTTIN: usize = 21;
+pub const SIGTTOU: usize = 22;
+pub const SIGURG: usize = 23;
+pub const SIGXCPU: usize = 24;
+pub const SIGXFSZ: usize = 25;
+pub const SIGVTALRM: usize = 26;
+pub const SIGPROF: usize = 27;
+pub const SIGWINCH: usize = 28;
+pub const SIGIO: usize = 29;
+pub const SIGPWR: usize = 30;
+pub const SIGSYS: usize = 31;
+
+/// Special values of `SignalAction::handler`.
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+/// A set of signals. Bit `sig - 1` is for the signal `sig`, same as the Linux sigset_t.
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SignalSet(pub u64);
+
+unsafe impl Pod for SignalSet {}
+
+impl SignalSet {
+    /// The signals that can't be caught, blocked or ignored.
+    pub const UNCATCHABLE: Self = Self((1 << (SIGKILL - 1)) | (1 << (SIGSTOP - 1)));
+
+    pub fn contains(self, sig: usize) -> bool {
+        self.0 & (1 << (sig - 1)) != 0
+    }
+
+    pub fn insert(&mut self, sig: usize) {
+        self.0 |= 1 << (sig - 1);
+    }
+
+    pub fn remove(&mut self, sig: usize) {
+        self.0 &= !(1 << (sig - 1));
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    /// The smallest signal in the set.
+    pub fn first(self) -> Option<usize> {
+        if self.0 == 0 {
+            None
+        } else {
+            Some(self.0.trailing_zeros() as usize + 1)
+        }
+    }
+
+    /// Drop the bits that can't be in a blocked mask.
+    pub fn blockable(self) -> Self {
+        Self(self.0 & ((1 << MAX_SIG) - 1)).difference(Self::UNCATCHABLE)
+    }
+}
+
+bitflags! {
+    /// Same values as Linux.
+    pub struct SignalActionFlags: usize {
+        /// `restorer` is set. We always need it, but it's accepted for compatibility.
+        const RESTORER = 0x0400_0000;
+        /// Don't block the signal in its handler.
+        const NODEFER = 0x4000_0000;
+        /// Reset to the default action once the handler is called.
+        const RESETHAND = 0x8000_0000;
+    }
+}
+
+/// Same layout as the `SignalAction` of the user.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SignalAction {
+    /// SIG_DFL, SIG_IGN, or the address of `extern "C" fn(sig: i32)`.
+    pub handler: usize,
+    pub flags: usize,
+    /// Where the handler returns to. It should call sigreturn.
+    pub restorer: usize,
+    /// Signals blocked in the handler, in addition to the signal itself.
+    pub mask: SignalSet,
+}
+
+// The flags are checked when used, so any bits will do.
+unsafe impl Pod for SignalAction {}
+
+impl SignalAction {
+    fn flags(&self) -> SignalActionFlags {
+        SignalActionFlags::from_bits_truncate(self.flags)
+    }
+}
+
+enum DefaultAction {
+    Terminate,
+    Ignore,
+}
+
+fn default_action(sig: usize) -> DefaultAction {
+    match sig {
+        // There is no job control, so the stop signals are ignored as well.
+        SIGCHLD | SIGCONT | SIGURG | SIGWINCH | SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Ignore,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+/// Signal state of a process, shared by its threads.
+#[derive(Debug, Clone, Default)]
+pub struct SignalState {
+    pub pending: SignalSet,
+    pub blocked: SignalSet,
+    /// Indexed by the signal. The 0th one is unused.
+    pub actions: [SignalAction; MAX_SIG + 1],
+}
+
+impl SignalState {
+    /// The child inherits the actions and the blocked mask, but not the pending signals.
+    pub fn fork(&self) -> Self {
+        Self {
+            pending: SignalSet::default(),
+            blocked: self.blocked,
+            actions: self.actions,
+        }
+    }
+
+    /// The handlers are gone with the old image. Ignored signals stay ignored.
+    pub fn exec(&mut self) {
+        for action in self.actions.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SignalAction::default();
+            }
+        }
+    }
+}
+
+/// Saved on the user stack when a handler is called, and restored by sigreturn.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SignalFrame {
+    x: [usize; 32],
+    sepc: usize,
+    blocked: SignalSet,
+}
+
+unsafe impl Pod for SignalFrame {}
+
+/// Exit code of a process killed by the signal.
+pub fn exit_code_of(sig: usize) -> i32 {
+    -(sig as i32)
+}
+
+pub fn is_valid(sig: usize) -> bool {
+    (1..=MAX_SIG).contains(&sig)
+}
+
+/// Make the signal pending on the process. It's delivered the next time any of its threads
+/// returns to the user mode. The threads in interruptible waits are woken up for it.
+pub fn send_signal(process: &ProcessControlBlock, sig: usize) {
+    process.lock().signals.pending.insert(sig);
+    process.interrupt_threads();
+}
+
+/// Whether the process has a signal that handle_signals won't skip, or it has exited.
+/// An interruptible wait gives up for it.
+pub fn has_signal_to_deliver(process: &ProcessControlBlock) -> bool {
+    let process_inner = process.lock();
+    if process_inner.is_zombie {
+        return true;
+    }
+    let signals = &process_inner.signals;
+    let mut deliverable = signals.pending.difference(signals.blocked);
+    while let Some(sig) = deliverable.first() {
+        let ignored = match signals.actions[sig].handler {
+            SIG_IGN => true,
+            SIG_DFL => matches!(default_action(sig), DefaultAction::Ignore),
+            _ => false,
+        };
+        if !ignored {
+            return true;
+        }
+        deliverable.remove(sig);
+    }
+    false
+}
+
+/// Send a signal caused by the current task itself, e.g. a page fault.
+/// It can't be blocked or ignored, or the task would just fault again.
+pub fn force_signal(sig: usize) {
+    let current_task = current_task();
+    let mut process_inner = current_task.process.lock();
+    let signals = &mut process_inner.signals;
+    if signals.blocked.contains(sig) || signals.actions[sig].handler == SIG_IGN {
+        signals.blocked.remove(sig);
+        signals.actions[sig] = SignalAction::default();
+    }
+    signals.pending.insert(sig);
+}
+
+/// Deliver a pending signal of the current process before the thread returns to the user
+/// mode. If there is a handler, it's called with the context saved on the user stack.
+/// The thread exits instead if the process is gone.
+pub fn handle_signals(cx: &mut TrapContext) {
+    let current_task = current_task();
+    let mut process_inner = current_task.process.lock();
+    if process_inner.is_zombie {
+        let exit_code = process_inner.exit_code;
+        drop(process_inner);
+        drop(current_task);
+        exit_and_run_next(exit_code);
+        return;
+    }
+    let fatal_sig = loop {
+        let signals = &mut process_inner.signals;
+        let sig = match signals.pending.difference(signals.blocked).first() {
+            Some(sig) => sig,
+            None => return,
+        };
+        signals.pending.remove(sig);
+        let action = signals.actions[sig];
+
+        match action.handler {
+            SIG_IGN => continue,
+            SIG_DFL => match default_action(sig) {
+                DefaultAction::Ignore => continue,
+                DefaultAction::Terminate => break sig,
+            },
+            handler => {
+                let frame = SignalFrame {
+                    x: cx.x,
+                    sepc: cx.sepc,
+                    blocked: signals.blocked,
+                };
+                // Keep the stack 16-byte aligned as the ABI requires.
+                let sp = cx.x[2].wrapping_sub(size_of::<SignalFrame>()) & !0xf;
+                if UserPtr::new(sp).write(&mut process_inner.addr_space, &frame).is_err() {
+                    // No room for the frame. There is nothing the process can do about it.
+                    break SIGSEGV;
+                }
+
+                let signals = &mut process_inner.signals;
+                if action.flags().contains(SignalActionFlags::RESETHAND) {
+                    signals.actions[sig] = SignalAction::default();
+                }
+                signals.blocked = signals.blocked.union(action.mask);
+                if !action.flags().contains(SignalActionFlags::NODEFER) {
+                    signals.blocked.insert(sig);
+                }
+                signals.blocked = signals.blocked.blockable();
+
+                cx.x[1] = action.restorer;
+                cx.x[2] = sp;
+                cx.x[10] = sig;
+                cx.sepc = handler;
+                return;
+            }
+        }
+    };
+
+    drop(process_inner);
+    drop(current_task);
+    exit_process_and_run_next(exit_code_of(fatal_sig));
+}
+
+/// Restore the context saved by handle_signals.
+/// Return the restored a0, so that the return value of the syscall doesn't clobber it.
+pub fn sigreturn() -> Result<isize, AccessError> {
+    let current_task = current_task();
+    // The trap context of the current thread is on its kernel stack, which is mapped here.
+    let cx = unsafe { &mut *(trap_cx_va(current_task.tid).0 as *mut TrapContext) };
+    let mut process_inner = current_task.process.lock();
+    // The handler has returned, so sp is back to the frame.
+    let frame = UserPtr::<SignalFrame>::new(cx.x[2]).read(&mut process_inner.addr_space)?;
+    // sstatus isn't restored, or the user could return to the supervisor mode.
+    cx.x = frame.x;
+    cx.sepc = frame.sepc;
+    process_inner.signals.blocked = frame.blocked.blockable();
+    Ok(cx.x[10] as isize)
+}
@@ -0,0 +1,83 @@
+use super::{current_task, run_next_task, TaskControlBlock, TaskStatus};
+use super::signal::has_signal_to_deliver;
+use super::wait_queue::wake_up;
+use crate::syscall::Errno;
+use crate::time;
+use alloc::collections::BinaryHeap;
+use alloc::sync::Arc;
+use core::cmp::Ordering;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+lazy_static! {
+    static ref SLEEPERS: Mutex<BinaryHeap<Sleeper>> = Mutex::new(BinaryHeap::new());
+}
+
+struct Sleeper {
+    deadline: usize,
+    task: Arc<TaskControlBlock>,
+}
+
+// Reversed, so that the heap pops the nearest deadline first.
+impl Ord for Sleeper {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.deadline.cmp(&self.deadline)
+    }
+}
+
+impl PartialOrd for Sleeper {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Sleeper {
+    fn eq(&self, other: &Self) -> bool {
+        self.deadline == other.deadline
+    }
+}
+
+impl Eq for Sleeper {}
+
+/// Block the current task until `time::get_time()` reaches the deadline.
+/// Like WaitQueue::wait_until_interruptible, it gives up with EINTR if a signal is to be
+/// delivered to the process.
+pub fn sleep_until(deadline: usize) -> Result<(), Errno> {
+    let current_task = current_task();
+    loop {
+        if time::get_time() >= deadline {
+            return Ok(());
+        }
+        if has_signal_to_deliver(&current_task.process) {
+            return Err(Errno::EINTR);
+        }
+        let mut sleepers = SLEEPERS.lock();
+        let mut inner = current_task.lock();
+        inner.status = TaskStatus::Blocked;
+        inner.interruptible = true;
+        drop(inner);
+        sleepers.push(Sleeper {
+            deadline,
+            task: Arc::clone(&current_task),
+        });
+        drop(sleepers);
+        run_next_task();
+        // It's still in the heap if it's woken up by interrupt. A stale entry would wake
+        // it up from whatever it blocks on next.
+        SLEEPERS.lock().retain(|s| !Arc::ptr_eq(&s.task, &current_task));
+    }
+}
+
+/// Move the sleepers whose deadline has passed back to the ready queue.
+pub fn wake_expired() {
+    let now = time::get_time();
+    let mut sleepers = SLEEPERS.lock();
+    while sleepers.peek().map_or(false, |s| s.deadline <= now) {
+        let sleeper = sleepers.pop().unwrap();
+        wake_up(sleeper.task);
+    }
+}
+
+pub fn next_deadline() -> Option<usize> {
+    SLEEPERS.lock().peek().map(|s| s.deadline)
+}
@@ -0,0 +1,128 @@
+use super::signal::has_signal_to_deliver;
+use super::{current_task, run_next_task, TaskControlBlock, TaskControlBlockInner, TaskStatus, TASK_MANAGER};
+use crate::syscall::Errno;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use spin::{Mutex, MutexGuard};
+
+/// Tasks blocked on something. They are out of the ready queue until woken up.
+#[derive(Default)]
+pub struct WaitQueue {
+    tasks: Mutex<VecDeque<Arc<TaskControlBlock>>>,
+}
+
+impl WaitQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Block the current task on this queue until `cond` returns Some.
+    /// `cond` is checked with the queue locked, so a wake-up from another hart between the
+    /// check and the sleep isn't lost, as long as the waker changes the condition first.
+    pub fn wait_until<R>(&self, mut cond: impl FnMut() -> Option<R>) -> R {
+        let current_task = current_task();
+        loop {
+            let mut tasks = self.tasks.lock();
+            if let Some(ret) = cond() {
+                return ret;
+            }
+            current_task.lock().status = TaskStatus::Blocked;
+            tasks.push_back(Arc::clone(&current_task));
+            drop(tasks);
+            run_next_task();
+        }
+    }
+
+    /// Like wait_until, but it gives up with EINTR if a signal is to be delivered to the
+    /// process, which `interrupt` wakes it up for. The signal is handled on the way back
+    /// to the user mode, e.g. SIGKILL exits there.
+    pub fn wait_until_interruptible<R>(&self, mut cond: impl FnMut() -> Option<R>) -> Result<R, Errno> {
+        let current_task = current_task();
+        let mut slept = false;
+        loop {
+            let mut tasks = self.tasks.lock();
+            if slept {
+                // It's still queued if it's woken up by interrupt.
+                tasks.retain(|task| !Arc::ptr_eq(task, &current_task));
+            }
+            if let Some(ret) = cond() {
+                return Ok(ret);
+            }
+            if has_signal_to_deliver(&current_task.process) {
+                return Err(Errno::EINTR);
+            }
+            let mut inner = current_task.lock();
+            inner.status = TaskStatus::Blocked;
+            inner.interruptible = true;
+            drop(inner);
+            tasks.push_back(Arc::clone(&current_task));
+            drop(tasks);
+            run_next_task();
+            slept = true;
+        }
+    }
+
+    /// Return false if there is no task to wake up.
+    pub fn wake_one(&self) -> bool {
+        loop {
+            let task = self.tasks.lock().pop_front();
+            match task {
+                // Skip the interrupted ones, which are leaving the queue by themselves.
+                Some(task) => {
+                    if wake_up(task) {
+                        return true;
+                    }
+                }
+                None => return false,
+            }
+        }
+    }
+
+    pub fn wake_all(&self) {
+        let tasks: VecDeque<_> = core::mem::take(&mut *self.tasks.lock());
+        tasks.into_iter().for_each(|task| {
+            wake_up(task);
+        });
+    }
+}
+
+impl core::fmt::Debug for WaitQueue {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // Only the ids, since a task may wait on a queue of its own process.
+        f.debug_list()
+            .entries(self.tasks.lock().iter().map(|task| (task.process.pid.0, task.tid)))
+            .finish()
+    }
+}
+
+/// Move the blocked task back to the ready queue.
+/// Return false if it isn't blocked, since it has been interrupted, or it has exited
+/// after being popped from the queue.
+pub(super) fn wake_up(task: Arc<TaskControlBlock>) -> bool {
+    let inner = task.lock();
+    if inner.status != TaskStatus::Blocked {
+        return false;
+    }
+    make_ready(&task, inner);
+    true
+}
+
+/// Wake up the task if it's in wait_until_interruptible, which then checks the signals.
+/// It's left in the wait queue, and removes itself.
+pub fn interrupt(task: &Arc<TaskControlBlock>) {
+    let inner = task.lock();
+    if inner.status == TaskStatus::Blocked && inner.interruptible {
+        make_ready(task, inner);
+    }
+}
+
+fn make_ready(task: &Arc<TaskControlBlock>, mut inner: MutexGuard<'_, TaskControlBlockInner>) {
+    inner.status = TaskStatus::Ready;
+    inner.interruptible = false;
+    // It's still switching out on another hart, which will put it back instead.
+    let on_cpu = inner.on_cpu;
+    drop(inner);
+    if !on_cpu {
+        TASK_MANAGER.lock().add(Arc::clone(task));
+    }
+}
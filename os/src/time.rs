@@ -1,11 +1,29 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
 use riscv::register::time;
 
-pub const CLOCK_FREQ: usize = 12500000;
 pub const MILLI_PER_SEC: usize = 1000;
+pub const NANO_PER_MILLI_SEC: usize = 1_000_000;
+pub const NANO_PER_SEC: usize = NANO_PER_MILLI_SEC * MILLI_PER_SEC;
 
-pub const CLOCKS_PER_SEC: usize = CLOCK_FREQ / 1;
-pub const CLOCKS_PER_MILLI_SEC: usize = CLOCKS_PER_SEC / MILLI_PER_SEC;
+/// The timebase-frequency of the device tree. The default is that of QEMU virt.
+static CLOCK_FREQ: AtomicUsize = AtomicUsize::new(10_000_000);
 
+pub fn init(clock_freq: usize) {
+    assert!(clock_freq >= MILLI_PER_SEC, "timebase-frequency {} too low", clock_freq);
+    CLOCK_FREQ.store(clock_freq, Ordering::Relaxed);
+}
+
+pub fn clock_freq() -> usize {
+    CLOCK_FREQ.load(Ordering::Relaxed)
+}
+
+pub fn clocks_per_sec() -> usize {
+    clock_freq()
+}
+
+pub fn clocks_per_milli_sec() -> usize {
+    clocks_per_sec() / MILLI_PER_SEC
+}
 
 pub fn get_time() -> usize {
     time::read()
@@ -1,8 +1,12 @@
 mod context;
 
 use crate::task::{
-    run_next_task, exit_and_run_next,
+    clear_ipi, current_process, on_timer_tick, record_trap_enter, record_trap_exit,
 };
+use crate::task::signal::{force_signal, handle_signals, SIGILL, SIGKILL, SIGSEGV, SIGTRAP};
+use crate::drivers::irq;
+use crate::mm::VirtAddr;
+use crate::mm::address_space::MemAccess;
 use crate::println;
 use crate::syscall::syscall;
 pub use context::TrapContext;
@@ -11,23 +15,26 @@ use riscv::register::{
     scause::{self, Exception, Interrupt, Trap},
     stval, stvec, sepc
 };
-pub use context::TRAP_CX_VA;
+pub use context::trap_cx_va;
 
 global_asm!(include_str!("trap/trap.S"));
 extern "C" {
-    fn __all_traps();
+    fn __kernel_trap();
     pub fn __restore(cx: usize) -> !;
 }
 
+/// Set up the trap entry of the current hart. It's switched to that of the user when
+/// returning to the user mode, and back on the next trap.
 pub fn init() {
     unsafe {
-        stvec::write(__all_traps as usize, stvec::TrapMode::Direct);
+        stvec::write(__kernel_trap as usize, stvec::TrapMode::Direct);
     }
 }
 
 #[no_mangle]
 pub extern "C" fn trap_handler(cx: &mut TrapContext) -> &mut TrapContext {
     // println!("into trap handler");
+    record_trap_enter();
     let scause = scause::read();
     let stval = stval::read();
 
@@ -35,7 +42,12 @@ pub extern "C" fn trap_handler(cx: &mut TrapContext) -> &mut TrapContext {
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
             // println!("\nscheduling");
             // set_next_trigger();
-            run_next_task();
+            on_timer_tick();
+        }
+        Trap::Interrupt(Interrupt::SupervisorExternal) => irq::handle_pending(),
+        Trap::Interrupt(Interrupt::SupervisorSoft) => {
+            // An IPI for an idle hart, which arrives after we've picked up a task.
+            clear_ipi();
         }
         Trap::Exception(Exception::UserEnvCall) => {
             cx.sepc += 4;
@@ -43,32 +55,85 @@ pub extern "C" fn trap_handler(cx: &mut TrapContext) -> &mut TrapContext {
             let args = [cx.x[10], cx.x[11], cx.x[12]];
             cx.x[10] = syscall(id, args) as usize;
         }
-        Trap::Exception(Exception::StoreFault | Exception::StorePageFault) => {
-            println!("[kernel] PageFault in application, kernel killed it.");
+        Trap::Exception(Exception::LoadPageFault) if handle_page_fault(stval, MemAccess::Read) => {}
+        Trap::Exception(Exception::StorePageFault) if handle_page_fault(stval, MemAccess::Write) => {}
+        Trap::Exception(Exception::InstructionPageFault) if handle_page_fault(stval, MemAccess::Execute) => {}
+        Trap::Exception(
+            Exception::LoadFault | Exception::LoadPageFault
+            | Exception::StoreFault | Exception::StorePageFault
+            | Exception::InstructionFault | Exception::InstructionPageFault
+        ) => {
+            println!("[kernel] PageFault in application, raise SIGSEGV.");
             println!("[kernel] stval: 0x{:x}, sepc: 0x{:x}", stval, sepc::read());
-
-            // unsafe {
-            //     let task_mgr = crate::task::TASK_MANAGER.lock();
-            //     let translated = (*task_mgr.addr_spaces[1].page_table.as_page_table()).translate(crate::mm::VirtAddr::new(stval)).unwrap().0;
-            //     crate::println!("buffer translate 0x{:x}", translated);
-            //     println!("decoded: {}", *(translated as *const u8));
-
-            // }
-
-            // TODO: custom exit code
-            exit_and_run_next(-1);
+            force_signal(SIGSEGV);
         }
         Trap::Exception(Exception::IllegalInstruction) => {
-            println!("[kernel] IllegalInstruction in application, kernel killed it.");
+            println!("[kernel] IllegalInstruction in application, raise SIGILL.");
             println!("[kernel] stval: 0x{:x}, sepc: 0x{:x}", stval, sepc::read());
-            // TODO: custom exit code
-            exit_and_run_next(-1);
+            force_signal(SIGILL);
+        }
+        Trap::Exception(Exception::Breakpoint) if handle_breakpoint(cx) => {}
+        Trap::Exception(Exception::Breakpoint) => {
+            println!("[kernel] Breakpoint in application, raise SIGTRAP.");
+            println!("[kernel] sepc: 0x{:x}", sepc::read());
+            force_signal(SIGTRAP);
         }
-        unknown => {
+        // Whatever else the application caused, e.g. a misaligned access, it can't go on.
+        Trap::Exception(unknown) => {
+            println!("[kernel] Unsupported exception {:?} in application, raise SIGILL.", unknown);
             println!("[kernel] stval: 0x{:x}, sepc: 0x{:x}", stval, sepc::read());
-            panic!("Unsupported trap {:?}", unknown);
-            // panic!("Unsupported trap {:?}, stval = {:#x}!", unknown, stval);
+            force_signal(SIGILL);
+        }
+        // Not the fault of the application.
+        Trap::Interrupt(unknown) => {
+            println!("[kernel] Unsupported interrupt {:?}, ignored.", unknown);
         }
     }
+    #[cfg(feature = "gdbstub")]
+    crate::gdbstub::check_stop(cx);
+    handle_signals(cx);
+    record_trap_exit();
     cx
 }
+
+/// A trap from the kernel. The interrupts are disabled in the kernel, and it doesn't
+/// fault on purpose, so it's a bug.
+#[no_mangle]
+pub extern "C" fn kernel_trap_handler(cx: &TrapContext) -> ! {
+    println!("[kernel] stval: 0x{:x}, context:", stval::read());
+    println!("{}", cx);
+    panic!("Unexpected trap {:?} in the kernel", scause::read().cause());
+}
+
+/// Return false if it's a real bad access. If there is no frame for the page, the process
+/// can't go on either, and is killed.
+fn handle_page_fault(va: usize, access: MemAccess) -> bool {
+    let va = match VirtAddr::try_new(va) {
+        Some(va) => va,
+        None => return false,
+    };
+    let current_process = current_process();
+    let mut process_inner = current_process.lock();
+    let handled = process_inner.addr_space.handle_page_fault(va, access);
+    drop(process_inner);
+    match handled {
+        Ok(true) => current_process.usage.add_page_fault(),
+        Ok(false) => return false,
+        Err(_) => {
+            println!("[kernel] Out of memory for the page fault at 0x{:x}, raise SIGKILL.", va.0);
+            force_signal(SIGKILL);
+        }
+    }
+    true
+}
+
+/// The gdb stub takes the breakpoints of the process it debugs.
+#[cfg(feature = "gdbstub")]
+fn handle_breakpoint(cx: &mut TrapContext) -> bool {
+    crate::gdbstub::handle_breakpoint(cx)
+}
+
+#[cfg(not(feature = "gdbstub"))]
+fn handle_breakpoint(_cx: &mut TrapContext) -> bool {
+    false
+}
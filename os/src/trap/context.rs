@@ -1,15 +1,23 @@
 use riscv::register::sstatus::{self, Sstatus, SPP};
-use crate::config::KERNEL_STACK_VA;
+use crate::config::kernel_stack_va;
 use crate::config::KERNEL_STACK_SIZE;
 use crate::mm::VirtAddr;
+use crate::backtrace::lookup;
+use core::fmt;
 
-pub const TRAP_CX_VA: VirtAddr = KERNEL_STACK_VA.add(KERNEL_STACK_SIZE - core::mem::size_of::<TrapContext>());
+/// The trap context of a thread is at the top of its kernel stack.
+pub const fn trap_cx_va(tid: usize) -> VirtAddr {
+    kernel_stack_va(tid).add(KERNEL_STACK_SIZE - core::mem::size_of::<TrapContext>())
+}
 
-#[repr(C)]
+// Aligned so that the kernel stack below it is 16-byte aligned.
+#[repr(C, align(16))]
 pub struct TrapContext {
     pub x: [usize; 32],
     pub sstatus: Sstatus,
     pub sepc: usize,
+    /// tp of the kernel, which is the hart id. It's saved when returning to the user.
+    pub kernel_tp: usize,
     // pub satp: usize,
     // pub kernel_stack: usize,
     // pub brk: VirtAddr,
@@ -29,9 +37,32 @@ impl TrapContext {
             x: [0; 32],
             sstatus,
             sepc: entry,
+            kernel_tp: 0,
         };
         cx.set_sp(sp);
 
         cx
     }
 }
+
+const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2",
+    "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7",
+    "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+/// The registers, four in a row, then sepc with the function it's in.
+impl fmt::Display for TrapContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (name, value)) in REG_NAMES.iter().zip(self.x).enumerate() {
+            write!(f, "{:>4}: {:#018x}", name, value)?;
+            f.write_str(if i % 4 == 3 { "\n" } else { "  " })?;
+        }
+        write!(f, "spp: {:?}, sepc: {:#x}", self.sstatus.spp(), self.sepc)?;
+        if let Some((name, offset)) = lookup(self.sepc) {
+            write!(f, " <{}+{:#x}>", name, offset)?;
+        }
+        Ok(())
+    }
+}
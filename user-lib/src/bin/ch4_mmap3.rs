@@ -5,7 +5,7 @@
 #[macro_use]
 extern crate user_lib;
 
-use user_lib::mmap;
+use user_lib::{mmap, try_mmap, Errno};
 
 /*
 理想结果：对于错误的 mmap 返回 -1，最终输出 Test 04_4 test OK!
@@ -17,10 +17,10 @@ fn main() -> i32 {
     let len: usize = 4096;
     let prot: usize = 3;
     assert_eq!(0, mmap(start, len, prot));
-    assert_eq!(mmap(start - len, len + 1, prot), -1);
-    assert_eq!(mmap(start + len + 1, len, prot), -1);
-    assert_eq!(mmap(start + len, len, 0), -1);
-    assert_eq!(mmap(start + len, len, prot | 8), -1);
+    assert_eq!(try_mmap(start - len, len + 1, prot), Err(Errno::EEXIST));
+    assert_eq!(try_mmap(start + len + 1, len, prot), Err(Errno::EINVAL));
+    assert_eq!(try_mmap(start + len, len, 0), Err(Errno::EINVAL));
+    assert_eq!(try_mmap(start + len, len, prot | 8), Err(Errno::EINVAL));
     println!("Test 04_4 test OK!");
     0
 }
@@ -5,7 +5,7 @@
 #[macro_use]
 extern crate user_lib;
 
-use user_lib::{mmap, munmap};
+use user_lib::{mmap, try_mmap, try_munmap, Errno};
 
 /*
 理想结果：输出 Test 04_6 ummap2 OK!
@@ -17,8 +17,13 @@ fn main() -> i32 {
     let len: usize = 4096;
     let prot: usize = 3;
     assert_eq!(0, mmap(start, len, prot));
-    assert_eq!(munmap(start, len + 1), -1);
-    assert_eq!(munmap(start + 1, len - 1), -1);
+    assert_eq!(try_munmap(start, len + 1), Err(Errno::EINVAL));
+    assert_eq!(try_munmap(start + 1, len - 1), Err(Errno::EINVAL));
+    assert_eq!(try_munmap(start, 0), Err(Errno::EINVAL));
+    assert_eq!(try_mmap(start + len, 0, prot), Err(Errno::EINVAL));
+    // The code isn't mmapped, and can't be unmapped.
+    let code = main as usize / len * len;
+    assert_eq!(try_munmap(code, len), Err(Errno::EINVAL));
     println!("Test 04_6 ummap2 OK!");
     0
 }
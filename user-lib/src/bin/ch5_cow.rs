@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, pipe, read, wait, write};
+
+/// 测试写时复制的 fork：父子进程一开始看到相同的数据，之后各自的写互不可见，
+/// 输出 cow passed! 即为正确。
+
+const PAGES: usize = 16;
+const LEN: usize = PAGES * 4096 / 8;
+
+/// Spans many pages, so that each of them is shared and copied on its own.
+static mut DATA: [usize; LEN] = [0; LEN];
+
+fn data() -> &'static mut [usize; LEN] {
+    // Only the main thread touches it.
+    unsafe { &mut *core::ptr::addr_of_mut!(DATA) }
+}
+
+fn fill(value: usize) {
+    data().iter_mut().enumerate().for_each(|(i, x)| *x = value + i);
+}
+
+fn check(value: usize) -> bool {
+    data().iter().enumerate().all(|(i, &x)| x == value + i)
+}
+
+/// Block until the other end writes a byte.
+fn wait_for(fd: usize) {
+    let mut byte = [0u8; 1];
+    assert_eq!(read(fd, &mut byte), 1);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    fill(100);
+    let mut to_child = [0usize; 2];
+    let mut to_parent = [0usize; 2];
+    assert_eq!(pipe(&mut to_child), 0);
+    assert_eq!(pipe(&mut to_parent), 0);
+
+    let pid = fork();
+    if pid == 0 {
+        // Shared with the parent.
+        assert!(check(100));
+        // The parent writes first, which isn't seen here.
+        wait_for(to_child[0]);
+        assert!(check(100));
+        fill(300);
+        assert!(check(300));
+        // A grandchild shares the copy of the child.
+        if fork() == 0 {
+            assert!(check(300));
+            fill(400);
+            exit(0);
+        }
+        let mut exit_code = 0;
+        assert!(wait(&mut exit_code) > 0);
+        assert_eq!(exit_code, 0);
+        assert!(check(300));
+        write(to_parent[1], b"x");
+        exit(0);
+    }
+
+    fill(200);
+    write(to_child[1], b"x");
+    wait_for(to_parent[0]);
+    // The writes of the child aren't seen here either.
+    assert!(check(200));
+    let mut exit_code = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    println!("cow passed!");
+    0
+}
@@ -0,0 +1,86 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, get_time, getrusage, mmap, sleep, try_getrusage, wait, Errno, RUsage, TimeVal,
+    RUSAGE_CHILDREN, RUSAGE_SELF,
+};
+
+/// 测试 getrusage：用户态时间、缺页次数、主动切换次数，以及回收子进程后的统计，输出 rusage passed! 即为正确。
+
+const PAGES: usize = 8;
+const PAGE_SIZE: usize = 4096;
+
+fn ms(time: &TimeVal) -> usize {
+    time.sec * 1000 + time.usec / 1000
+}
+
+/// Spin in the user mode for about `period_ms`, with few syscalls.
+fn spin(period_ms: isize) {
+    let start = get_time();
+    let mut acc = 0usize;
+    while get_time() - start < period_ms {
+        for i in 0..10000 {
+            acc = core::hint::black_box(acc.wrapping_add(i));
+        }
+    }
+}
+
+fn touch_pages(start: usize) {
+    assert_eq!(mmap(start, PAGES * PAGE_SIZE, 3), 0);
+    for i in 0..PAGES {
+        unsafe {
+            *((start + i * PAGE_SIZE) as *mut u8) = i as u8;
+        }
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut usage = RUsage::default();
+    assert_eq!(try_getrusage(2, &mut usage), Err(Errno::EINVAL));
+
+    spin(200);
+    touch_pages(0x1000_0000);
+    sleep(10);
+    assert_eq!(getrusage(RUSAGE_SELF, &mut usage), 0);
+    println!(
+        "self: utime = {}ms, stime = {}ms, maxrss = {}KiB, minflt = {}, nvcsw = {}, nivcsw = {}",
+        ms(&usage.utime), ms(&usage.stime), usage.maxrss, usage.minflt, usage.nvcsw, usage.nivcsw
+    );
+    assert!(ms(&usage.utime) >= 100);
+    assert!(usage.minflt >= PAGES);
+    assert!(usage.maxrss >= PAGES * PAGE_SIZE / 1024);
+    // Sleeping switches out by itself.
+    assert!(usage.nvcsw >= 1);
+
+    // Nothing has been waited for.
+    let mut children = RUsage::default();
+    assert_eq!(getrusage(RUSAGE_CHILDREN, &mut children), 0);
+    assert_eq!(ms(&children.utime), 0);
+    assert_eq!(children.minflt, 0);
+
+    let pid = fork();
+    if pid == 0 {
+        spin(200);
+        touch_pages(0x2000_0000);
+        exit(0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(getrusage(RUSAGE_CHILDREN, &mut children), 0);
+    println!(
+        "children: utime = {}ms, stime = {}ms, maxrss = {}KiB, minflt = {}",
+        ms(&children.utime), ms(&children.stime), children.maxrss, children.minflt
+    );
+    assert!(ms(&children.utime) >= 100);
+    assert!(children.minflt >= PAGES);
+    assert!(children.maxrss >= PAGES * PAGE_SIZE / 1024);
+
+    println!("rusage passed!");
+    0
+}
@@ -3,7 +3,7 @@
 
 #[macro_use]
 extern crate user_lib;
-use user_lib::set_priority;
+use user_lib::{set_priority, try_set_priority, Errno};
 
 /// 正确输出：（无报错信息）
 /// Test set_priority OK!
@@ -12,9 +12,9 @@ use user_lib::set_priority;
 pub fn main() -> i32 {
     assert_eq!(set_priority(10), 10);
     assert_eq!(set_priority(isize::MAX), isize::MAX);
-    assert_eq!(set_priority(0), -1);
-    assert_eq!(set_priority(1), -1);
-    assert_eq!(set_priority(-10), -1);
+    assert_eq!(try_set_priority(0), Err(Errno::EINVAL));
+    assert_eq!(try_set_priority(1), Err(Errno::EINVAL));
+    assert_eq!(try_set_priority(-10), Err(Errno::EINVAL));
     println!("Test set_priority OK!");
     0
 }
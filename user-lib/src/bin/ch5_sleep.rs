@@ -0,0 +1,29 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{fork, get_time, sleep, wait};
+
+/// 测试 sleep 系统调用：子进程休眠期间父进程继续运行，输出 Test sleep1 OK! 即为正确。
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let start = get_time();
+    let pid = fork();
+    if pid == 0 {
+        sleep(500);
+        let slept = get_time() - start;
+        assert!(slept >= 500, "woken up too early: {} ms", slept);
+        return 0;
+    }
+    // The child is blocked now, so we should run before it wakes up.
+    sleep(100);
+    assert!(get_time() - start < 500);
+    let mut exit_code = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    println!("Test sleep1 OK!");
+    0
+}
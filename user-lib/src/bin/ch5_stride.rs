@@ -1,33 +1,50 @@
 #![no_std]
 #![no_main]
 
+#[macro_use]
 extern crate user_lib;
 
-static TESTS: &[&str] = &[
-    "ch5_stride0\0",
-    "ch5_stride1\0",
-    "ch5_stride2\0",
-    "ch5_stride3\0",
-    "ch5_stride4\0",
-    "ch5_stride5\0",
+static TESTS: &[(&str, isize)] = &[
+    ("ch5_stride0\0", 5),
+    ("ch5_stride1\0", 6),
+    ("ch5_stride2\0", 7),
+    ("ch5_stride3\0", 8),
+    ("ch5_stride4\0", 9),
+    ("ch5_stride5\0", 10),
 ];
 
+/// The largest count / priority may exceed the smallest one by this percentage.
+const TOLERANCE: isize = 30;
 
 use user_lib::{spawn, waitpid, set_priority};
 
+/// 测试 stride 调度：6个进程的 count 应基本正比于 priority，输出 ch5_stride passed! 即为正确。
+/// 只对默认的 stride 调度算法成立。
+/// stride 只在同一个 hart 上按 priority 分配时间：hart 多于1个时，各 hart 上的进程分到的时间
+/// 取决于 hart 的个数和负载，而不是 priority。所以6个进程都用 sched_setaffinity 绑定在 hart 0 上，
+/// 结果和 hart 的数量无关。
+
 #[no_mangle]
 pub fn main() -> i32 {
     let mut pid = [0; 6];
-    let mut i = 0;
-    for test in TESTS {
-        pid[i] = spawn(*test);
-        i += 1;
+    for (i, &(test, _)) in TESTS.iter().enumerate() {
+        pid[i] = spawn(test);
+        assert!(pid[i] > 0);
     }
     set_priority(4);
-    for i in 0..6{
+    let mut min_ratio = isize::MAX;
+    let mut max_ratio = 0;
+    for (i, &(_, prio)) in TESTS.iter().enumerate() {
         let mut xstate: i32 = Default::default();
         let wait_pid = waitpid(pid[i] as usize, &mut xstate);
         assert_eq!(pid[i], wait_pid);
+        // The exit code is the count.
+        let ratio = xstate as isize / prio;
+        min_ratio = min_ratio.min(ratio);
+        max_ratio = max_ratio.max(ratio);
     }
+    println!("ratio: min = {}, max = {}", min_ratio, max_ratio);
+    assert!(max_ratio * 100 <= min_ratio * (100 + TOLERANCE));
+    println!("ch5_stride passed!");
     0
 }
@@ -3,7 +3,7 @@
 
 #[macro_use]
 extern crate user_lib;
-use user_lib::{get_time, set_priority};
+use user_lib::{get_time, sched_setaffinity, set_priority};
 
 /*
 理想结果：6个进程退出时，输出 count 基本正比于 priority
@@ -19,6 +19,8 @@ fn spin_delay() {
 // to get enough accuracy, MAX_TIME (the running time of each process) should > 1000 mseconds.
 const MAX_TIME: isize = 4000;
 pub fn count_during(prio: isize) -> isize {
+    // All on hart 0, or the counts follow the share of the harts instead of the priority.
+    assert_eq!(sched_setaffinity(1), 0);
     let start_time = get_time();
     let mut acc = 0;
     set_priority(prio);
@@ -35,9 +37,9 @@ pub fn count_during(prio: isize) -> isize {
 }
 
 #[no_mangle]
-pub fn main() -> usize {
+pub fn main() -> i32 {
     let prio = 5;
     let count = count_during(prio);
     println!("priority = {}, exitcode = {}, ratio = {}", prio, count, count/prio);
-    0
+    count as i32
 }
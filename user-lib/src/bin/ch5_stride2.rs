@@ -3,7 +3,7 @@
 
 #[macro_use]
 extern crate user_lib;
-use user_lib::{get_time, set_priority};
+use user_lib::{get_time, sched_setaffinity, set_priority};
 
 fn spin_delay() {
     let mut j = true;
@@ -15,6 +15,8 @@ fn spin_delay() {
 // to get enough accuracy, MAX_TIME (the running time of each process) should > 1000 mseconds.
 const MAX_TIME: isize = 4000;
 fn count_during(prio: isize) -> isize {
+    // All on hart 0, or the counts follow the share of the harts instead of the priority.
+    assert_eq!(sched_setaffinity(1), 0);
     let start_time = get_time();
     let mut acc = 0;
     set_priority(prio);
@@ -31,9 +33,9 @@ fn count_during(prio: isize) -> isize {
 }
 
 #[no_mangle]
-pub fn main() -> usize {
+pub fn main() -> i32 {
     let prio = 7;
     let count = count_during(prio);
     println!("priority = {}, exitcode = {}, ratio = {}", prio, count, count/prio);
-    0
+    count as i32
 }
\ No newline at end of file
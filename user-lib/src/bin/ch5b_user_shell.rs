@@ -6,61 +6,87 @@ extern crate alloc;
 #[macro_use]
 extern crate user_lib;
 
-const LF: u8 = 0x0au8;
-const CR: u8 = 0x0du8;
-const DL: u8 = 0x7fu8;
-const BS: u8 = 0x08u8;
+/// A line from the tty, with the newline.
+const LINE_MAX: usize = 256;
 
 use alloc::string::String;
-use user_lib::console::getchar;
-use user_lib::{exec, flush, fork, waitpid};
+use alloc::vec::Vec;
+use user_lib::console::STDIN;
+use user_lib::{close, dup2, exec, exit, flush, fork, pipe, read, waitpid};
+
+/// Run `a | b | c`, where the stdout of each app is connected to the stdin of the next.
+fn run_pipeline(line: &str) {
+    let apps: Vec<String> = line
+        .split('|')
+        .map(|app| {
+            let mut app = String::from(app.trim());
+            app.push('\0');
+            app
+        })
+        .collect();
+    if apps.iter().any(|app| app.len() == 1) {
+        println!("Invalid pipeline!");
+        return;
+    }
+
+    // pipes[i] connects apps[i] and apps[i + 1].
+    let mut pipes: Vec<[usize; 2]> = Vec::new();
+    for _ in 1..apps.len() {
+        let mut fds = [0; 2];
+        if pipe(&mut fds) < 0 {
+            println!("Error when creating pipes!");
+            pipes.iter().flatten().for_each(|&fd| { close(fd); });
+            return;
+        }
+        pipes.push(fds);
+    }
+
+    let mut pids = Vec::new();
+    for (i, app) in apps.iter().enumerate() {
+        let pid = fork();
+        if pid == 0 {
+            // child process
+            if i > 0 {
+                dup2(pipes[i - 1][0], 0);
+            }
+            if i + 1 < apps.len() {
+                dup2(pipes[i][1], 1);
+            }
+            pipes.iter().flatten().for_each(|&fd| { close(fd); });
+            if exec(app.as_str(), &[0 as *const u8]) < 0 {
+                println!("Error when executing!");
+                exit(-4);
+            }
+            unreachable!();
+        }
+        pids.push(pid);
+    }
+    // Or the readers never see the EOF.
+    pipes.iter().flatten().for_each(|&fd| { close(fd); });
+
+    for pid in pids {
+        let mut exit_code: i32 = 0;
+        let exit_pid = waitpid(pid as usize, &mut exit_code);
+        assert_eq!(pid, exit_pid);
+        println!("Shell: Process {} exited with code {}", pid, exit_code);
+    }
+}
 
 #[no_mangle]
 pub fn main() -> i32 {
     println!("Rust user shell");
-    let mut line: String = String::new();
-    print!(">> ");
-    flush();
+    // The tty is in the canonical mode, which echoes and edits the line for us.
+    let mut buf = [0u8; LINE_MAX];
     loop {
-        let c = getchar();
-        match c {
-            LF | CR => {
-                print!("\n");
-                if !line.is_empty() {
-                    line.push('\0');
-                    let pid = fork();
-                    if pid == 0 {
-                        // child process
-                        if exec(line.as_str(), &[0 as *const u8]) == -1 {
-                            println!("Error when executing!");
-                            return -4;
-                        }
-                        unreachable!();
-                    } else {
-                        let mut exit_code: i32 = 0;
-                        let exit_pid = waitpid(pid as usize, &mut exit_code);
-                        assert_eq!(pid, exit_pid);
-                        println!("Shell: Process {} exited with code {}", pid, exit_code);
-                    }
-                    line.clear();
-                }
-                print!(">> ");
-                flush();
-            }
-            BS | DL => {
-                if !line.is_empty() {
-                    print!("{}", BS as char);
-                    print!(" ");
-                    print!("{}", BS as char);
-                    flush();
-                    line.pop();
-                }
-            }
-            _ => {
-                print!("{}", c as char);
-                flush();
-                line.push(c as char);
-            }
+        print!(">> ");
+        flush();
+        let n = read(STDIN, &mut buf);
+        if n <= 0 {
+            return 0;
+        }
+        let line = core::str::from_utf8(&buf[..n as usize]).unwrap_or("").trim();
+        if !line.is_empty() {
+            run_pipeline(line);
         }
     }
 }
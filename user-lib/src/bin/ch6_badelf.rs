@@ -0,0 +1,104 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, open, try_exec, try_spawn, waitpid, write, Errno, OpenFlags, SIGILL};
+
+/// 测试加载畸形的 ELF：exec 和 spawn 返回 ENOEXEC 而不是让内核崩溃，输出 badelf passed! 即为正确。
+/// 共用一页但不重叠的两个段是合法的，可以加载。
+
+const PATH: &str = "badelf\0";
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+const MAX_PHDRS: usize = 2;
+/// Where the data of the segments starts in the file.
+const DATA_OFFSET: u64 = (EHDR_SIZE + MAX_PHDRS * PHDR_SIZE) as u64;
+const DATA_SIZE: usize = 16;
+
+/// A loadable segment: (offset, vaddr, filesz, memsz).
+type Segment = (u64, u64, u64, u64);
+
+/// An ELF of RISC-V 64 with the segments, followed by DATA_SIZE bytes of data.
+fn build_elf(segments: &[Segment], buf: &mut [u8; DATA_OFFSET as usize + DATA_SIZE]) {
+    buf.fill(0);
+    buf[..8].copy_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    let mut put = |pos: usize, bytes: &[u8]| buf[pos..pos + bytes.len()].copy_from_slice(bytes);
+    // e_type, e_machine, e_version, e_entry, e_phoff
+    put(16, &2u16.to_le_bytes());
+    put(18, &0xf3u16.to_le_bytes());
+    put(20, &1u32.to_le_bytes());
+    put(24, &0x10000u64.to_le_bytes());
+    put(32, &(EHDR_SIZE as u64).to_le_bytes());
+    // e_ehsize, e_phentsize, e_phnum
+    put(52, &(EHDR_SIZE as u16).to_le_bytes());
+    put(54, &(PHDR_SIZE as u16).to_le_bytes());
+    put(56, &(segments.len() as u16).to_le_bytes());
+    for (i, &(offset, vaddr, filesz, memsz)) in segments.iter().enumerate() {
+        let ph = EHDR_SIZE + i * PHDR_SIZE;
+        // PT_LOAD, R|X
+        put(ph, &1u32.to_le_bytes());
+        put(ph + 4, &5u32.to_le_bytes());
+        put(ph + 8, &offset.to_le_bytes());
+        put(ph + 16, &vaddr.to_le_bytes());
+        put(ph + 24, &vaddr.to_le_bytes());
+        put(ph + 32, &filesz.to_le_bytes());
+        put(ph + 40, &memsz.to_le_bytes());
+        put(ph + 48, &4096u64.to_le_bytes());
+    }
+}
+
+fn write_file(data: &[u8]) {
+    let fd = open(PATH, OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC);
+    assert!(fd >= 0);
+    assert_eq!(write(fd as usize, data), data.len() as isize);
+    close(fd as usize);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    write_file(b"not an elf at all");
+    assert_eq!(try_spawn(PATH), Err(Errno::ENOEXEC));
+    assert_eq!(try_exec(PATH, &[core::ptr::null()]), Err(Errno::ENOEXEC));
+
+    let mut elf = [0; DATA_OFFSET as usize + DATA_SIZE];
+    let size = DATA_SIZE as u64;
+    let bad_segments: [&[Segment]; 6] = [
+        // The data is beyond the end of the file.
+        &[(DATA_OFFSET, 0x10000, size + 1, size + 1)],
+        // More data than memory.
+        &[(DATA_OFFSET, 0x10000, size, size - 1)],
+        // In the kernel half.
+        &[(DATA_OFFSET, 0xffff_ffff_c000_0000, size, size)],
+        // Wrapping around.
+        &[(DATA_OFFSET, u64::MAX - 0xfff, size, 0x2000)],
+        // Overlapping.
+        &[(DATA_OFFSET, 0x10000, size, size), (DATA_OFFSET, 0x10008, size, size)],
+        // On the main user stack.
+        &[(DATA_OFFSET, 0x7000_0000, size, size)],
+    ];
+    for segments in bad_segments {
+        build_elf(segments, &mut elf);
+        write_file(&elf);
+        assert_eq!(try_spawn(PATH), Err(Errno::ENOEXEC));
+        assert_eq!(try_exec(PATH, &[core::ptr::null()]), Err(Errno::ENOEXEC));
+    }
+
+    // The program header table is cut off.
+    build_elf(&[(DATA_OFFSET, 0x10000, size, size)], &mut elf);
+    write_file(&elf[..EHDR_SIZE + PHDR_SIZE / 2]);
+    assert_eq!(try_spawn(PATH), Err(Errno::ENOEXEC));
+
+    // Two segments in the same page, like most linkers emit. It loads, and then dies on
+    // the code of zeros.
+    build_elf(&[(DATA_OFFSET, 0x10000, size, size), (DATA_OFFSET, 0x10000 + size, size, size)], &mut elf);
+    write_file(&elf);
+    let pid = try_spawn(PATH).unwrap();
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), pid as isize);
+    assert_eq!(exit_code, -(SIGILL as i32));
+
+    println!("badelf passed!");
+    0
+}
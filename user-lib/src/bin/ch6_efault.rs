@@ -0,0 +1,59 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, mmap, pipe, read, syscall, write, Errno, AT_FDCWD, SYSCALL_GET_TIME, SYSCALL_OPENAT, SYSCALL_PIPE2,
+    SYSCALL_READ, SYSCALL_WRITE,
+};
+
+/// 测试传给系统调用的坏指针：返回 EFAULT 而不是让内核崩溃，跨到坏页的写只写前面的部分，
+/// 输出 efault passed! 即为正确。
+
+const PAGE_SIZE: usize = 4096;
+/// Nothing is mapped at the null page.
+const NULL: usize = 0;
+/// In the kernel half.
+const KERNEL_ADDR: usize = 0xffff_ffff_c000_0000;
+/// A page mmapped here, and the next one isn't.
+const MMAP_START: usize = 0x10000000;
+
+fn sys(id: usize, args: [usize; 3]) -> Result<usize, Errno> {
+    Errno::from_ret(syscall(id, args))
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    for addr in [NULL, KERNEL_ADDR] {
+        assert_eq!(sys(SYSCALL_WRITE, [1, addr, 8]), Err(Errno::EFAULT));
+        assert_eq!(sys(SYSCALL_GET_TIME, [addr, 0, 0]), Err(Errno::EFAULT));
+        assert_eq!(sys(SYSCALL_PIPE2, [addr, 0, 0]), Err(Errno::EFAULT));
+        assert_eq!(sys(SYSCALL_OPENAT, [AT_FDCWD as usize, addr, 0]), Err(Errno::EFAULT));
+    }
+
+    let mut fds = [0usize; 2];
+    assert_eq!(pipe(&mut fds), 0);
+    // The data isn't consumed by a read that can't deliver it.
+    assert_eq!(write(fds[1], b"abc"), 3);
+    assert_eq!(sys(SYSCALL_READ, [fds[0], KERNEL_ADDR, 3]), Err(Errno::EFAULT));
+    let mut buf = [0u8; 32];
+    assert_eq!(read(fds[0], &mut buf[..3]), 3);
+    assert_eq!(&buf[..3], b"abc");
+
+    // A write stops at the first bad page.
+    assert_eq!(mmap(MMAP_START, PAGE_SIZE, 3), 0);
+    let tail = MMAP_START + PAGE_SIZE - 16;
+    unsafe {
+        core::slice::from_raw_parts_mut(tail as *mut u8, 16).fill(b'x');
+    }
+    assert_eq!(sys(SYSCALL_WRITE, [fds[1], tail, 32]), Ok(16));
+    assert_eq!(read(fds[0], &mut buf), 16);
+    assert!(buf[..16].iter().all(|&c| c == b'x'));
+    close(fds[0]);
+    close(fds[1]);
+
+    println!("efault passed!");
+    0
+}
@@ -0,0 +1,31 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{try_open, Errno, OpenFlags};
+
+/// 测试过长的文件名和路径：创建时返回 ENAMETOOLONG 而不是让内核崩溃，输出 longname passed! 即为正确。
+
+/// Longer than the 123 bytes of an easy-fs name, but shorter than PATH_MAX.
+const NAME_LEN: usize = 200;
+/// Longer than PATH_MAX.
+const PATH_LEN: usize = 300;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut name = [b'a'; NAME_LEN + 1];
+    name[NAME_LEN] = 0;
+    let name = core::str::from_utf8(&name).unwrap();
+    assert_eq!(try_open(name, OpenFlags::CREATE | OpenFlags::WRONLY), Err(Errno::ENAMETOOLONG));
+    assert_eq!(try_open(name, OpenFlags::RDONLY), Err(Errno::ENAMETOOLONG));
+
+    let mut path = [b'b'; PATH_LEN + 1];
+    path[PATH_LEN] = 0;
+    let path = core::str::from_utf8(&path).unwrap();
+    assert_eq!(try_open(path, OpenFlags::RDONLY), Err(Errno::ENAMETOOLONG));
+
+    println!("longname passed!");
+    0
+}
@@ -0,0 +1,61 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, exit, fork, pipe, read, try_sigaction, try_write, wait, write, Errno, SignalAction, SIGPIPE, SIG_IGN,
+};
+
+/// 测试管道：父进程写入的字符串应被子进程完整读出，写端关闭后子进程读到 EOF；
+/// 读端全部关闭后写入返回 EPIPE，并且默认的 SIGPIPE 会杀死写者，输出 pipetest passed! 即为正确。
+
+const STR: &str = "Hello, world!";
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    let pid = fork();
+    if pid == 0 {
+        close(pipe_fd[1]);
+        let mut buffer = [0u8; 32];
+        let mut len = 0;
+        loop {
+            let n = read(pipe_fd[0], &mut buffer[len..]);
+            assert!(n >= 0);
+            if n == 0 {
+                break;
+            }
+            len += n as usize;
+        }
+        close(pipe_fd[0]);
+        assert_eq!(core::str::from_utf8(&buffer[..len]).unwrap(), STR);
+        return 0;
+    }
+    close(pipe_fd[0]);
+    assert_eq!(write(pipe_fd[1], STR.as_bytes()), STR.len() as isize);
+    close(pipe_fd[1]);
+    let mut exit_code = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+
+    // No readers. SIGPIPE kills the writer by default.
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    close(pipe_fd[0]);
+    let pid = fork();
+    if pid == 0 {
+        write(pipe_fd[1], STR.as_bytes());
+        exit(0);
+    }
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, -(SIGPIPE as i32));
+    // It's just an error if SIGPIPE is ignored.
+    try_sigaction(SIGPIPE, Some(&SignalAction::with_special(SIG_IGN)), None).unwrap();
+    assert_eq!(try_write(pipe_fd[1], STR.as_bytes()), Err(Errno::EPIPE));
+    close(pipe_fd[1]);
+
+    println!("pipetest passed!");
+    0
+}
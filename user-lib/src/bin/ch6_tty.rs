@@ -0,0 +1,40 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::console::STDIN;
+use user_lib::{
+    close, pipe, try_tcgetattr, try_tcsetattr, Errno, Termios, ECHO, ICANON, ONLCR, OPOST, VEOF,
+};
+
+/// 测试终端属性：stdin 默认为规范模式并回显、输出时把换行转成回车换行、Ctrl-D 为文件结束符，切换到原始模式后能读回，恢复后与原来一致，管道不是终端，输出 tty passed! 即为正确。
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut cooked = Termios::default();
+    try_tcgetattr(STDIN, &mut cooked).unwrap();
+    assert_eq!(cooked.lflag & (ICANON | ECHO), ICANON | ECHO);
+    assert_eq!(cooked.oflag & (OPOST | ONLCR), OPOST | ONLCR);
+    assert_eq!(cooked.cc[VEOF], 0x04);
+
+    let mut raw = cooked;
+    raw.lflag &= !(ICANON | ECHO);
+    try_tcsetattr(STDIN, &raw).unwrap();
+    let mut current = Termios::default();
+    try_tcgetattr(STDIN, &mut current).unwrap();
+    assert_eq!(current, raw);
+
+    try_tcsetattr(STDIN, &cooked).unwrap();
+    try_tcgetattr(STDIN, &mut current).unwrap();
+    assert_eq!(current, cooked);
+
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    assert_eq!(try_tcgetattr(pipe_fd[0], &mut current), Err(Errno::ENOTTY));
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+    println!("tty passed!");
+    0
+}
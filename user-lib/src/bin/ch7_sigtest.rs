@@ -0,0 +1,128 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{
+    exit, fork, getpid, kill, pipe, read, sigaction, sigprocmask, sleep, try_read, try_sigaction, try_waitpid,
+    wait, waitpid, yield_, Errno, SignalAction, SignalSet, SIGKILL, SIGSEGV, SIGUSR1, SIGUSR2, SIG_BLOCK,
+    SIG_UNBLOCK,
+};
+
+/// 测试信号：自定义处理函数、屏蔽信号、捕获 SIGSEGV、SIGKILL 以及打断阻塞的系统调用，
+/// 输出 sigtest passed! 即为正确。
+
+static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn count_handler(sig: i32) {
+    HANDLED.fetch_add(sig as usize, Ordering::SeqCst);
+}
+
+extern "C" fn segv_handler(sig: i32) {
+    assert_eq!(sig as usize, SIGSEGV);
+    // Returning would fault again.
+    exit(42);
+}
+
+fn test_handler() {
+    let action = SignalAction::new(count_handler, SignalSet::empty());
+    assert_eq!(sigaction(SIGUSR1, Some(&action), None), 0);
+    assert_eq!(kill(getpid() as usize, SIGUSR1), 0);
+    // Delivered when the kill returns.
+    assert_eq!(HANDLED.swap(0, Ordering::SeqCst), SIGUSR1);
+}
+
+fn test_mask() {
+    let action = SignalAction::new(count_handler, SignalSet::empty());
+    assert_eq!(sigaction(SIGUSR2, Some(&action), None), 0);
+    let set = SignalSet::single(SIGUSR2);
+    assert_eq!(sigprocmask(SIG_BLOCK, Some(&set), None), 0);
+    assert_eq!(kill(getpid() as usize, SIGUSR2), 0);
+    assert_eq!(HANDLED.load(Ordering::SeqCst), 0);
+    assert_eq!(sigprocmask(SIG_UNBLOCK, Some(&set), None), 0);
+    assert_eq!(HANDLED.swap(0, Ordering::SeqCst), SIGUSR2);
+}
+
+fn test_segv() {
+    let pid = fork();
+    if pid == 0 {
+        let action = SignalAction::new(segv_handler, SignalSet::empty());
+        assert_eq!(sigaction(SIGSEGV, Some(&action), None), 0);
+        unsafe {
+            (0 as *mut u8).write_volatile(0);
+        }
+        unreachable!();
+    }
+    let mut exit_code = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, 42);
+}
+
+fn test_kill() {
+    let action = SignalAction::new(count_handler, SignalSet::empty());
+    assert_eq!(try_sigaction(SIGKILL, Some(&action), None), Err(Errno::EINVAL));
+
+    let pid = fork();
+    if pid == 0 {
+        loop {
+            yield_();
+        }
+    }
+    assert_eq!(kill(pid as usize, SIGKILL), 0);
+    let mut exit_code = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, -(SIGKILL as i32));
+}
+
+extern "C" fn nop_handler(_sig: i32) {}
+
+/// Long enough for the child to block.
+const BLOCK_MS: usize = 100;
+
+/// A caught signal interrupts a blocking read or waitpid with EINTR, and SIGKILL kills
+/// a blocked reader.
+fn test_interrupt() {
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    let pid = fork();
+    if pid == 0 {
+        let action = SignalAction::new(nop_handler, SignalSet::empty());
+        assert_eq!(sigaction(SIGUSR1, Some(&action), None), 0);
+        let mut buf = [0u8; 1];
+        // The write end is still open, so it blocks.
+        assert_eq!(try_read(pipe_fd[0], &mut buf), Err(Errno::EINTR));
+
+        let reader = fork();
+        if reader == 0 {
+            read(pipe_fd[0], &mut buf);
+            unreachable!();
+        }
+        let mut exit_code = 0;
+        assert_eq!(try_waitpid(reader as usize, &mut exit_code), Err(Errno::EINTR));
+        assert_eq!(kill(reader as usize, SIGKILL), 0);
+        assert_eq!(waitpid(reader as usize, &mut exit_code), reader);
+        assert_eq!(exit_code, -(SIGKILL as i32));
+        exit(0);
+    }
+    // One for the read, and one for the waitpid.
+    for _ in 0..2 {
+        sleep(BLOCK_MS);
+        assert_eq!(kill(pid as usize, SIGUSR1), 0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    test_handler();
+    test_mask();
+    test_segv();
+    test_kill();
+    test_interrupt();
+    println!("sigtest passed!");
+    0
+}
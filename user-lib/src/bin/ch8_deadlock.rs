@@ -0,0 +1,76 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use user_lib::sync::{Mutex, Semaphore};
+use user_lib::{enable_deadlock_detect, exit, thread_create, waittid, yield_, Errno};
+
+/// 测试死锁检测：两个线程以相反顺序获取两把锁时，其中一方应得到 EDEADLK 而不是永久阻塞；
+/// 无人能 up 的信号量 down 也应失败。输出 deadlock passed! 即为正确。
+
+struct Locks {
+    a: Mutex,
+    b: Mutex,
+    b_taken: AtomicBool,
+    deadlocks: AtomicUsize,
+}
+
+/// Lock `second` while holding `first`, or back off if it would deadlock.
+fn lock_second(locks: &Locks, second: &Mutex) {
+    match second.checked_lock() {
+        Ok(_second) => {}
+        Err(errno) => {
+            assert_eq!(errno, Errno::EDEADLK);
+            locks.deadlocks.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+extern "C" fn b_then_a(locks: usize) -> ! {
+    let locks = unsafe { &*(locks as *const Locks) };
+    let _b = locks.b.lock();
+    locks.b_taken.store(true, Ordering::SeqCst);
+    lock_second(locks, &locks.a);
+    exit(0)
+}
+
+fn test_mutex() {
+    let locks = Locks {
+        a: Mutex::new_blocking(),
+        b: Mutex::new_blocking(),
+        b_taken: AtomicBool::new(false),
+        deadlocks: AtomicUsize::new(0),
+    };
+    let a_guard = locks.a.lock();
+    let tid = thread_create(b_then_a, &locks as *const Locks as usize) as usize;
+    while !locks.b_taken.load(Ordering::SeqCst) {
+        yield_();
+    }
+    // Each of us holds one lock and wants the other. Whoever asks last backs off.
+    lock_second(&locks, &locks.b);
+    drop(a_guard);
+    let mut exit_code = 0;
+    assert_eq!(waittid(tid, &mut exit_code), tid as isize);
+    assert_eq!(locks.deadlocks.load(Ordering::SeqCst), 1);
+}
+
+fn test_semaphore() {
+    let empty = Semaphore::new(0);
+    // No one else could up it.
+    assert_eq!(empty.checked_down(), Err(Errno::EDEADLK));
+    empty.up();
+    assert_eq!(empty.checked_down(), Ok(()));
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(enable_deadlock_detect(true), 0);
+    test_mutex();
+    test_semaphore();
+    assert_eq!(enable_deadlock_detect(false), 0);
+    println!("deadlock passed!");
+    0
+}
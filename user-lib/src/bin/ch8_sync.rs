@@ -0,0 +1,121 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use user_lib::sync::{Condvar, Mutex, Semaphore};
+use user_lib::{exit, fork, thread_create, try_mutex_unlock, wait, waittid, yield_, Errno};
+
+/// 测试同步原语：互斥锁保护共享计数且只能由持有者解锁、条件变量等待标志、信号量在 fork 出的父子进程间同步，输出 sync passed! 即为正确。
+
+const THREADS: usize = 4;
+const ROUNDS: usize = 100;
+
+static mut COUNTER: usize = 0;
+
+extern "C" fn add_worker(mutex: usize) -> ! {
+    let mutex = unsafe { &*(mutex as *const Mutex) };
+    for _ in 0..ROUNDS {
+        let _guard = mutex.lock();
+        unsafe {
+            let old = COUNTER;
+            // Let the others run in the critical section.
+            yield_();
+            COUNTER = old + 1;
+        }
+    }
+    exit(0)
+}
+
+extern "C" fn unlock_worker(mutex: usize) -> ! {
+    let mutex = unsafe { &*(mutex as *const Mutex) };
+    assert_eq!(try_mutex_unlock(mutex.id()), Err(Errno::EPERM));
+    exit(0)
+}
+
+fn test_mutex(mutex: Mutex) {
+    unsafe {
+        COUNTER = 0;
+    }
+    let mut tids = [0usize; THREADS];
+    for tid in tids.iter_mut() {
+        *tid = thread_create(add_worker, &mutex as *const Mutex as usize) as usize;
+    }
+    for tid in tids {
+        let mut exit_code = 0;
+        assert_eq!(waittid(tid, &mut exit_code), tid as isize);
+    }
+    assert_eq!(unsafe { COUNTER }, THREADS * ROUNDS);
+    assert_eq!(try_mutex_unlock(mutex.id()), Err(Errno::EPERM));
+
+    // Only the holder can unlock it.
+    let guard = mutex.lock();
+    let tid = thread_create(unlock_worker, &mutex as *const Mutex as usize) as usize;
+    let mut exit_code = -1;
+    assert_eq!(waittid(tid, &mut exit_code), tid as isize);
+    assert_eq!(exit_code, 0);
+    drop(guard);
+    assert_eq!(try_mutex_unlock(mutex.id()), Err(Errno::EPERM));
+}
+
+struct Flag {
+    mutex: Mutex,
+    condvar: Condvar,
+    set: AtomicBool,
+}
+
+extern "C" fn flag_setter(flag: usize) -> ! {
+    let flag = unsafe { &*(flag as *const Flag) };
+    for _ in 0..10 {
+        yield_();
+    }
+    let _guard = flag.mutex.lock();
+    flag.set.store(true, Ordering::SeqCst);
+    flag.condvar.signal();
+    exit(0)
+}
+
+fn test_condvar() {
+    let flag = Flag {
+        mutex: Mutex::new_blocking(),
+        condvar: Condvar::new(),
+        set: AtomicBool::new(false),
+    };
+    let tid = thread_create(flag_setter, &flag as *const Flag as usize) as usize;
+    let mut guard = flag.mutex.lock();
+    while !flag.set.load(Ordering::SeqCst) {
+        guard = flag.condvar.wait(guard);
+    }
+    drop(guard);
+    let mut exit_code = 0;
+    assert_eq!(waittid(tid, &mut exit_code), tid as isize);
+}
+
+fn test_semaphore() {
+    let ready = Semaphore::new(0);
+    // Shared with the child.
+    let pid = fork();
+    if pid == 0 {
+        for _ in 0..10 {
+            yield_();
+        }
+        ready.up();
+        exit(0);
+    }
+    ready.down();
+    let mut exit_code = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    test_mutex(Mutex::new());
+    test_mutex(Mutex::new_blocking());
+    test_condvar();
+    test_semaphore();
+    println!("sync passed!");
+    0
+}
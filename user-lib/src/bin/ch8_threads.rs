@@ -0,0 +1,103 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::syscall::sys_thread_create;
+use user_lib::{
+    exit, gettid, thread_create, try_mmap, try_munmap, try_thread_create, try_waittid, waittid, yield_,
+    Errno,
+};
+
+/// 测试线程：多个线程共享全局变量、各自使用独立的用户栈，主线程依次等待它们退出并检查退出码；
+/// 线程数达到上限时返回 EAGAIN，用户栈所在区域不能被 mmap/munmap，输出 threads passed! 即为正确。
+
+const THREADS: usize = 4;
+const ROUNDS: usize = 1000;
+/// MAX_THREADS of the kernel.
+const MAX_THREADS: usize = 1024;
+const PAGE_SIZE: usize = 4096;
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn worker(arg: usize) -> ! {
+    // On the stack of this thread only.
+    let mut local = [arg; 64];
+    for _ in 0..ROUNDS {
+        COUNTER.fetch_add(arg, Ordering::SeqCst);
+        local[arg] += 1;
+        yield_();
+    }
+    assert_eq!(local[arg], arg + ROUNDS);
+    exit(gettid() as i32 + 100)
+}
+
+extern "C" fn quit(_arg: usize) -> ! {
+    exit(0)
+}
+
+/// The exited threads count until they are waited for.
+fn test_limit() {
+    let mut count = 0;
+    loop {
+        match try_thread_create(quit, 0) {
+            Ok(_) => count += 1,
+            Err(err) => {
+                assert_eq!(err, Errno::EAGAIN);
+                break;
+            }
+        }
+    }
+    assert_eq!(count, MAX_THREADS - 1);
+    // The smallest free tids are taken, which are all but the main thread's.
+    for tid in 1..MAX_THREADS {
+        let mut exit_code = -1;
+        assert_eq!(try_waittid(tid, &mut exit_code), Ok(tid));
+        assert_eq!(exit_code, 0);
+    }
+    // The tids are reusable.
+    let tid = try_thread_create(quit, 0).unwrap();
+    let mut exit_code = -1;
+    assert_eq!(try_waittid(tid, &mut exit_code), Ok(tid));
+}
+
+/// The stacks of the threads to come can't be taken by mmap, and the current one can't be unmapped.
+fn test_stack_region() {
+    let local = 0u8;
+    let page = &local as *const u8 as usize / PAGE_SIZE * PAGE_SIZE;
+    assert_eq!(try_mmap(page - 4 * PAGE_SIZE, PAGE_SIZE, 3), Err(Errno::EINVAL));
+    assert_eq!(try_munmap(page, PAGE_SIZE), Err(Errno::EINVAL));
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(gettid(), 0);
+
+    let mut tids = [0usize; THREADS];
+    for (i, tid) in tids.iter_mut().enumerate() {
+        let ret = thread_create(worker, i + 1);
+        assert!(ret > 0);
+        *tid = ret as usize;
+    }
+    for tid in tids {
+        let mut exit_code = 0;
+        assert_eq!(waittid(tid, &mut exit_code), tid as isize);
+        assert_eq!(exit_code, tid as i32 + 100);
+    }
+    let expected: usize = (1..=THREADS).map(|arg| arg * ROUNDS).sum();
+    assert_eq!(COUNTER.load(Ordering::SeqCst), expected);
+
+    let mut exit_code = 0;
+    assert_eq!(try_waittid(0, &mut exit_code), Err(Errno::EDEADLK));
+    // Each thread can only be waited for once.
+    assert_eq!(try_waittid(tids[0], &mut exit_code), Err(Errno::ESRCH));
+
+    // The entry must be in the user space.
+    assert_eq!(Errno::from_ret(sys_thread_create(usize::MAX, 0)), Err(Errno::EINVAL));
+    test_limit();
+    test_stack_region();
+    println!("threads passed!");
+    0
+}
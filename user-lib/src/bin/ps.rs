@@ -0,0 +1,41 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+#[macro_use]
+extern crate user_lib;
+
+use alloc::vec;
+use user_lib::{get_process_list, getpid, ProcessInfo};
+
+/// 列出所有进程。自身应在列表中且状态为 R。
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut list = vec![ProcessInfo::default(); 16];
+    // There may be new processes in between, so try until the buffer is large enough.
+    let count = loop {
+        let count = get_process_list(&mut list);
+        assert!(count >= 0);
+        let count = count as usize;
+        if count <= list.len() {
+            break count;
+        }
+        list.resize(count * 2, ProcessInfo::default());
+    };
+
+    println!("{:>5} {:>5} S {:>4} {:>4} {:>10} {:>8} {:>8}", "PID", "PPID", "THR", "PRIO", "PASS", "TIME(ms)", "MEM(KiB)");
+    for info in &list[..count] {
+        println!(
+            "{:>5} {:>5} {} {:>4} {:>4} {:>10} {:>8} {:>8}",
+            info.pid, info.ppid, info.state as char, info.threads, info.priority, info.pass, info.cpu_ms, info.frames * 4
+        );
+    }
+
+    let pid = getpid() as usize;
+    let this = list[..count].iter().find(|info| info.pid == pid).expect("ps isn't in the list");
+    assert_eq!(this.state, b'R');
+    assert_eq!(this.threads, 1);
+    0
+}
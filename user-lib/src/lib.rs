@@ -8,11 +8,17 @@ extern crate alloc;
 pub mod console;
 pub mod lang_items;
 pub mod syscall;
+pub mod errno;
+pub mod signal;
+pub mod sync;
 
 pub use syscall::*;
+pub use errno::Errno;
+pub use signal::*;
 pub use console::flush;
 
 use buddy_system_allocator::LockedHeap;
+use bitflags::bitflags;
 
 // const MICRO_PER_SEC: usize = 1_000_000;
 pub const CLOCK_FREQ: usize = 12500000;
@@ -39,6 +45,7 @@ pub extern "C" fn _start() -> ! {
             .init(HEAP_SPACE.as_ptr() as usize, USER_HEAP_SIZE);
     }
     let xstate = main();
+    console::flush();
     syscall::sys_exit(xstate);
 }
 
@@ -110,17 +117,175 @@ impl TimeVal {
     }
 }
 
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct TimeSpec {
+    pub sec: isize,
+    pub nsec: isize,
+}
+
+/// Block for at least `period_ms` milliseconds.
+/// A signal handled meanwhile doesn't cut it short, the rest is slept again.
 pub fn sleep(period_ms: usize) {
-    let start = get_time();
-    while get_time() < start + period_ms as isize {
-        sys_yield();
+    let mut req = TimeSpec {
+        sec: (period_ms / 1000) as isize,
+        nsec: (period_ms % 1000 * 1_000_000) as isize,
+    };
+    let mut rem = TimeSpec::default();
+    while sys_nanosleep(&req, &mut rem) == -Errno::EINTR.0 {
+        req = core::mem::take(&mut rem);
     }
 }
 
+/// An entry of the process list.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProcessInfo {
+    pub pid: usize,
+    /// 0 if there is no parent, i.e. initproc.
+    pub ppid: usize,
+    /// Threads that haven't exited.
+    pub threads: usize,
+    /// Scheduling state of the thread with the smallest tid.
+    pub priority: u64,
+    pub pass: u64,
+    /// User and kernel time of all the threads.
+    pub cpu_ms: usize,
+    /// Frames owned by the address space now.
+    pub frames: usize,
+    /// Same letters as ps: R (running or ready), S (sleeping) or Z (zombie).
+    pub state: u8,
+}
+
+/// Fill the buffer with the processes, ordered by the pid. Return the number of all the
+/// processes, which may be more than the buffer.
+pub fn get_process_list(buf: &mut [ProcessInfo]) -> isize {
+    sys_get_process_list(buf)
+}
+
+// Requests of ioctl.
+pub const TCGETS: usize = 0x5401;
+pub const TCSETS: usize = 0x5402;
+
+// Flags of Termios.
+pub const ICRNL: u32 = 0o400;
+pub const OPOST: u32 = 0o1;
+pub const ONLCR: u32 = 0o4;
+pub const ICANON: u32 = 0o2;
+pub const ECHO: u32 = 0o10;
+
+// Indices of Termios::cc.
+pub const VERASE: usize = 2;
+pub const VEOF: usize = 4;
+
+/// Same layout as the Linux `struct termios`. The kernel only honors ICRNL of iflag, OPOST
+/// and ONLCR of oflag, ICANON and ECHO of lflag, and VERASE and VEOF of cc.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Termios {
+    pub iflag: u32,
+    pub oflag: u32,
+    pub cflag: u32,
+    pub lflag: u32,
+    pub line: u8,
+    pub cc: [u8; 19],
+}
+
+/// Get the attributes of the terminal. It fails with ENOTTY if fd isn't the console.
+pub fn tcgetattr(fd: usize, termios: &mut Termios) -> isize {
+    sys_ioctl(fd, TCGETS, termios as *mut Termios as usize)
+}
+
+/// Set the attributes of the terminal, which are shared by all the fds of the console.
+pub fn tcsetattr(fd: usize, termios: &Termios) -> isize {
+    sys_ioctl(fd, TCSETS, termios as *const Termios as usize)
+}
+
+pub const RUSAGE_SELF: isize = 0;
+pub const RUSAGE_CHILDREN: isize = -1;
+
+/// Same layout as the Linux `struct rusage`. The kernel only fills in the times, maxrss,
+/// minflt, nvcsw and nivcsw.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct RUsage {
+    /// Time spent in the user mode.
+    pub utime: TimeVal,
+    /// Time spent in the kernel.
+    pub stime: TimeVal,
+    /// Peak resident set size in KiB.
+    pub maxrss: usize,
+    pub ixrss: usize,
+    pub idrss: usize,
+    pub isrss: usize,
+    /// Page faults resolved by the kernel, e.g. lazy allocation and copy-on-write.
+    pub minflt: usize,
+    pub majflt: usize,
+    pub nswap: usize,
+    pub inblock: usize,
+    pub oublock: usize,
+    pub msgsnd: usize,
+    pub msgrcv: usize,
+    pub nsignals: usize,
+    /// Context switches to wait for something.
+    pub nvcsw: usize,
+    /// Context switches by preemption or yield.
+    pub nivcsw: usize,
+}
+
+/// Usage of the current process (RUSAGE_SELF), or of its children that have been
+/// waited for (RUSAGE_CHILDREN).
+pub fn getrusage(who: isize, usage: &mut RUsage) -> isize {
+    sys_getrusage(who, usage)
+}
+
 pub fn task_info(info: &mut TaskInfo) -> isize {
     sys_task_info(info)
 }
 
+pub const AT_FDCWD: isize = -100;
+
+bitflags! {
+    pub struct OpenFlags: u32 {
+        const RDONLY = 0;
+        const WRONLY = 1 << 0;
+        const RDWR = 1 << 1;
+        const CREATE = 1 << 6;
+        const TRUNC = 1 << 9;
+    }
+}
+
+/// `path` must end with '\0'.
+pub fn open(path: &str, flags: OpenFlags) -> isize {
+    sys_openat(AT_FDCWD, path, flags.bits)
+}
+
+pub fn close(fd: usize) -> isize {
+    sys_close(fd)
+}
+
+/// fds[0] is the read end, and fds[1] is the write end.
+pub fn pipe(fds: &mut [usize; 2]) -> isize {
+    let mut raw_fds = [0i32; 2];
+    let ret = sys_pipe2(&mut raw_fds, 0);
+    fds[0] = raw_fds[0] as usize;
+    fds[1] = raw_fds[1] as usize;
+    ret
+}
+
+pub fn dup(fd: usize) -> isize {
+    sys_dup(fd)
+}
+
+/// Make new_fd a copy of old_fd, closing the file at new_fd first.
+pub fn dup2(old_fd: usize, new_fd: usize) -> isize {
+    if old_fd == new_fd {
+        // dup3 rejects it, but it's a no-op for dup2.
+        return new_fd as isize;
+    }
+    sys_dup3(old_fd, new_fd, 0)
+}
+
 pub fn read(fd: usize, buf: &mut [u8]) -> isize {
     sys_read(fd, buf)
 }
@@ -145,35 +310,28 @@ pub fn exec(path: &str, args: &[*const u8]) -> isize {
     sys_exec(path, args)
 }
 
+/// Block until a child exits.
 pub fn wait(exit_code: &mut i32) -> isize {
-    loop {
-        match sys_waitpid(-1, exit_code as *mut _) {
-            -2 => {
-                yield_();
-            }
-            // -1 or a real pid
-            exit_pid => return exit_pid,
-        }
-    }
+    sys_waitpid(-1, exit_code as *mut _)
 }
 
+/// Block until the child exits.
 pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
-    loop {
-        match sys_waitpid(pid as isize, exit_code as *mut _) {
-            -2 => {
-                yield_();
-            }
-            // -1 or a real pid
-            exit_pid => return exit_pid,
-        }
-    }
+    sys_waitpid(pid as isize, exit_code as *mut _)
 }
 
 pub fn set_priority(prio: isize) -> isize {
     sys_set_priority(prio)
 }
 
+/// Only run the current thread on the harts whose bits are set in `harts`.
+pub fn sched_setaffinity(harts: usize) -> isize {
+    let mask = harts.to_le_bytes();
+    sys_sched_setaffinity(0, mask.len(), mask.as_ptr())
+}
+
 pub fn exit(exit_code: i32) -> ! {
+    console::flush();
     sys_exit(exit_code);
 }
 
@@ -184,3 +342,178 @@ pub fn spawn(path: &str) -> isize {
 pub fn getpid() -> isize {
     sys_getpid()
 }
+
+/// Run `entry(arg)` in a new thread of the process. Return the tid.
+/// The thread must call `exit` in the end, since there is nowhere to return to.
+pub fn thread_create(entry: extern "C" fn(usize) -> !, arg: usize) -> isize {
+    sys_thread_create(entry as usize, arg)
+}
+
+pub fn gettid() -> isize {
+    sys_gettid()
+}
+
+/// Block until the thread exits. Return the tid.
+pub fn waittid(tid: usize, exit_code: &mut i32) -> isize {
+    sys_waittid(tid, exit_code as *mut _)
+}
+
+// The synchronization objects are shared by the threads, and the processes forked after
+// they are created. See `sync` for the RAII wrappers.
+
+/// Return the id of a mutex which yields while waiting.
+pub fn mutex_create() -> isize {
+    sys_mutex_create(false)
+}
+
+/// Return the id of a mutex which sleeps while waiting.
+pub fn mutex_blocking_create() -> isize {
+    sys_mutex_create(true)
+}
+
+pub fn mutex_lock(id: usize) -> isize {
+    sys_mutex_lock(id)
+}
+
+pub fn mutex_unlock(id: usize) -> isize {
+    sys_mutex_unlock(id)
+}
+
+pub fn semaphore_create(count: usize) -> isize {
+    sys_semaphore_create(count)
+}
+
+pub fn semaphore_up(id: usize) -> isize {
+    sys_semaphore_up(id)
+}
+
+pub fn semaphore_down(id: usize) -> isize {
+    sys_semaphore_down(id)
+}
+
+pub fn condvar_create() -> isize {
+    sys_condvar_create()
+}
+
+pub fn condvar_signal(id: usize) -> isize {
+    sys_condvar_signal(id)
+}
+
+/// Unlock the mutex and sleep until signaled, then lock the mutex again.
+pub fn condvar_wait(id: usize, mutex_id: usize) -> isize {
+    sys_condvar_wait(id, mutex_id)
+}
+
+/// Once enabled, locking a mutex or downing a semaphore fails with EDEADLK instead of
+/// blocking, if the threads of the process could deadlock.
+pub fn enable_deadlock_detect(enabled: bool) -> isize {
+    sys_enable_deadlock_detect(enabled)
+}
+
+// Wrappers that split the result and the errno.
+
+/// `path` must end with '\0'.
+pub fn try_open(path: &str, flags: OpenFlags) -> errno::Result<usize> {
+    Errno::from_ret(open(path, flags))
+}
+
+pub fn try_close(fd: usize) -> errno::Result<()> {
+    Errno::from_ret(close(fd)).map(drop)
+}
+
+pub fn try_pipe(fds: &mut [usize; 2]) -> errno::Result<()> {
+    Errno::from_ret(pipe(fds)).map(drop)
+}
+
+pub fn try_dup(fd: usize) -> errno::Result<usize> {
+    Errno::from_ret(dup(fd))
+}
+
+pub fn try_dup2(old_fd: usize, new_fd: usize) -> errno::Result<usize> {
+    Errno::from_ret(dup2(old_fd, new_fd))
+}
+
+pub fn try_read(fd: usize, buf: &mut [u8]) -> errno::Result<usize> {
+    Errno::from_ret(read(fd, buf))
+}
+
+pub fn try_write(fd: usize, buf: &[u8]) -> errno::Result<usize> {
+    Errno::from_ret(write(fd, buf))
+}
+
+pub fn try_mmap(start: usize, len: usize, prot: usize) -> errno::Result<()> {
+    Errno::from_ret(mmap(start, len, prot)).map(drop)
+}
+
+pub fn try_munmap(start: usize, len: usize) -> errno::Result<()> {
+    Errno::from_ret(munmap(start, len)).map(drop)
+}
+
+pub fn try_fork() -> errno::Result<usize> {
+    Errno::from_ret(fork())
+}
+
+/// It only returns on failure.
+pub fn try_exec(path: &str, args: &[*const u8]) -> errno::Result<()> {
+    Errno::from_ret(exec(path, args)).map(drop)
+}
+
+pub fn try_wait(exit_code: &mut i32) -> errno::Result<usize> {
+    Errno::from_ret(wait(exit_code))
+}
+
+pub fn try_waitpid(pid: usize, exit_code: &mut i32) -> errno::Result<usize> {
+    Errno::from_ret(waitpid(pid, exit_code))
+}
+
+pub fn try_set_priority(prio: isize) -> errno::Result<usize> {
+    Errno::from_ret(set_priority(prio))
+}
+
+pub fn try_spawn(path: &str) -> errno::Result<usize> {
+    Errno::from_ret(spawn(path))
+}
+
+pub fn try_thread_create(entry: extern "C" fn(usize) -> !, arg: usize) -> errno::Result<usize> {
+    Errno::from_ret(thread_create(entry, arg))
+}
+
+pub fn try_waittid(tid: usize, exit_code: &mut i32) -> errno::Result<usize> {
+    Errno::from_ret(waittid(tid, exit_code))
+}
+
+pub fn try_mutex_lock(id: usize) -> errno::Result<()> {
+    Errno::from_ret(mutex_lock(id)).map(drop)
+}
+
+pub fn try_mutex_unlock(id: usize) -> errno::Result<()> {
+    Errno::from_ret(mutex_unlock(id)).map(drop)
+}
+
+pub fn try_semaphore_down(id: usize) -> errno::Result<()> {
+    Errno::from_ret(semaphore_down(id)).map(drop)
+}
+
+pub fn try_tcgetattr(fd: usize, termios: &mut Termios) -> errno::Result<()> {
+    Errno::from_ret(tcgetattr(fd, termios)).map(drop)
+}
+
+pub fn try_tcsetattr(fd: usize, termios: &Termios) -> errno::Result<()> {
+    Errno::from_ret(tcsetattr(fd, termios)).map(drop)
+}
+
+pub fn try_getrusage(who: isize, usage: &mut RUsage) -> errno::Result<()> {
+    Errno::from_ret(getrusage(who, usage)).map(drop)
+}
+
+pub fn try_kill(pid: usize, sig: usize) -> errno::Result<()> {
+    Errno::from_ret(kill(pid, sig)).map(drop)
+}
+
+pub fn try_sigaction(sig: usize, act: Option<&SignalAction>, old_act: Option<&mut SignalAction>) -> errno::Result<()> {
+    Errno::from_ret(sigaction(sig, act, old_act)).map(drop)
+}
+
+pub fn try_sigprocmask(how: usize, set: Option<&SignalSet>, old_set: Option<&mut SignalSet>) -> errno::Result<()> {
+    Errno::from_ret(sigprocmask(how, set, old_set)).map(drop)
+}
@@ -0,0 +1,135 @@
+use crate::syscall::{sys_kill, sys_sigaction, sys_sigprocmask};
+use bitflags::bitflags;
+use core::arch::global_asm;
+
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGBUS: usize = 7;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGSTKFLT: usize = 16;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGTTIN: usize = 21;
+pub const SIGTTOU: usize = 22;
+pub const SIGURG: usize = 23;
+pub const SIGXCPU: usize = 24;
+pub const SIGXFSZ: usize = 25;
+pub const SIGVTALRM: usize = 26;
+pub const SIGPROF: usize = 27;
+pub const SIGWINCH: usize = 28;
+pub const SIGIO: usize = 29;
+pub const SIGPWR: usize = 30;
+pub const SIGSYS: usize = 31;
+
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+/// Bit `sig - 1` is for the signal `sig`.
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SignalSet(pub u64);
+
+impl SignalSet {
+    pub fn empty() -> Self {
+        Self(0)
+    }
+
+    pub fn single(sig: usize) -> Self {
+        Self(1 << (sig - 1))
+    }
+
+    pub fn contains(self, sig: usize) -> bool {
+        self.0 & (1 << (sig - 1)) != 0
+    }
+
+    pub fn insert(&mut self, sig: usize) {
+        self.0 |= 1 << (sig - 1);
+    }
+}
+
+bitflags! {
+    pub struct SignalActionFlags: usize {
+        const RESTORER = 0x0400_0000;
+        const NODEFER = 0x4000_0000;
+        const RESETHAND = 0x8000_0000;
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SignalAction {
+    pub handler: usize,
+    pub flags: usize,
+    pub restorer: usize,
+    pub mask: SignalSet,
+}
+
+impl SignalAction {
+    /// Call `handler` on the signal, with the signals in `mask` blocked as well.
+    pub fn new(handler: extern "C" fn(i32), mask: SignalSet) -> Self {
+        Self {
+            handler: handler as usize,
+            flags: SignalActionFlags::RESTORER.bits(),
+            restorer: __sigreturn_trampoline as usize,
+            mask,
+        }
+    }
+
+    /// SIG_DFL or SIG_IGN.
+    pub fn with_special(handler: usize) -> Self {
+        Self {
+            handler,
+            ..Self::default()
+        }
+    }
+}
+
+// The handlers return here, with sp pointing to the frame saved by the kernel.
+global_asm!(
+    "    .section .text",
+    "    .p2align 2",
+    "    .global __sigreturn_trampoline",
+    "__sigreturn_trampoline:",
+    // SYSCALL_SIGRETURN
+    "    li a7, 139",
+    "    ecall",
+);
+
+extern "C" {
+    fn __sigreturn_trampoline();
+}
+
+pub fn kill(pid: usize, sig: usize) -> isize {
+    sys_kill(pid as isize, sig)
+}
+
+/// Both of the actions are optional.
+pub fn sigaction(sig: usize, act: Option<&SignalAction>, old_act: Option<&mut SignalAction>) -> isize {
+    let act = act.map_or(core::ptr::null(), |act| act as *const _);
+    let old_act = old_act.map_or(core::ptr::null_mut(), |old_act| old_act as *mut _);
+    sys_sigaction(sig, act, old_act)
+}
+
+/// `how` is one of SIG_BLOCK, SIG_UNBLOCK and SIG_SETMASK.
+pub fn sigprocmask(how: usize, set: Option<&SignalSet>, old_set: Option<&mut SignalSet>) -> isize {
+    let set = set.map_or(core::ptr::null(), |set| set as *const _);
+    let old_set = old_set.map_or(core::ptr::null_mut(), |old_set| old_set as *mut _);
+    sys_sigprocmask(how, set, old_set)
+}
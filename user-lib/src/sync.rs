@@ -0,0 +1,117 @@
+use crate::{
+    condvar_create, condvar_signal, condvar_wait, errno, mutex_blocking_create, mutex_create,
+    mutex_unlock, semaphore_create, semaphore_up, try_mutex_lock, try_semaphore_down, Errno,
+};
+
+// The kernel objects live as long as the process, so dropping the wrappers only forgets them.
+
+fn expect_id(ret: isize) -> usize {
+    Errno::from_ret(ret).expect("failed to create the sync object")
+}
+
+pub struct Mutex {
+    id: usize,
+}
+
+impl Mutex {
+    /// It yields while waiting.
+    pub fn new() -> Self {
+        Self { id: expect_id(mutex_create()) }
+    }
+
+    /// It sleeps while waiting.
+    pub fn new_blocking() -> Self {
+        Self { id: expect_id(mutex_blocking_create()) }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_> {
+        self.checked_lock().expect("failed to lock the mutex")
+    }
+
+    /// Fail with EDEADLK if the deadlock detection is enabled and it could deadlock.
+    pub fn checked_lock(&self) -> errno::Result<MutexGuard<'_>> {
+        try_mutex_lock(self.id)?;
+        Ok(MutexGuard { mutex: self })
+    }
+}
+
+impl Default for Mutex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The mutex is unlocked when it's dropped.
+pub struct MutexGuard<'a> {
+    mutex: &'a Mutex,
+}
+
+impl Drop for MutexGuard<'_> {
+    fn drop(&mut self) {
+        assert_eq!(mutex_unlock(self.mutex.id), 0);
+    }
+}
+
+pub struct Semaphore {
+    id: usize,
+}
+
+impl Semaphore {
+    pub fn new(count: usize) -> Self {
+        Self { id: expect_id(semaphore_create(count)) }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn up(&self) {
+        assert_eq!(semaphore_up(self.id), 0);
+    }
+
+    /// Block until the count is positive, and decrease it.
+    pub fn down(&self) {
+        self.checked_down().expect("failed to down the semaphore");
+    }
+
+    /// Fail with EDEADLK if the deadlock detection is enabled and it could deadlock.
+    pub fn checked_down(&self) -> errno::Result<()> {
+        try_semaphore_down(self.id)
+    }
+}
+
+pub struct Condvar {
+    id: usize,
+}
+
+impl Condvar {
+    pub fn new() -> Self {
+        Self { id: expect_id(condvar_create()) }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Wake up one of the waiters, if any.
+    pub fn signal(&self) {
+        assert_eq!(condvar_signal(self.id), 0);
+    }
+
+    /// Unlock the mutex and sleep until signaled, then lock the mutex again.
+    /// There may be other waiters woken up first, so check the condition again.
+    pub fn wait<'a>(&self, guard: MutexGuard<'a>) -> MutexGuard<'a> {
+        assert_eq!(condvar_wait(self.id, guard.mutex.id), 0);
+        guard
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -2,10 +2,22 @@ use crate::*;
 
 pub const MAX_SYSCALL_NUM: usize = 500;
 
+pub const SYSCALL_DUP: usize = 23;
+pub const SYSCALL_DUP3: usize = 24;
+pub const SYSCALL_IOCTL: usize = 29;
+pub const SYSCALL_OPENAT: usize = 56;
+pub const SYSCALL_CLOSE: usize = 57;
+pub const SYSCALL_PIPE2: usize = 59;
 pub const SYSCALL_READ: usize = 63;
 pub const SYSCALL_WRITE: usize = 64;
 pub const SYSCALL_EXIT: usize = 93;
+pub const SYSCALL_NANOSLEEP: usize = 101;
 pub const SYSCALL_YIELD: usize = 124;
+pub const SYSCALL_KILL: usize = 129;
+pub const SYSCALL_SIGACTION: usize = 134;
+pub const SYSCALL_SIGPROCMASK: usize = 135;
+pub const SYSCALL_SIGRETURN: usize = 139;
+pub const SYSCALL_GETRUSAGE: usize = 165;
 pub const SYSCALL_GET_TIME: usize = 169;
 pub const SYSCALL_GETTIMEOFDAY: usize = SYSCALL_GET_TIME;
 pub const SYSCALL_FORK: usize = 220;
@@ -16,7 +28,22 @@ pub const SYSCALL_SPAWN: usize = 400;
 pub const SYSCALL_MUNMAP: usize = 215;
 pub const SYSCALL_MMAP: usize = 222;
 pub const SYSCALL_TASK_INFO: usize = 410;
+pub const SYSCALL_ENABLE_DEADLOCK_DETECT: usize = 469;
 pub const SYSCALL_SET_PRIORITY: usize = 140;
+pub const SYSCALL_SCHED_SETAFFINITY: usize = 122;
+pub const SYSCALL_THREAD_CREATE: usize = 1000;
+pub const SYSCALL_GETTID: usize = 1001;
+pub const SYSCALL_WAITTID: usize = 1002;
+pub const SYSCALL_MUTEX_CREATE: usize = 1010;
+pub const SYSCALL_MUTEX_LOCK: usize = 1011;
+pub const SYSCALL_MUTEX_UNLOCK: usize = 1012;
+pub const SYSCALL_SEMAPHORE_CREATE: usize = 1020;
+pub const SYSCALL_SEMAPHORE_UP: usize = 1021;
+pub const SYSCALL_SEMAPHORE_DOWN: usize = 1022;
+pub const SYSCALL_CONDVAR_CREATE: usize = 1030;
+pub const SYSCALL_CONDVAR_SIGNAL: usize = 1031;
+pub const SYSCALL_CONDVAR_WAIT: usize = 1032;
+pub const SYSCALL_GET_PROCESS_LIST: usize = 1100;
 
 
 pub fn syscall(id: usize, args: [usize; 3]) -> isize {
@@ -40,6 +67,30 @@ pub fn sys_exit(xstate: i32) -> ! {
     unreachable!("It should have exited")
 }
 
+pub fn sys_openat(dirfd: isize, path: &str, flags: u32) -> isize {
+    syscall(SYSCALL_OPENAT, [dirfd as usize, path.as_ptr() as usize, flags as usize])
+}
+
+pub fn sys_close(fd: usize) -> isize {
+    syscall(SYSCALL_CLOSE, [fd, 0, 0])
+}
+
+pub fn sys_pipe2(fds: &mut [i32; 2], flags: u32) -> isize {
+    syscall(SYSCALL_PIPE2, [fds.as_mut_ptr() as usize, flags as usize, 0])
+}
+
+pub fn sys_dup(fd: usize) -> isize {
+    syscall(SYSCALL_DUP, [fd, 0, 0])
+}
+
+pub fn sys_dup3(old_fd: usize, new_fd: usize, flags: u32) -> isize {
+    syscall(SYSCALL_DUP3, [old_fd, new_fd, flags as usize])
+}
+
+pub fn sys_ioctl(fd: usize, request: usize, arg: usize) -> isize {
+    syscall(SYSCALL_IOCTL, [fd, request, arg])
+}
+
 pub fn sys_read(fd: usize, buffer: &mut [u8]) -> isize {
     syscall(
         SYSCALL_READ,
@@ -63,6 +114,18 @@ pub fn sys_get_time(time: &mut TimeVal, tz: usize) -> isize {
     syscall(SYSCALL_GET_TIME, [time as *mut TimeVal as usize, tz, 0])
 }
 
+pub fn sys_get_process_list(buf: &mut [ProcessInfo]) -> isize {
+    syscall(SYSCALL_GET_PROCESS_LIST, [buf.as_mut_ptr() as usize, buf.len(), 0])
+}
+
+pub fn sys_getrusage(who: isize, usage: &mut RUsage) -> isize {
+    syscall(SYSCALL_GETRUSAGE, [who as usize, usage as *mut RUsage as usize, 0])
+}
+
+pub fn sys_nanosleep(req: &TimeSpec, rem: *mut TimeSpec) -> isize {
+    syscall(SYSCALL_NANOSLEEP, [req as *const TimeSpec as usize, rem as usize, 0])
+}
+
 pub fn sys_mmap(start: usize, len: usize, prot: usize) -> isize {
     syscall(SYSCALL_MMAP, [start, len, prot])
 }
@@ -90,6 +153,10 @@ pub fn sys_set_priority(prio: isize) -> isize {
     syscall(SYSCALL_SET_PRIORITY, [prio as usize, 0, 0])
 }
 
+pub fn sys_sched_setaffinity(pid: usize, mask_size: usize, mask: *const u8) -> isize {
+    syscall(SYSCALL_SCHED_SETAFFINITY, [pid, mask_size, mask as usize])
+}
+
 pub fn sys_spawn(path: &str) -> isize {
     syscall(SYSCALL_SPAWN, [path.as_ptr() as usize, 0, 0])
 }
@@ -97,3 +164,67 @@ pub fn sys_spawn(path: &str) -> isize {
 pub fn sys_getpid() -> isize {
     syscall(SYSCALL_GETPID, [0, 0, 0])
 }
+
+pub fn sys_kill(pid: isize, sig: usize) -> isize {
+    syscall(SYSCALL_KILL, [pid as usize, sig, 0])
+}
+
+pub fn sys_sigaction(sig: usize, act: *const SignalAction, old_act: *mut SignalAction) -> isize {
+    syscall(SYSCALL_SIGACTION, [sig, act as usize, old_act as usize])
+}
+
+pub fn sys_sigprocmask(how: usize, set: *const SignalSet, old_set: *mut SignalSet) -> isize {
+    syscall(SYSCALL_SIGPROCMASK, [how, set as usize, old_set as usize])
+}
+
+pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
+    syscall(SYSCALL_THREAD_CREATE, [entry, arg, 0])
+}
+
+pub fn sys_gettid() -> isize {
+    syscall(SYSCALL_GETTID, [0, 0, 0])
+}
+
+pub fn sys_waittid(tid: usize, exit_code: *mut i32) -> isize {
+    syscall(SYSCALL_WAITTID, [tid, exit_code as usize, 0])
+}
+
+pub fn sys_mutex_create(blocking: bool) -> isize {
+    syscall(SYSCALL_MUTEX_CREATE, [blocking as usize, 0, 0])
+}
+
+pub fn sys_mutex_lock(id: usize) -> isize {
+    syscall(SYSCALL_MUTEX_LOCK, [id, 0, 0])
+}
+
+pub fn sys_mutex_unlock(id: usize) -> isize {
+    syscall(SYSCALL_MUTEX_UNLOCK, [id, 0, 0])
+}
+
+pub fn sys_semaphore_create(count: usize) -> isize {
+    syscall(SYSCALL_SEMAPHORE_CREATE, [count, 0, 0])
+}
+
+pub fn sys_semaphore_up(id: usize) -> isize {
+    syscall(SYSCALL_SEMAPHORE_UP, [id, 0, 0])
+}
+
+pub fn sys_semaphore_down(id: usize) -> isize {
+    syscall(SYSCALL_SEMAPHORE_DOWN, [id, 0, 0])
+}
+
+pub fn sys_condvar_create() -> isize {
+    syscall(SYSCALL_CONDVAR_CREATE, [0, 0, 0])
+}
+
+pub fn sys_condvar_signal(id: usize) -> isize {
+    syscall(SYSCALL_CONDVAR_SIGNAL, [id, 0, 0])
+}
+
+pub fn sys_condvar_wait(id: usize, mutex_id: usize) -> isize {
+    syscall(SYSCALL_CONDVAR_WAIT, [id, mutex_id, 0])
+}
+
+pub fn sys_enable_deadlock_detect(enabled: bool) -> isize {
+    syscall(SYSCALL_ENABLE_DEADLOCK_DETECT, [enabled as usize, 0, 0])
+}